@@ -1,7 +1,7 @@
 use std::fs;
 use std::io;
 
-use clap::{Arg, ArgAction, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 
 const B64TABLE: &[char] = &[
     'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S',
@@ -18,6 +18,10 @@ enum Mode {
 
 struct Config {
     mode: Mode,
+    /// Encoded lines are at most this long, or not broken at all if 0.
+    wrap: usize,
+    /// Skip bytes outside the alphabet when decoding, rather than failing.
+    ignore_garbage: bool,
     input: Box<dyn io::Read>,
     output: Box<dyn io::Write>,
 }
@@ -29,6 +33,8 @@ impl Config {
                 true => Mode::Decode,
                 false => Mode::Encode,
             },
+            wrap: *options.get_one::<usize>("wrap").unwrap(),
+            ignore_garbage: options.get_flag("ignore_garbage"),
             input: match options.get_one::<String>("input") {
                 Some(path) => match fs::OpenOptions::new().read(true).open(path) {
                     Ok(handle) => Box::new(handle) as Box<dyn io::Read>,
@@ -39,7 +45,12 @@ impl Config {
                 None => Box::new(io::stdin()) as Box<dyn io::Read>,
             },
             output: match options.get_one::<String>("output") {
-                Some(path) => match fs::OpenOptions::new().create(true).write(true).open(path) {
+                Some(path) => match fs::OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(path)
+                {
                     Ok(handle) => Box::new(handle) as Box<dyn io::Write>,
                     Err(e) => {
                         panic!("{path}:{e}")
@@ -61,21 +72,46 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .help("Decode incoming Base64 stream into binary data."),
         )
-        .arg(Arg::new("input").short('i').long("input"))
+        .arg(
+            Arg::new("wrap")
+                .short('w')
+                .long("wrap")
+                .value_name("COLS")
+                .value_parser(value_parser!(usize))
+                .default_value("76")
+                .help("Wrap encoded lines after COLS characters. Use 0 to disable line wrapping."),
+        )
+        .arg(
+            Arg::new("ignore_garbage")
+                .short('i')
+                .long("ignore-garbage")
+                .action(ArgAction::SetTrue)
+                .help("When decoding, ignore non-alphabet characters."),
+        )
+        .arg(Arg::new("input").long("input"))
         .arg(Arg::new("output").short('o').long("output"))
         .get_matches();
     let mut config = Config::from(&matches);
     let mut input = Vec::new();
     config.input.read_to_end(&mut input).unwrap();
     let output = match config.mode {
-        Mode::Encode => encode(&input),
-        Mode::Decode => decode(&input),
+        Mode::Encode => encode(&input).map(|encoded| wrap(&encoded, config.wrap)),
+        Mode::Decode => decode(&input, config.ignore_garbage),
     }
     .unwrap();
     config.output.write_all(&output).unwrap();
 }
 
-fn decode(input: &[u8]) -> Result<Vec<u8>, &'static str> {
+/// Decodes `input`, which may be broken into lines. With `ignore_garbage`,
+/// anything else outside the alphabet is skipped too.
+fn decode(input: &[u8], ignore_garbage: bool) -> Result<Vec<u8>, &'static str> {
+    let in_alphabet = |c: &u8| *c == b'=' || B64TABLE.iter().any(|&x| x as u8 == *c);
+    let input: Vec<u8> = input
+        .iter()
+        .filter(|c| !matches!(c, b'\n' | b'\r'))
+        .filter(|c| !ignore_garbage || in_alphabet(c))
+        .copied()
+        .collect();
     let chunks = input[..].chunks(4);
     let mut decoded = Vec::new();
     chunks.to_owned().try_for_each(|chunk| {
@@ -125,6 +161,24 @@ fn encode(input: &[u8]) -> Result<Vec<u8>, &'static str> {
     Ok(encoded)
 }
 
+/// Breaks `encoded` into lines of `width` characters, or leaves it on one
+/// line if `width` is 0, ending the last line with a newline too.
+fn wrap(encoded: &[u8], width: usize) -> Vec<u8> {
+    if encoded.is_empty() {
+        return Vec::new();
+    }
+    let width = match width {
+        0 => encoded.len(),
+        _ => width,
+    };
+    let mut wrapped = Vec::with_capacity(encoded.len() + encoded.len() / width + 1);
+    for line in encoded.chunks(width) {
+        wrapped.extend_from_slice(line);
+        wrapped.push(b'\n');
+    }
+    wrapped
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -137,11 +191,66 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_wrap() -> Result<(), &'static str> {
+        let input: Vec<u8> = (0..100).collect();
+        let encoded = encode(&input)?;
+        assert_eq!(encoded.len(), 136);
+
+        let wrapped = wrap(&encoded, 76);
+        let lines: Vec<&[u8]> = wrapped.split(|&b| b == b'\n').collect();
+        assert_eq!(
+            lines.iter().map(|l| l.len()).collect::<Vec<_>>(),
+            [76, 60, 0]
+        );
+        assert_eq!(lines.concat(), encoded);
+        assert_eq!(
+            wrap(&encoded, 68).iter().filter(|&&b| b == b'\n').count(),
+            2
+        );
+
+        // 0 leaves it all on one line.
+        assert_eq!(wrap(&encoded, 0), [encoded.as_slice(), b"\n"].concat());
+        assert_eq!(wrap(b"", 76), b"");
+        Ok(())
+    }
+
     #[test]
     fn test_decode() -> Result<(), &'static str> {
         let expected = "HELLO".as_bytes().to_vec();
         let input = "SEVMTE8=".as_bytes().to_vec();
-        assert_eq!(expected, decode(&input)?);
+        assert_eq!(expected, decode(&input, false)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_wrapped() -> Result<(), &'static str> {
+        // Any bytes will do, so long as they are all there.
+        let mut state: u32 = 1;
+        let input: Vec<u8> = (0..1024)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        let wrapped = wrap(&encode(&input)?, 76);
+        assert_eq!(decode(&wrapped, false)?, input);
+        let crlf: Vec<u8> = wrapped
+            .iter()
+            .flat_map(|&b| match b {
+                b'\n' => b"\r\n".to_vec(),
+                _ => vec![b],
+            })
+            .collect();
+        assert_eq!(decode(&crlf, false)?, input);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_garbage() -> Result<(), &'static str> {
+        let input = b"SE*VM TE8=\n!";
+        assert_eq!(decode(input, false), Err("invalid input"));
+        assert_eq!(decode(input, true)?, b"HELLO");
         Ok(())
     }
 }
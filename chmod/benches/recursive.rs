@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// A wide tree of empty files, where chmod -R spends its time on system
+/// calls rather than on modes.
+fn build(root: &Path) {
+    for i in 0..100 {
+        let sub = root.join(format!("sub{i}"));
+        fs::create_dir_all(&sub).unwrap();
+        for j in 0..100 {
+            fs::write(sub.join(format!("file{j}")), "").unwrap();
+        }
+    }
+}
+
+fn bench_recursive(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("tree");
+    build(&root);
+    let mut group = c.benchmark_group("recursive");
+    group.sample_size(10);
+    for jobs in [1, 4] {
+        group.bench_with_input(BenchmarkId::new("jobs", jobs), &jobs, |b, jobs| {
+            let mut modes = ["u+x", "u-x"].iter().cycle();
+            b.iter(|| {
+                let status = Command::new(env!("CARGO_BIN_EXE_qchmod"))
+                    .args(["-R", "--jobs", &jobs.to_string(), modes.next().unwrap()])
+                    .arg(&root)
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_recursive);
+criterion_main!(benches);
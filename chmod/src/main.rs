@@ -0,0 +1,1383 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use qoreutils_common::error::strerror;
+use qoreutils_common::files0::Files0;
+
+#[cfg(unix)]
+use std::{
+    ffi::{CStr, CString, OsStr},
+    mem::MaybeUninit,
+    os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::mpsc::{self, Sender},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+#[cfg(target_os = "linux")]
+use libc::__errno_location as errno;
+#[cfg(all(unix, not(target_os = "linux")))]
+use libc::__error as errno;
+
+const USER: u32 = 0o700;
+const GROUP: u32 = 0o070;
+const OTHER: u32 = 0o007;
+
+const READ: u32 = 0o444;
+const WRITE: u32 = 0o222;
+const EXECUTE: u32 = 0o111;
+
+const SETUID: u32 = 0o4000;
+const SETGID: u32 = 0o2000;
+const STICKY: u32 = 0o1000;
+const MODE_BITS: u32 = 0o7777;
+
+// Once the path from the nearest open directory gets this long, the walk
+// opens the directory it is in and carries on from there. Trees of any
+// depth then take a handful of descriptors, and no path handed to the
+// system comes near PATH_MAX.
+#[cfg(unix)]
+const ANCHOR_AFTER: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Remove,
+    Set,
+}
+
+/// One `[ugoa]*[-+=]([rwxXst]*|[ugo])` part of a symbolic mode. Each class
+/// owns one of the special bits: set-user-ID goes with `u`, set-group-ID
+/// with `g` and the sticky bit with `o`.
+#[derive(Debug, PartialEq)]
+struct Clause {
+    who: u32,
+    op: Op,
+    perms: u32,
+    /// Whether `X` was given: execute, but only for directories and files
+    /// some class can already execute.
+    conditional_execute: bool,
+    /// For `g=u` and the like, how far the bits of the class to copy from
+    /// are shifted.
+    copy_from: Option<u32>,
+    /// Whether no class was given, in which case the umask bits are left
+    /// alone: `+w` is `a+w` minus whatever the umask takes away.
+    masked: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Octal(u32),
+    Symbolic(Vec<Clause>),
+}
+
+impl Mode {
+    /// The new permission bits for a file with mode `current`. Symbolic
+    /// modes depend on the file, so they are worked out per file.
+    ///
+    /// Like GNU chmod, directories keep their set-user-ID and set-group-ID
+    /// bits unless the mode says otherwise, since those bits decide who owns
+    /// the files created inside.
+    fn apply(&self, current: u32, is_dir: bool, umask: u32) -> u32 {
+        let keep = |mode: u32| match is_dir {
+            true => (SETUID | SETGID) & !mode,
+            false => 0,
+        };
+        let clauses = match self {
+            Mode::Octal(mode) => return mode | current & keep(*mode),
+            Mode::Symbolic(clauses) => clauses,
+        };
+        let mut mode = current & MODE_BITS;
+        for clause in clauses {
+            let mut perms = clause.perms;
+            if let Some(shift) = clause.copy_from {
+                // The class's rwx, repeated for every class.
+                perms = (mode >> shift & 0o7) * 0o111;
+            }
+            if clause.conditional_execute && (is_dir || mode & EXECUTE != 0) {
+                perms |= EXECUTE;
+            }
+            if clause.masked {
+                perms &= !umask;
+            }
+            let perms = perms & clause.who;
+            mode = match clause.op {
+                Op::Add => mode | perms,
+                Op::Remove => mode & !perms,
+                // With nothing after the `=` this clears the class.
+                Op::Set => mode & (!clause.who | keep(perms)) | perms,
+            };
+        }
+        mode
+    }
+}
+
+fn parse_clause(s: &str) -> Option<Clause> {
+    let mut chars = s.chars().peekable();
+    let mut who = 0;
+    while let Some(c) = chars.peek() {
+        who |= match c {
+            'u' => USER | SETUID,
+            'g' => GROUP | SETGID,
+            'o' => OTHER | STICKY,
+            'a' => MODE_BITS,
+            _ => break,
+        };
+        chars.next();
+    }
+    let op = match chars.next() {
+        Some('+') => Op::Add,
+        Some('-') => Op::Remove,
+        Some('=') => Op::Set,
+        _ => return None,
+    };
+    let mut perms = 0;
+    let mut conditional_execute = false;
+    // A class to copy from stands alone, like GNU chmod wants it.
+    let rest: String = chars.collect();
+    let copy_from = match rest.as_str() {
+        "u" => Some(6),
+        "g" => Some(3),
+        "o" => Some(0),
+        _ => None,
+    };
+    if copy_from.is_none() {
+        for c in rest.chars() {
+            match c {
+                'r' => perms |= READ,
+                'w' => perms |= WRITE,
+                'x' => perms |= EXECUTE,
+                'X' => conditional_execute = true,
+                's' => perms |= SETUID | SETGID,
+                't' => perms |= STICKY,
+                _ => return None,
+            }
+        }
+    }
+    Some(Clause {
+        who: if who == 0 { MODE_BITS } else { who },
+        op,
+        perms,
+        conditional_execute,
+        copy_from,
+        masked: who == 0,
+    })
+}
+
+fn parse_mode(s: &str) -> Result<Mode, String> {
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        // Four digits are all the bits there are.
+        return match u32::from_str_radix(s, 8) {
+            Ok(mode) if s.len() <= 4 => Ok(Mode::Octal(mode)),
+            _ => Err(format!("invalid mode: '{s}'")),
+        };
+    }
+    s.split(',')
+        .map(parse_clause)
+        .collect::<Option<Vec<_>>>()
+        .map(Mode::Symbolic)
+        .ok_or_else(|| format!("invalid mode: '{s}'"))
+}
+
+/// Which symlinks -R follows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Traversal {
+    /// -H: only those given on the command line.
+    CommandLine,
+    /// -L: all of them.
+    All,
+    /// -P: none.
+    None,
+}
+
+#[derive(Debug)]
+struct Config {
+    mode: Mode,
+    /// With --dirs, the mode for directories, leaving `mode` to the rest.
+    dir_mode: Option<Mode>,
+    /// With --from, only files with exactly this mode are changed.
+    from: Option<Mode>,
+    recursive: bool,
+    traversal: Traversal,
+    preserve_root: bool,
+    /// Whether operands that are symlinks stand for what they point to,
+    /// rather than for themselves as with -h.
+    dereference: bool,
+    umask: u32,
+    silent: bool,
+    verbose: bool,
+    dry_run: bool,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    jobs: usize,
+    files0_from: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+}
+
+/// The process umask. Reading it means setting it, so it is put right back.
+#[cfg(unix)]
+fn umask() -> u32 {
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask as u32 & 0o777
+    }
+}
+
+#[cfg(not(unix))]
+fn umask() -> u32 {
+    0
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Result<Self, String> {
+        Ok(Self {
+            mode: parse_mode(options.get_one::<String>("mode").unwrap())?,
+            dir_mode: options
+                .get_one::<String>("dirs")
+                .map(|s| parse_mode(s))
+                .transpose()?,
+            from: options
+                .get_one::<String>("from")
+                .map(|s| parse_mode(s))
+                .transpose()?,
+            recursive: options.get_flag("recursive"),
+            traversal: if options.get_flag("dereference_all") {
+                Traversal::All
+            } else if options.get_flag("no_dereference") {
+                Traversal::None
+            } else {
+                Traversal::CommandLine
+            },
+            preserve_root: !options.get_flag("no_preserve_root"),
+            dereference: !options.get_flag("no_dereference_operands"),
+            umask: umask(),
+            silent: options.get_flag("silent"),
+            verbose: options.get_flag("verbose"),
+            dry_run: options.get_flag("dry_run"),
+            jobs: *options.get_one::<u32>("jobs").unwrap() as usize,
+            files0_from: options.get_one::<PathBuf>("files0_from").cloned(),
+            paths: options
+                .get_many::<PathBuf>("paths")
+                .map(|v| v.cloned().collect())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// The new permission bits for a file with mode `current`, or None if
+    /// --from leaves it alone.
+    fn new_mode(&self, current: u32, is_dir: bool) -> Option<u32> {
+        if let Some(from) = &self.from {
+            // A symbolic --from describes the mode it would make from
+            // nothing.
+            if from.apply(0, is_dir, 0) != current {
+                return None;
+            }
+        }
+        let mode = match (&self.dir_mode, is_dir) {
+            (Some(dir_mode), true) => dir_mode,
+            _ => &self.mode,
+        };
+        Some(mode.apply(current, is_dir, self.umask))
+    }
+}
+
+/// The little of a stat the walk looks at.
+#[cfg(unix)]
+struct Stat {
+    mode: u32,
+    is_dir: bool,
+    is_symlink: bool,
+    dev: u64,
+    ino: u64,
+}
+
+/// A file to change: either an operand, or a name in a directory the walk
+/// has already been through.
+#[cfg(unix)]
+struct Entry {
+    name: PathBuf,
+    parent: Option<Arc<Dir>>,
+}
+
+/// A directory whose contents are being changed.
+#[cfg(unix)]
+struct Dir {
+    entry: Entry,
+    /// (dev, ino), to notice when symlinks lead back to an ancestor.
+    key: (u64, u64),
+    /// The directory itself, when its contents are opened from here.
+    anchor: Option<OwnedFd>,
+    /// Entries not done yet, plus one while they are being listed.
+    remaining: AtomicUsize,
+    /// The current and new mode, when the change has to wait until the
+    /// contents are done.
+    deferred: Option<(u32, u32)>,
+}
+
+// Chains of directories are as long as the tree is deep, so they are taken
+// apart one link at a time rather than by recursing.
+#[cfg(unix)]
+impl Drop for Dir {
+    fn drop(&mut self) {
+        let mut parent = self.entry.parent.take();
+        while let Some(dir) = parent {
+            parent = match Arc::try_unwrap(dir) {
+                Ok(mut dir) => dir.entry.parent.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Entry {
+    fn operand(path: &Path) -> Self {
+        Self {
+            name: path.to_path_buf(),
+            parent: None,
+        }
+    }
+
+    /// The whole path, for messages.
+    fn path(&self) -> PathBuf {
+        let mut names = vec![&self.name];
+        let mut dir = self.parent.as_deref();
+        while let Some(d) = dir {
+            names.push(&d.entry.name);
+            dir = d.entry.parent.as_deref();
+        }
+        names.iter().rev().collect()
+    }
+
+    /// The directory descriptor to open this from, and the path from there.
+    fn location(&self) -> io::Result<(RawFd, CString)> {
+        let mut names = vec![&self.name];
+        let mut dir = self.parent.as_deref();
+        let mut fd = libc::AT_FDCWD;
+        while let Some(d) = dir {
+            if let Some(anchor) = &d.anchor {
+                fd = anchor.as_raw_fd();
+                break;
+            }
+            names.push(&d.entry.name);
+            dir = d.entry.parent.as_deref();
+        }
+        let path: PathBuf = names.iter().rev().collect();
+        let path = CString::new(path.into_os_string().into_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok((fd, path))
+    }
+
+    // The field types vary between platforms.
+    #[allow(clippy::unnecessary_cast)]
+    fn stat(&self, follow: bool) -> io::Result<Stat> {
+        let (fd, path) = self.location()?;
+        let flags = if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+        let mut st = MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::fstatat(fd, path.as_ptr(), st.as_mut_ptr(), flags) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let st = unsafe { st.assume_init() };
+        Ok(Stat {
+            mode: st.st_mode as u32,
+            is_dir: st.st_mode & libc::S_IFMT == libc::S_IFDIR,
+            is_symlink: st.st_mode & libc::S_IFMT == libc::S_IFLNK,
+            dev: st.st_dev as u64,
+            ino: st.st_ino as u64,
+        })
+    }
+
+    /// Sets the mode, of a symlink itself unless `follow`. Not every system
+    /// can do the latter; Linux says so with EOPNOTSUPP.
+    fn set_mode(&self, mode: u32, follow: bool) -> io::Result<()> {
+        let (fd, path) = self.location()?;
+        let flags = if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+        match unsafe { libc::fchmodat(fd, path.as_ptr(), mode as libc::mode_t, flags) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Opens the directory and lists what is in it.
+    fn read_dir(&self, follow: bool) -> io::Result<(OwnedFd, Vec<PathBuf>)> {
+        let (fd, path) = self.location()?;
+        let mut flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        if !follow {
+            flags |= libc::O_NOFOLLOW;
+        }
+        let dir_fd = match unsafe { libc::openat(fd, path.as_ptr(), flags) } {
+            fd if fd < 0 => return Err(io::Error::last_os_error()),
+            fd => unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+        // closedir closes the descriptor it was opened on, so it gets a copy.
+        let copy = dir_fd.try_clone()?.into_raw_fd();
+        let stream = unsafe { libc::fdopendir(copy) };
+        if stream.is_null() {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(copy) };
+            return Err(e);
+        }
+        let mut names = Vec::new();
+        let result = loop {
+            // The end of the directory and an error look the same but for
+            // errno.
+            unsafe { *errno() = 0 };
+            let dirent = unsafe { libc::readdir(stream) };
+            if dirent.is_null() {
+                match io::Error::last_os_error() {
+                    e if e.raw_os_error() == Some(0) => break Ok(()),
+                    e => break Err(e),
+                }
+            }
+            let name = unsafe { CStr::from_ptr((*dirent).d_name.as_ptr()) };
+            if name != c"." && name != c".." {
+                names.push(PathBuf::from(OsStr::from_bytes(name.to_bytes())));
+            }
+        };
+        unsafe { libc::closedir(stream) };
+        result.map(|()| (dir_fd, names))
+    }
+
+    /// Whether `key` is this entry's directory or one above it.
+    fn within(&self, key: (u64, u64)) -> bool {
+        let mut dir = self.parent.as_deref();
+        while let Some(d) = dir {
+            if d.key == key {
+                return true;
+            }
+            dir = d.entry.parent.as_deref();
+        }
+        false
+    }
+}
+
+/// Why `entry` could not be looked at. A symlink that leads nowhere is
+/// called that, rather than a file that does not exist.
+#[cfg(unix)]
+fn cannot_access(entry: &Entry, e: io::Error, follow: bool) -> String {
+    if follow
+        && e.kind() == io::ErrorKind::NotFound
+        && entry.stat(false).is_ok_and(|stat| stat.is_symlink)
+    {
+        return format!(
+            "cannot operate on dangling symlink '{}'",
+            entry.path().display()
+        );
+    }
+    format!(
+        "cannot access '{}': {}",
+        entry.path().display(),
+        strerror(&e)
+    )
+}
+
+/// Something that went wrong: with one file, which is reported and passed
+/// over, or with writing out what -v says, which ends the run.
+#[derive(Debug)]
+enum Failure {
+    File(String),
+    Output(io::Error),
+}
+
+impl From<String> for Failure {
+    fn from(e: String) -> Self {
+        Failure::File(e)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::File(e) => f.write_str(e),
+            Failure::Output(e) => write!(f, "write error: {}", strerror(e)),
+        }
+    }
+}
+
+/// What -v says about a file going from `current` to `mode`.
+fn describe(path: &Path, current: u32, mode: Option<u32>) -> String {
+    match mode {
+        Some(mode) if mode != current => format!(
+            "mode of '{}' changed from {current:04o} to {mode:04o}",
+            path.display()
+        ),
+        _ => format!("mode of '{}' retained as {current:04o}", path.display()),
+    }
+}
+
+/// Changes `entry` from `current` to `mode`, if there is one.
+#[cfg(unix)]
+fn change(
+    entry: &Entry,
+    current: u32,
+    mode: Option<u32>,
+    follow: bool,
+    config: &Config,
+) -> Result<(), Failure> {
+    if let (Some(mode), false) = (mode, config.dry_run) {
+        entry.set_mode(mode, follow).map_err(|e| {
+            format!(
+                "changing permissions of '{}': {}",
+                entry.path().display(),
+                strerror(&e)
+            )
+        })?;
+    }
+    if config.verbose {
+        let line = describe(&entry.path(), current, mode);
+        writeln!(io::stdout().lock(), "{line}").map_err(Failure::Output)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn chmod_file(path: &Path, config: &Config) -> Result<(), Failure> {
+    let entry = Entry::operand(path);
+    let follow = config.dereference;
+    let stat = entry
+        .stat(follow)
+        .map_err(|e| cannot_access(&entry, e, follow))?;
+    let current = stat.mode & MODE_BITS;
+    let mode = config.new_mode(current, stat.is_dir);
+    // Only with -h is the symlink itself still there to change.
+    change(&entry, current, mode, !stat.is_symlink, config)
+}
+
+/// Whether `path` is the root directory under another name, such as `//`
+/// or a symlink to `/`.
+fn is_root(path: &Path) -> bool {
+    fs::canonicalize(path).is_ok_and(|p| p == Path::new("/"))
+}
+
+/// Entries waiting to be visited, and how many workers are busy with one
+/// and so may yet add more.
+#[cfg(unix)]
+struct Pending {
+    entries: Vec<Entry>,
+    busy: usize,
+}
+
+/// Changes `path` and everything under it with `config.jobs` threads,
+/// passing each failure to `report` rather than stopping at it.
+#[cfg(unix)]
+fn chmod_recursive(path: &Path, config: &Config, report: &mut impl FnMut(Failure)) {
+    let pending = Mutex::new(Pending {
+        entries: vec![Entry::operand(path)],
+        busy: 0,
+    });
+    let changed = Condvar::new();
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..config.jobs {
+            let tx = tx.clone();
+            let (pending, changed) = (&pending, &changed);
+            s.spawn(move || work(pending, changed, config, &tx));
+        }
+        drop(tx);
+        for e in rx {
+            report(e);
+        }
+    });
+}
+
+#[cfg(unix)]
+fn work(pending: &Mutex<Pending>, changed: &Condvar, config: &Config, tx: &Sender<Failure>) {
+    let mut state = pending.lock().unwrap();
+    loop {
+        if let Some(entry) = state.entries.pop() {
+            state.busy += 1;
+            drop(state);
+            let children = visit(entry, config, tx);
+            state = pending.lock().unwrap();
+            // Reversed, so that one worker goes through a directory in order.
+            state.entries.extend(children.into_iter().rev());
+            state.busy -= 1;
+            changed.notify_all();
+        } else if state.busy == 0 {
+            return;
+        } else {
+            state = changed.wait(state).unwrap();
+        }
+    }
+}
+
+/// Counts one entry of `parent` as done. The last one to finish in a
+/// directory finishes the directory too, making any change that was held
+/// back until then.
+#[cfg(unix)]
+fn done(mut parent: Option<Arc<Dir>>, config: &Config, tx: &Sender<Failure>) {
+    while let Some(dir) = parent {
+        if dir.remaining.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        if let Some((current, mode)) = dir.deferred {
+            if let Err(e) = change(&dir.entry, current, Some(mode), true, config) {
+                let _ = tx.send(e);
+            }
+        }
+        parent = dir.entry.parent.clone();
+    }
+}
+
+/// Changes one entry, and returns what is in it if it is a directory.
+///
+/// Symlinks are followed only as `config.traversal` allows, since chmod
+/// cannot change a symlink itself. A directory whose new mode takes away
+/// its owner's read or search permission is changed only after its
+/// contents, like GNU chmod does, so that the walk does not lock itself
+/// out.
+#[cfg(unix)]
+fn visit(entry: Entry, config: &Config, tx: &Sender<Failure>) -> Vec<Entry> {
+    let follow = match config.traversal {
+        Traversal::CommandLine => entry.parent.is_none(),
+        Traversal::All => true,
+        Traversal::None => false,
+    };
+    let report = |e: Failure| {
+        let _ = tx.send(e);
+    };
+    let stat = match entry.stat(follow) {
+        Ok(stat) if !stat.is_symlink => stat,
+        Ok(_) => return finish(entry, config, tx),
+        Err(e) => {
+            report(cannot_access(&entry, e, follow).into());
+            return finish(entry, config, tx);
+        }
+    };
+    let key = (stat.dev, stat.ino);
+    // Without -L nothing can lead back up the tree.
+    if config.traversal == Traversal::All && stat.is_dir && entry.within(key) {
+        eprintln!(
+            "chmod: WARNING: circular directory structure at '{}'",
+            entry.path().display()
+        );
+        return finish(entry, config, tx);
+    }
+
+    let current = stat.mode & MODE_BITS;
+    let mode = config.new_mode(current, stat.is_dir);
+    let deferred =
+        stat.is_dir && mode.is_some_and(|mode| current & !mode & USER & (READ | EXECUTE) != 0);
+    if !deferred {
+        if let Err(e) = change(&entry, current, mode, true, config) {
+            report(e);
+        }
+    }
+    if !stat.is_dir {
+        return finish(entry, config, tx);
+    }
+    let (fd, names) = match entry.read_dir(follow) {
+        Ok(listing) => listing,
+        Err(e) => {
+            report(
+                format!(
+                    "cannot read directory '{}': {}",
+                    entry.path().display(),
+                    strerror(&e)
+                )
+                .into(),
+            );
+            if deferred {
+                if let Err(e) = change(&entry, current, mode, true, config) {
+                    report(e);
+                }
+            }
+            return finish(entry, config, tx);
+        }
+    };
+    let long = entry
+        .location()
+        .is_ok_and(|(_, path)| path.as_bytes().len() > ANCHOR_AFTER);
+    let dir = Arc::new(Dir {
+        entry,
+        key,
+        anchor: long.then_some(fd),
+        remaining: AtomicUsize::new(names.len() + 1),
+        deferred: mode.filter(|_| deferred).map(|mode| (current, mode)),
+    });
+    let children = names
+        .into_iter()
+        .map(|name| Entry {
+            name,
+            parent: Some(dir.clone()),
+        })
+        .collect();
+    done(Some(dir), config, tx);
+    children
+}
+
+/// Counts `entry` as done.
+#[cfg(unix)]
+fn finish(entry: Entry, config: &Config, tx: &Sender<Failure>) -> Vec<Entry> {
+    done(entry.parent, config, tx);
+    Vec::new()
+}
+
+/// Elsewhere there are no mode bits to speak of, only whether a file is
+/// read-only. Any write permission left in the new mode makes it writable.
+#[cfg(not(unix))]
+fn chmod_file(path: &Path, config: &Config) -> Result<(), Failure> {
+    let link = fs::symlink_metadata(path).is_ok_and(|meta| meta.is_symlink());
+    if link && !config.dereference {
+        return Err(format!(
+            "cannot change the mode of symbolic link '{}' on this platform, skipping",
+            path.display()
+        ));
+    }
+    let meta = fs::metadata(path).map_err(|e| match link {
+        true => format!("cannot operate on dangling symlink '{}'", path.display()),
+        false => format!("cannot access '{}': {}", path.display(), strerror(&e)),
+    })?;
+    let mut permissions = meta.permissions();
+    let current = match (permissions.readonly(), meta.is_dir()) {
+        (true, false) => 0o444,
+        (false, false) => 0o666,
+        (true, true) => 0o555,
+        (false, true) => 0o777,
+    };
+    let mode = config.new_mode(current, meta.is_dir());
+    if let (Some(mode), false) = (mode, config.dry_run) {
+        permissions.set_readonly(mode & WRITE == 0);
+        fs::set_permissions(path, permissions).map_err(|e| {
+            format!(
+                "changing permissions of '{}': {}",
+                path.display(),
+                strerror(&e)
+            )
+        })?;
+    }
+    if config.verbose {
+        let line = describe(path, current, mode);
+        writeln!(io::stdout().lock(), "{line}").map_err(Failure::Output)?;
+    }
+    Ok(())
+}
+
+/// Changes `path` and everything under it, one file at a time.
+#[cfg(not(unix))]
+fn chmod_recursive(path: &Path, config: &Config, report: &mut impl FnMut(Failure)) {
+    let mut pending = vec![(path.to_path_buf(), true)];
+    while let Some((path, top)) = pending.pop() {
+        let follow = match config.traversal {
+            Traversal::CommandLine => top,
+            Traversal::All => true,
+            Traversal::None => false,
+        };
+        let meta = match follow {
+            true => fs::metadata(&path),
+            false => fs::symlink_metadata(&path),
+        };
+        let meta = match meta {
+            Ok(meta) => meta,
+            Err(e) => {
+                report(format!("cannot access '{}': {}", path.display(), strerror(&e)).into());
+                continue;
+            }
+        };
+        if meta.is_symlink() {
+            continue;
+        }
+        if let Err(e) = chmod_file(&path, config) {
+            report(e);
+        }
+        if !meta.is_dir() {
+            continue;
+        }
+        let unreadable = |e: io::Error| {
+            format!(
+                "cannot read directory '{}': {}",
+                path.display(),
+                strerror(&e)
+            )
+        };
+        match fs::read_dir(&path) {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry {
+                        Ok(entry) => pending.push((entry.path(), false)),
+                        Err(e) => report(unreadable(e).into()),
+                    }
+                }
+            }
+            Err(e) => report(unreadable(e).into()),
+        }
+    }
+}
+
+fn main() {
+    let matches = Command::new("chmod")
+        // -h is --no-dereference, as in GNU chmod.
+        .disable_help_flag(true)
+        .arg(
+            Arg::new("recursive")
+                .short('R')
+                .long("recursive")
+                .action(ArgAction::SetTrue)
+                .help("Change files and directories recursively."),
+        )
+        .arg(
+            Arg::new("dereference_args")
+                .short('H')
+                .action(ArgAction::SetTrue)
+                .overrides_with_all(["dereference_all", "no_dereference"])
+                .help("With -R, follow symbolic links given on the command line (the default)."),
+        )
+        .arg(
+            Arg::new("dereference_all")
+                .short('L')
+                .action(ArgAction::SetTrue)
+                .overrides_with_all(["dereference_args", "no_dereference"])
+                .help("With -R, follow every symbolic link."),
+        )
+        .arg(
+            Arg::new("no_dereference")
+                .short('P')
+                .action(ArgAction::SetTrue)
+                .overrides_with_all(["dereference_args", "dereference_all"])
+                .help("With -R, follow no symbolic links, not even operands."),
+        )
+        .arg(
+            Arg::new("preserve_root")
+                .long("preserve-root")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no_preserve_root")
+                .help("Fail to operate recursively on '/' (the default)."),
+        )
+        .arg(
+            Arg::new("no_preserve_root")
+                .long("no-preserve-root")
+                .action(ArgAction::SetTrue)
+                .overrides_with("preserve_root")
+                .help("Do not treat '/' specially."),
+        )
+        .arg(
+            Arg::new("dereference")
+                .long("dereference")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no_dereference_operands")
+                .help("Change the file a symlink operand points to (the default)."),
+        )
+        .arg(
+            Arg::new("no_dereference_operands")
+                .short('h')
+                .long("no-dereference")
+                .action(ArgAction::SetTrue)
+                .overrides_with("dereference")
+                .help("Change symlink operands themselves, where the system allows."),
+        )
+        .arg(
+            Arg::new("silent")
+                .short('f')
+                .long("silent")
+                .visible_alias("quiet")
+                .action(ArgAction::SetTrue)
+                .help("Suppress most error messages."),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("Output a diagnostic for every file processed."),
+        )
+        .arg(
+            Arg::new("dirs")
+                .long("dirs")
+                .value_name("MODE")
+                .help("Give directories MODE instead, leaving the other mode to everything else."),
+        )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .value_name("MODE")
+                .help("Change only files whose mode is exactly MODE."),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .long("dry-run")
+                .action(ArgAction::SetTrue)
+                .help("Change nothing, only work out what would change."),
+        )
+        .arg(
+            Arg::new("jobs")
+                .long("jobs")
+                .value_name("N")
+                .default_value("1")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("With -R, change files with N threads."),
+        )
+        .arg(
+            Arg::new("help")
+                .long("help")
+                .action(ArgAction::Help)
+                .help("Print help."),
+        )
+        // Modes like `-w` look like options. Real options still win, and
+        // none of their letters mean anything in a mode.
+        .arg(
+            Arg::new("mode")
+                .required(true)
+                .value_name("MODE")
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::new("files0_from")
+                .long("files0-from")
+                .value_name("F")
+                .conflicts_with("paths")
+                .value_parser(value_parser!(PathBuf))
+                .help("Change the files named by NUL-terminated names in file F; if F is - then read names from standard input."),
+        )
+        .arg(
+            Arg::new("paths")
+                .required_unless_present("files0_from")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .get_matches();
+    let config = match Config::from(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("chmod: {e}");
+            process::exit(1);
+        }
+    };
+
+    #[cfg(not(unix))]
+    if !config.silent {
+        eprintln!("chmod: warning: only write permission can be changed on this platform");
+    }
+
+    // With -f failures still decide the exit status, they just go unsaid.
+    let mut failed = false;
+    let mut report = |e: Failure| match e {
+        // Whoever was reading -v has gone away, as in `chmod -Rv ... | head`.
+        Failure::Output(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(failed as i32),
+        Failure::Output(_) => {
+            eprintln!("chmod: {e}");
+            process::exit(1);
+        }
+        Failure::File(_) => {
+            if !config.silent {
+                eprintln!("chmod: {e}");
+            }
+            failed = true;
+        }
+    };
+    let paths: Box<dyn Iterator<Item = io::Result<PathBuf>>> = match &config.files0_from {
+        Some(list) if list.as_os_str() == "-" => Box::new(Files0::new(io::stdin().lock())),
+        Some(list) => match fs::File::open(list) {
+            Ok(file) => Box::new(Files0::new(io::BufReader::new(file))),
+            Err(e) => {
+                eprintln!(
+                    "chmod: cannot open '{}' for reading: {}",
+                    list.display(),
+                    strerror(&e)
+                );
+                process::exit(1);
+            }
+        },
+        None => Box::new(config.paths.iter().cloned().map(Ok)),
+    };
+    // Only a list can fail to be read, or hold an empty name.
+    let list = config.files0_from.as_deref().unwrap_or(Path::new("-"));
+    let mut refused_root = false;
+    for (i, path) in paths.enumerate() {
+        let path = match path {
+            Ok(path) if path.as_os_str().is_empty() && config.files0_from.is_some() => {
+                report(
+                    format!(
+                        "{}:{}: invalid zero-length file name",
+                        list.display(),
+                        i + 1
+                    )
+                    .into(),
+                );
+                continue;
+            }
+            Ok(path) => path,
+            Err(e) => {
+                report(format!("{}: read error: {}", list.display(), strerror(&e)).into());
+                break;
+            }
+        };
+        if config.recursive && config.preserve_root && is_root(&path) {
+            eprintln!("chmod: it is dangerous to operate recursively on '/'");
+            eprintln!("chmod: use --no-preserve-root to override this failsafe");
+            refused_root = true;
+            continue;
+        }
+        match config.recursive {
+            true => chmod_recursive(&path, &config, &mut report),
+            false => {
+                if let Err(e) = chmod_file(&path, &config) {
+                    report(e);
+                }
+            }
+        }
+    }
+    if failed || refused_root {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    fn apply(mode: &str, current: u32, is_dir: bool) -> u32 {
+        parse_mode(mode).unwrap().apply(current, is_dir, 0)
+    }
+
+    fn config(mode: &str) -> Config {
+        Config {
+            mode: parse_mode(mode).unwrap(),
+            dir_mode: None,
+            from: None,
+            recursive: false,
+            traversal: Traversal::CommandLine,
+            preserve_root: true,
+            dereference: true,
+            umask: 0,
+            silent: false,
+            verbose: false,
+            dry_run: false,
+            jobs: 1,
+            files0_from: None,
+            paths: vec![],
+        }
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("755"), Ok(Mode::Octal(0o755)));
+        assert_eq!(parse_mode("2755"), Ok(Mode::Octal(0o2755)));
+        assert_eq!(
+            parse_mode("go-w"),
+            Ok(Mode::Symbolic(vec![Clause {
+                who: GROUP | SETGID | OTHER | STICKY,
+                op: Op::Remove,
+                perms: WRITE,
+                conditional_execute: false,
+                copy_from: None,
+                masked: false,
+            }]))
+        );
+        assert!(parse_mode("u+q").is_err());
+        assert!(parse_mode("u").is_err());
+        assert!(parse_mode("8").is_err());
+    }
+
+    #[test]
+    fn test_invalid_modes() {
+        assert_eq!(parse_mode("7777"), Ok(Mode::Octal(0o7777)));
+        assert_eq!(parse_mode("0644"), Ok(Mode::Octal(0o644)));
+        for mode in [
+            "77777", "00755", "999", "", "u+rw;", "u+rw,", ",u+rw", "u+rw g-w",
+        ] {
+            assert_eq!(
+                parse_mode(mode),
+                Err(format!("invalid mode: '{mode}'")),
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        assert_eq!(apply("u+x", 0o644, false), 0o744);
+        assert_eq!(apply("go-r", 0o644, false), 0o600);
+        assert_eq!(apply("g=rw,o-r", 0o644, false), 0o660);
+        assert_eq!(apply("+x", 0o644, false), 0o755);
+    }
+
+    #[test]
+    fn test_set_nothing_clears() {
+        // What GNU chmod gives for the same modes.
+        assert_eq!(apply("o=", 0o757, false), 0o750);
+        assert_eq!(apply("a=", 0o4777, false), 0o0);
+        assert_eq!(apply("=", 0o777, false), 0o0);
+        assert_eq!(apply("u=rw,go=", 0o4777, false), 0o600);
+        assert_eq!(apply("go=,u=rw", 0o4777, false), 0o600);
+        assert_eq!(apply("u=rw,go=", 0o2755, true), 0o2600);
+        assert_eq!(apply("a=", 0o2755, true), 0o2000);
+        assert_eq!(apply("g=", 0o2755, true), 0o2705);
+        assert_eq!(parse_mode("go=").unwrap().apply(0o777, false, 0o022), 0o700);
+    }
+
+    #[test]
+    fn test_umask() {
+        let apply = |mode: &str, current| parse_mode(mode).unwrap().apply(current, false, 0o022);
+        assert_eq!(apply("+w", 0o444), 0o644);
+        assert_eq!(apply("a+w", 0o444), 0o666);
+        assert_eq!(apply("-w", 0o777), 0o577);
+        assert_eq!(apply("=r", 0o777), 0o444);
+        assert_eq!(apply("+x,u+w", 0o400), 0o711);
+        assert_eq!(apply("=u", 0o764), 0o755);
+        assert_eq!(apply("755", 0o600), 0o755);
+    }
+
+    #[test]
+    fn test_conditional_execute() {
+        assert_eq!(apply("a+X", 0o644, true), 0o755);
+        assert_eq!(apply("a+X", 0o744, false), 0o755);
+        assert_eq!(apply("a+X", 0o644, false), 0o644);
+        assert_eq!(apply("go+rX", 0o600, false), 0o644);
+    }
+
+    #[test]
+    fn test_copy_class() {
+        // Results from GNU chmod.
+        let cases = [
+            ("g=u", [0o660, 0o771, 0o664, 0o4770]),
+            ("o+u", [0o646, 0o757, 0o606, 0o4757]),
+            ("g=rx,o=g", [0o655, 0o755, 0o655, 0o4755]),
+            ("u-g", [0o240, 0o251, 0o604, 0o4250]),
+            ("a=o", [0o000, 0o111, 0o444, 0o000]),
+        ];
+        for (mode, expected) in cases {
+            for (start, expected) in [0o640, 0o751, 0o604, 0o4750].into_iter().zip(expected) {
+                assert_eq!(apply(mode, start, false), expected, "{mode} on {start:o}");
+            }
+        }
+        assert!(parse_mode("o=ug").is_err());
+        assert!(parse_mode("g+ur").is_err());
+    }
+
+    #[test]
+    fn test_special_bits() {
+        assert_eq!(apply("u+s", 0o755, false), 0o4755);
+        assert_eq!(apply("g+s", 0o755, false), 0o2755);
+        assert_eq!(apply("+t", 0o755, true), 0o1755);
+        assert_eq!(apply("o+t", 0o755, true), 0o1755);
+        assert_eq!(apply("u+t", 0o755, true), 0o755);
+        assert_eq!(apply("a-s", 0o6755, false), 0o755);
+        assert_eq!(apply("u=rwx", 0o4755, false), 0o755);
+        assert_eq!(apply("4755", 0o644, false), 0o4755);
+        // Directories keep set-user-ID and set-group-ID unless asked.
+        assert_eq!(apply("755", 0o2700, true), 0o2755);
+        assert_eq!(apply("g=rx", 0o2770, true), 0o2750);
+        assert_eq!(apply("g-s", 0o2770, true), 0o770);
+        assert_eq!(apply("755", 0o2700, false), 0o755);
+        assert_eq!(apply("1755", 0o6700, true), 0o7755);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_set_and_clear_special_bits_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        let sub = dir.path().join("sub");
+        fs::write(&file, "").unwrap();
+        fs::create_dir(&sub).unwrap();
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        for path in [&file, &sub] {
+            chmod_file(path, &config("755")).unwrap();
+            for (set, clear, bit) in [
+                ("u+s", "u-s", SETUID),
+                ("g+s", "g-s", SETGID),
+                ("+t", "-t", STICKY),
+            ] {
+                chmod_file(path, &config(set)).unwrap();
+                assert_eq!(mode(path), 0o755 | bit, "{set} on {}", path.display());
+                chmod_file(path, &config(clear)).unwrap();
+                assert_eq!(mode(path), 0o755, "{clear} on {}", path.display());
+            }
+        }
+        chmod_file(&sub, &config("2755")).unwrap();
+        chmod_file(&sub, &config("750")).unwrap();
+        assert_eq!(mode(&sub), 0o2750);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_is_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let link = dir.path().join("root");
+        std::os::unix::fs::symlink("/", &link).unwrap();
+        assert!(is_root(Path::new("/")));
+        assert!(is_root(Path::new("//")));
+        assert!(is_root(Path::new("/tmp/..")));
+        assert!(is_root(&link));
+        assert!(!is_root(dir.path()));
+        assert!(!is_root(&dir.path().join("missing")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_recursive_conditional_execute() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("tree");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("tool"), "").unwrap();
+        fs::write(root.join("plain"), "").unwrap();
+        let set = |p: &str, mode| {
+            fs::set_permissions(root.join(p), fs::Permissions::from_mode(mode)).unwrap()
+        };
+        set("sub", 0o700);
+        set("tool", 0o700);
+        set("plain", 0o600);
+
+        let config = Config {
+            recursive: true,
+            ..config("a+rX")
+        };
+        chmod_recursive(&root, &config, &mut |e| panic!("{e}"));
+        let mode = |p: &str| fs::metadata(root.join(p)).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode("sub"), 0o755);
+        assert_eq!(mode("tool"), 0o755);
+        assert_eq!(mode("plain"), 0o644);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_recursion_and_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("tree");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(root.join("sub/file"), "").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("out")).unwrap();
+        std::os::unix::fs::symlink("loop", root.join("loop")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("sub/up")).unwrap();
+        let link_to_root = dir.path().join("link");
+        std::os::unix::fs::symlink(&root, &link_to_root).unwrap();
+
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        let run = |path: &Path, mode: &str, traversal| {
+            let config = Config {
+                recursive: true,
+                traversal,
+                ..config(mode)
+            };
+            let mut errors = Vec::new();
+            chmod_recursive(path, &config, &mut |e| errors.push(e.to_string()));
+            errors
+        };
+        fs::set_permissions(&outside, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // -P follows no symlink, not even the operand.
+        assert!(run(&root, "700", Traversal::None).is_empty());
+        assert_eq!(mode(&root.join("sub/file")), 0o700);
+        assert_eq!(mode(&outside), 0o755);
+        assert!(run(&link_to_root, "750", Traversal::None).is_empty());
+        assert_eq!(mode(&root), 0o700);
+
+        // -H, the default, follows the operand but nothing below it.
+        assert!(run(&link_to_root, "750", Traversal::CommandLine).is_empty());
+        assert_eq!(mode(&root.join("sub/file")), 0o750);
+        assert_eq!(mode(&outside), 0o755);
+
+        // -L follows everything, and still gets past the cycle through
+        // "sub/up" without going round it forever. The self-referential
+        // link cannot be resolved at all, but the rest is still done.
+        fs::remove_file(root.join("loop")).unwrap();
+        assert!(run(&root, "711", Traversal::All).is_empty());
+        assert_eq!(mode(&outside), 0o711);
+        assert_eq!(mode(&root.join("sub/file")), 0o711);
+        std::os::unix::fs::symlink("loop", root.join("loop")).unwrap();
+        let errors = run(&root, "701", Traversal::All);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("/loop'"));
+        assert_eq!(mode(&root.join("sub/file")), 0o701);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_deep_tree() {
+        const DEPTH: usize = 10_000;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("d");
+        fs::create_dir(&root).unwrap();
+        // Far too deep for paths, so it is built and checked a directory
+        // at a time through descriptors.
+        let open = |fd, name: &CStr| unsafe {
+            libc::openat(fd, name.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY)
+        };
+        let top = CString::new(root.as_os_str().as_bytes()).unwrap();
+        let mut fd = open(libc::AT_FDCWD, &top);
+        for _ in 1..DEPTH {
+            unsafe {
+                assert_eq!(libc::mkdirat(fd, c"d".as_ptr(), 0o755), 0);
+                let next = open(fd, c"d");
+                libc::close(fd);
+                fd = next;
+            }
+        }
+        unsafe { libc::close(fd) };
+        let modes = || {
+            let mut modes = Vec::new();
+            let mut fd = open(libc::AT_FDCWD, &top);
+            while fd >= 0 {
+                let mut st = MaybeUninit::<libc::stat>::uninit();
+                unsafe {
+                    libc::fstat(fd, st.as_mut_ptr());
+                    modes.push(st.assume_init().st_mode & 0o7777);
+                    let next = open(fd, c"d");
+                    libc::close(fd);
+                    fd = next;
+                }
+            }
+            modes
+        };
+
+        let run = |mode: &str| {
+            let config = Config {
+                recursive: true,
+                ..config(mode)
+            };
+            chmod_recursive(&root, &config, &mut |e| panic!("{e}"));
+        };
+        // Taking away search permission works from the bottom up.
+        run("u-x");
+        assert_eq!(
+            fs::metadata(&root).unwrap().permissions().mode() & 0o7777,
+            0o655
+        );
+        run("u+x,g-x");
+        assert_eq!(modes(), vec![0o745; DEPTH]);
+
+        // Taken apart from the bottom, as it was built.
+        let mut fd = open(libc::AT_FDCWD, &top);
+        for _ in 1..DEPTH - 1 {
+            let next = open(fd, c"d");
+            unsafe { libc::close(fd) };
+            fd = next;
+        }
+        for _ in 1..DEPTH {
+            unsafe {
+                assert_eq!(libc::unlinkat(fd, c"d".as_ptr(), libc::AT_REMOVEDIR), 0);
+                let up = open(fd, c"..");
+                libc::close(fd);
+                fd = up;
+            }
+        }
+        unsafe { libc::close(fd) };
+    }
+
+    #[test]
+    #[cfg(not(unix))]
+    fn test_readonly_mapping() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let readonly = || fs::metadata(&file).unwrap().permissions().readonly();
+        chmod_file(&file, &config("a-w")).unwrap();
+        assert!(readonly());
+        chmod_file(&file, &config("o+w")).unwrap();
+        assert!(!readonly());
+        chmod_file(&file, &config("444")).unwrap();
+        assert!(readonly());
+        chmod_file(&file, &config("600")).unwrap();
+        assert!(!readonly());
+    }
+}
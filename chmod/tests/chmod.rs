@@ -0,0 +1,426 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+
+fn qchmod() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_qchmod"))
+}
+
+fn mode(path: &std::path::Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o7777
+}
+
+#[test]
+fn test_missing_operand_does_not_stop_the_rest() {
+    let dir = tempfile::tempdir().unwrap();
+    let real = dir.path().join("real");
+    fs::write(&real, "").unwrap();
+    fs::set_permissions(&real, fs::Permissions::from_mode(0o644)).unwrap();
+    let output = qchmod()
+        .current_dir(dir.path())
+        .args(["600", "missing", "real"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(mode(&real), 0o600);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("chmod: "));
+    assert!(stderr.contains("'missing'"));
+    assert_eq!(stderr.lines().count(), 1);
+}
+
+#[test]
+fn test_silent() {
+    let dir = tempfile::tempdir().unwrap();
+    for flag in ["-f", "--silent", "--quiet"] {
+        let output = qchmod()
+            .current_dir(dir.path())
+            .args([flag, "600", "missing"])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stderr.is_empty());
+    }
+    // A bad mode is a usage error, which -f does not hide.
+    let output = qchmod()
+        .current_dir(dir.path())
+        .args(["-f", "u+q", "missing"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn test_umask_limits_who_less_modes() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file");
+    fs::write(&file, "").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o400)).unwrap();
+    let script = format!("umask 027; {} +rw file", env!("CARGO_BIN_EXE_qchmod"));
+    let status = Command::new("sh")
+        .current_dir(dir.path())
+        .args(["-c", &script])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(mode(&file), 0o640);
+}
+
+fn stderr(args: &[&str], dir: &std::path::Path) -> (Option<i32>, String) {
+    let output = qchmod().current_dir(dir).args(args).output().unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_diagnostics() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(
+        stderr(&["644", "missing"], dir.path()),
+        (
+            Some(1),
+            "chmod: cannot access 'missing': No such file or directory\n".to_string()
+        )
+    );
+    assert_eq!(
+        stderr(&["u+q", "missing"], dir.path()),
+        (Some(1), "chmod: invalid mode: 'u+q'\n".to_string())
+    );
+    // Usage errors are clap's, with its exit status.
+    assert_eq!(stderr(&["644"], dir.path()).0, Some(2));
+
+    // Root can look inside anything, so the permission case needs a normal
+    // user.
+    let locked = dir.path().join("locked");
+    fs::create_dir(&locked).unwrap();
+    fs::write(locked.join("file"), "").unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    let readable = fs::metadata(locked.join("file")).is_ok();
+    let result = stderr(&["644", "locked/file"], dir.path());
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    if !readable {
+        assert_eq!(
+            result,
+            (
+                Some(1),
+                "chmod: cannot access 'locked/file': Permission denied\n".to_string()
+            )
+        );
+    }
+}
+
+#[test]
+fn test_directory_changed_after_contents_when_losing_search() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("tree")).unwrap();
+    fs::write(dir.path().join("tree/file"), "").unwrap();
+    let changed = |mode: &str| {
+        let output = qchmod()
+            .current_dir(dir.path())
+            .args(["-Rv", mode, "tree"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('\'').nth(1).unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(changed("u-x"), ["tree/file", "tree"]);
+    assert_eq!(changed("u+x"), ["tree", "tree/file"]);
+}
+
+#[test]
+fn test_jobs() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    for i in 0..20 {
+        let sub = tree.join(format!("sub{i}"));
+        fs::create_dir_all(&sub).unwrap();
+        for j in 0..20 {
+            fs::write(sub.join(format!("file{j}")), "").unwrap();
+        }
+    }
+    let output = qchmod()
+        .current_dir(dir.path())
+        .args(["-R", "--jobs", "4", "700", "tree", "missing"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "chmod: cannot access 'missing': No such file or directory\n"
+    );
+    for i in 0..20 {
+        let sub = tree.join(format!("sub{i}"));
+        assert_eq!(mode(&sub), 0o700);
+        for j in 0..20 {
+            assert_eq!(mode(&sub.join(format!("file{j}"))), 0o700);
+        }
+    }
+}
+
+#[test]
+fn test_verbose_into_closed_pipe() {
+    use std::process::Stdio;
+
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir(&tree).unwrap();
+    // Enough to say that a pipe cannot hold it all.
+    for i in 0..2000 {
+        fs::write(tree.join(format!("file{i}")), "").unwrap();
+    }
+    let mut child = qchmod()
+        .current_dir(dir.path())
+        .args(["-Rv", "--jobs", "4", "644", "tree"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{output:?}");
+    assert!(output.stderr.is_empty(), "{output:?}");
+}
+
+#[test]
+fn test_mode_starting_with_dash() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("f");
+    let sub = dir.path().join("d");
+    fs::write(&file, "").unwrap();
+    fs::create_dir(&sub).unwrap();
+    fs::write(sub.join("g"), "").unwrap();
+    let run = |args: &[&str]| {
+        let status = qchmod()
+            .current_dir(dir.path())
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{args:?}");
+    };
+
+    // Only the owner can write, so that the umask does not matter.
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+    run(&["-w", "f"]);
+    assert_eq!(mode(&file), 0o444);
+
+    run(&["755", "f", "d", "d/g"]);
+    run(&["-R", "-w", "d"]);
+    assert_eq!(mode(&sub), 0o555);
+    assert_eq!(mode(&sub.join("g")), 0o555);
+
+    run(&["644", "f"]);
+    run(&["--", "-w", "f"]);
+    assert_eq!(mode(&file), 0o444);
+}
+
+#[test]
+fn test_unreadable_directory_is_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    for sub in ["a", "locked", "z"] {
+        fs::create_dir_all(tree.join(sub)).unwrap();
+        fs::write(tree.join(sub).join("file"), "").unwrap();
+    }
+    let locked = tree.join("locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    // There is nothing to skip for a user the mode does not keep out.
+    if fs::read_dir(&locked).is_ok() {
+        return;
+    }
+    let output = qchmod()
+        .current_dir(dir.path())
+        .args(["-R", "o-r", "tree"])
+        .output()
+        .unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "chmod: cannot read directory 'tree/locked': Permission denied\n"
+    );
+    for sub in ["a", "z"] {
+        assert_eq!(mode(&tree.join(sub).join("file")) & 0o004, 0);
+    }
+}
+
+#[test]
+fn test_files0_from() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut list = Vec::new();
+    for i in 0..50 {
+        let file = dir.path().join(format!("file {i}"));
+        fs::write(&file, "").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        list.extend_from_slice(format!("file {i}\0").as_bytes());
+    }
+    list.extend_from_slice(b"\0");
+    let mut child = qchmod()
+        .current_dir(dir.path())
+        .args(["--files0-from=-", "600"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&list).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "chmod: -:51: invalid zero-length file name\n"
+    );
+    for i in 0..50 {
+        assert_eq!(mode(&dir.path().join(format!("file {i}"))), 0o600);
+    }
+
+    let output = qchmod()
+        .current_dir(dir.path())
+        .args(["--files0-from=-", "600", "file 0"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_symlink_operands() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file");
+    fs::write(&file, "").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+    std::os::unix::fs::symlink("file", dir.path().join("link")).unwrap();
+    std::os::unix::fs::symlink("missing", dir.path().join("dangling")).unwrap();
+
+    // By default the link stands for its target.
+    let (code, err) = stderr(&["600", "link"], dir.path());
+    assert_eq!((code, err.as_str()), (Some(0), ""));
+    assert_eq!(mode(&file), 0o600);
+
+    assert_eq!(
+        stderr(&["600", "dangling"], dir.path()),
+        (
+            Some(1),
+            "chmod: cannot operate on dangling symlink 'dangling'\n".to_string()
+        )
+    );
+
+    // Linux has no modes on symlinks, and says so.
+    if cfg!(target_os = "linux") {
+        assert_eq!(
+            stderr(&["-h", "644", "link"], dir.path()),
+            (
+                Some(1),
+                "chmod: changing permissions of 'link': Operation not supported\n".to_string()
+            )
+        );
+        assert_eq!(mode(&file), 0o600);
+    }
+    let (code, _) = stderr(&["--no-dereference", "644", "file"], dir.path());
+    assert_eq!(code, Some(0));
+    assert_eq!(mode(&file), 0o644);
+
+    // So it does with -R, unless -P says otherwise.
+    let (code, err) = stderr(&["-R", "600", "link"], dir.path());
+    assert_eq!((code, err.as_str()), (Some(0), ""));
+    assert_eq!(mode(&file), 0o600);
+    let (code, _) = stderr(&["-R", "-P", "644", "link"], dir.path());
+    assert_eq!(code, Some(0));
+    assert_eq!(mode(&file), 0o600);
+}
+
+#[test]
+fn test_dirs_mode() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("a/b")).unwrap();
+    for file in ["top", "a/mid", "a/b/deep"] {
+        fs::write(tree.join(file), "").unwrap();
+        fs::set_permissions(tree.join(file), fs::Permissions::from_mode(0o700)).unwrap();
+    }
+    std::os::unix::fs::symlink("top", tree.join("link")).unwrap();
+    let run = |args: &[&str]| {
+        let status = qchmod()
+            .current_dir(dir.path())
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{args:?}");
+    };
+
+    run(&["-R", "--dirs", "755", "644", "tree"]);
+    for sub in ["tree", "tree/a", "tree/a/b"] {
+        assert_eq!(mode(&dir.path().join(sub)), 0o755, "{sub}");
+    }
+    for file in ["top", "a/mid", "a/b/deep"] {
+        assert_eq!(mode(&tree.join(file)), 0o644, "{file}");
+    }
+
+    // Taking search permission away from directories still gets to the
+    // files inside them.
+    run(&["-R", "--dirs", "u=rw,go=", "u=r,go=", "tree"]);
+    run(&["-R", "--dirs", "u+x", "u+w", "tree"]);
+    for sub in ["tree", "tree/a", "tree/a/b"] {
+        assert_eq!(mode(&dir.path().join(sub)), 0o700, "{sub}");
+    }
+    for file in ["top", "a/mid", "a/b/deep"] {
+        assert_eq!(mode(&tree.join(file)), 0o600, "{file}");
+    }
+}
+
+#[test]
+fn test_from() {
+    let dir = tempfile::tempdir().unwrap();
+    let tree = dir.path().join("tree");
+    fs::create_dir_all(tree.join("sub")).unwrap();
+    fs::set_permissions(&tree, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::set_permissions(tree.join("sub"), fs::Permissions::from_mode(0o755)).unwrap();
+    let files = [("a", 0o777), ("b", 0o755), ("c", 0o644), ("sub/d", 0o777)];
+    for (name, perms) in files {
+        fs::write(tree.join(name), "").unwrap();
+        fs::set_permissions(tree.join(name), fs::Permissions::from_mode(perms)).unwrap();
+    }
+    let run = |args: &[&str]| {
+        let output = qchmod()
+            .current_dir(dir.path())
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{args:?}");
+        let mut lines: Vec<String> = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        lines
+    };
+
+    let expected = [
+        "mode of 'tree' retained as 0755",
+        "mode of 'tree/a' changed from 0777 to 0755",
+        "mode of 'tree/b' retained as 0755",
+        "mode of 'tree/c' retained as 0644",
+        "mode of 'tree/sub' retained as 0755",
+        "mode of 'tree/sub/d' changed from 0777 to 0755",
+    ];
+    assert_eq!(
+        run(&["-Rv", "--dry-run", "--from=777", "755", "tree"]),
+        expected
+    );
+    assert_eq!(mode(&tree.join("a")), 0o777);
+
+    assert_eq!(run(&["-R", "--from=a=rwx", "755", "tree"]), [""; 0]);
+    for (name, perms) in files {
+        assert_eq!(mode(&tree.join(name)), perms.min(0o755), "{name}");
+    }
+}
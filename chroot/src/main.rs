@@ -0,0 +1,498 @@
+use std::env;
+use std::ffi::{CString, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+
+// Exit codes shared with the other "run a command" utilities.
+const EXIT_CANCELED: i32 = 125;
+const EXIT_CANNOT_INVOKE: i32 = 126;
+const EXIT_ENOENT: i32 = 127;
+
+#[derive(Debug)]
+struct Config {
+    root: PathBuf,
+    command: Vec<OsString>,
+    userspec: Option<String>,
+    groups: Option<String>,
+    skip_chdir: bool,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Self {
+        let command = match options.get_many::<OsString>("command") {
+            Some(v) => v.cloned().collect(),
+            None => {
+                let shell = env::var_os("SHELL").unwrap_or_else(|| OsString::from("/bin/sh"));
+                vec![shell, OsString::from("-i")]
+            }
+        };
+        Self {
+            root: options.get_one::<PathBuf>("newroot").unwrap().clone(),
+            command,
+            userspec: options.get_one::<String>("userspec").cloned(),
+            groups: options.get_one::<String>("groups").cloned(),
+            skip_chdir: options.get_flag("skip_chdir"),
+        }
+    }
+}
+
+/// The system calls chroot is made of. Keeping them behind a trait lets the
+/// tests check the order privileges are dropped in without being root.
+trait Sys {
+    fn chroot(&mut self, root: &Path) -> io::Result<()>;
+    fn chdir(&mut self, dir: &Path) -> io::Result<()>;
+    fn setgroups(&mut self, groups: &[libc::gid_t]) -> io::Result<()>;
+    fn setgid(&mut self, gid: libc::gid_t) -> io::Result<()>;
+    fn setuid(&mut self, uid: libc::uid_t) -> io::Result<()>;
+    /// Returns the uid and primary gid of a user name.
+    fn lookup_user(&self, name: &str) -> Option<(libc::uid_t, libc::gid_t)>;
+    fn lookup_group(&self, name: &str) -> Option<libc::gid_t>;
+    /// Returns the supplementary groups of a user, including `gid`.
+    fn user_groups(&self, name: &str, gid: libc::gid_t) -> Vec<libc::gid_t>;
+    /// Replaces the process image; only returns when that failed.
+    fn exec(&mut self, command: &[OsString]) -> io::Error;
+}
+
+struct RealSys;
+
+fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+impl Sys for RealSys {
+    fn chroot(&mut self, root: &Path) -> io::Result<()> {
+        let root = cstring(root)?;
+        check(unsafe { libc::chroot(root.as_ptr()) })
+    }
+
+    fn chdir(&mut self, dir: &Path) -> io::Result<()> {
+        env::set_current_dir(dir)
+    }
+
+    fn setgroups(&mut self, groups: &[libc::gid_t]) -> io::Result<()> {
+        check(unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) })
+    }
+
+    fn setgid(&mut self, gid: libc::gid_t) -> io::Result<()> {
+        check(unsafe { libc::setgid(gid) })
+    }
+
+    fn setuid(&mut self, uid: libc::uid_t) -> io::Result<()> {
+        check(unsafe { libc::setuid(uid) })
+    }
+
+    fn lookup_user(&self, name: &str) -> Option<(libc::uid_t, libc::gid_t)> {
+        let name = CString::new(name).ok()?;
+        let pw = unsafe { libc::getpwnam(name.as_ptr()) };
+        match pw.is_null() {
+            true => None,
+            false => Some(unsafe { ((*pw).pw_uid, (*pw).pw_gid) }),
+        }
+    }
+
+    fn lookup_group(&self, name: &str) -> Option<libc::gid_t> {
+        let name = CString::new(name).ok()?;
+        let gr = unsafe { libc::getgrnam(name.as_ptr()) };
+        match gr.is_null() {
+            true => None,
+            false => Some(unsafe { (*gr).gr_gid }),
+        }
+    }
+
+    fn user_groups(&self, name: &str, gid: libc::gid_t) -> Vec<libc::gid_t> {
+        let Ok(cname) = CString::new(name) else {
+            return vec![gid];
+        };
+        let mut groups: Vec<libc::gid_t> = vec![0; 64];
+        loop {
+            let mut n = groups.len() as libc::c_int;
+            let ret =
+                unsafe { libc::getgrouplist(cname.as_ptr(), gid, groups.as_mut_ptr(), &mut n) };
+            if ret >= 0 {
+                groups.truncate(n as usize);
+                return groups;
+            }
+            // The list did not fit; n now holds the size that is needed.
+            groups.resize((n as usize).max(groups.len() * 2), 0);
+        }
+    }
+
+    fn exec(&mut self, command: &[OsString]) -> io::Error {
+        process::Command::new(&command[0])
+            .args(&command[1..])
+            .exec()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Failure {
+    status: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: i32, message: String) -> Self {
+        Self { status, message }
+    }
+}
+
+/// The identity to switch to once inside the new root.
+#[derive(Debug, Default, PartialEq)]
+struct Credentials {
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    groups: Option<Vec<libc::gid_t>>,
+}
+
+fn parse_id<S: Sys>(sys: &S, name: &str, is_user: bool) -> Result<(u32, Option<u32>), Failure> {
+    if is_user {
+        if let Some((uid, gid)) = sys.lookup_user(name) {
+            return Ok((uid, Some(gid)));
+        }
+    } else if let Some(gid) = sys.lookup_group(name) {
+        return Ok((gid, None));
+    }
+    match name.parse::<u32>() {
+        Ok(id) => Ok((id, None)),
+        Err(_) => {
+            let kind = if is_user { "user" } else { "group" };
+            Err(Failure::new(
+                EXIT_CANCELED,
+                format!("invalid {kind} '{name}'"),
+            ))
+        }
+    }
+}
+
+fn resolve<S: Sys>(sys: &S, config: &Config) -> Result<Credentials, Failure> {
+    let mut creds = Credentials::default();
+    let mut user_name = None;
+
+    if let Some(spec) = &config.userspec {
+        let (user, group) = match spec.split_once(':') {
+            Some((u, g)) => (u, g),
+            None => (spec.as_str(), ""),
+        };
+        if !user.is_empty() {
+            let (uid, primary) = parse_id(sys, user, true)?;
+            creds.uid = Some(uid);
+            creds.gid = primary;
+            if primary.is_some() {
+                user_name = Some(user);
+            }
+        }
+        if !group.is_empty() {
+            creds.gid = Some(parse_id(sys, group, false)?.0);
+        } else if creds.uid.is_some() && creds.gid.is_none() {
+            return Err(Failure::new(
+                EXIT_CANCELED,
+                format!("no group specified for unknown uid: {}", creds.uid.unwrap()),
+            ));
+        }
+    }
+
+    if let Some(list) = &config.groups {
+        let groups = list
+            .split(',')
+            .filter(|g| !g.is_empty())
+            .map(|g| parse_id(sys, g, false).map(|(gid, _)| gid))
+            .collect::<Result<Vec<_>, _>>()?;
+        creds.groups = Some(groups);
+    } else if let (Some(name), Some(gid)) = (user_name, creds.gid) {
+        creds.groups = Some(sys.user_groups(name, gid));
+    } else if let (Some(_), Some(gid)) = (creds.uid, creds.gid) {
+        creds.groups = Some(vec![gid]);
+    }
+
+    Ok(creds)
+}
+
+/// Enters the new root and runs the command. Only returns on failure.
+fn run<S: Sys>(sys: &mut S, config: &Config) -> Failure {
+    let is_old_root = config
+        .root
+        .canonicalize()
+        .map(|p| p == Path::new("/"))
+        .unwrap_or(false);
+    if config.skip_chdir && !is_old_root {
+        return Failure::new(
+            EXIT_CANCELED,
+            "option --skip-chdir only permitted if NEWROOT is old '/'".to_string(),
+        );
+    }
+
+    // Names are resolved before the chroot, while the host databases are
+    // still reachable.
+    let creds = match resolve(sys, config) {
+        Ok(creds) => creds,
+        Err(f) => return f,
+    };
+
+    if let Err(e) = sys.chroot(&config.root) {
+        return Failure::new(
+            EXIT_CANCELED,
+            format!(
+                "cannot change root directory to '{}': {e}",
+                config.root.display()
+            ),
+        );
+    }
+    if !config.skip_chdir {
+        if let Err(e) = sys.chdir(Path::new("/")) {
+            return Failure::new(
+                EXIT_CANCELED,
+                format!("cannot chdir to root directory: {e}"),
+            );
+        }
+    }
+
+    // Supplementary groups and the gid can only be changed while we still
+    // have the privileges that setuid gives up, so the order matters.
+    if let Some(groups) = &creds.groups {
+        if let Err(e) = sys.setgroups(groups) {
+            return Failure::new(
+                EXIT_CANCELED,
+                format!("failed to set supplemental groups: {e}"),
+            );
+        }
+    }
+    if let Some(gid) = creds.gid {
+        if let Err(e) = sys.setgid(gid) {
+            return Failure::new(EXIT_CANCELED, format!("failed to set group-ID: {e}"));
+        }
+    }
+    if let Some(uid) = creds.uid {
+        if let Err(e) = sys.setuid(uid) {
+            return Failure::new(EXIT_CANCELED, format!("failed to set user-ID: {e}"));
+        }
+    }
+
+    let e = sys.exec(&config.command);
+    let status = match e.kind() {
+        io::ErrorKind::NotFound => EXIT_ENOENT,
+        _ => EXIT_CANNOT_INVOKE,
+    };
+    Failure::new(
+        status,
+        format!(
+            "failed to run command '{}': {e}",
+            config.command[0].to_string_lossy()
+        ),
+    )
+}
+
+fn main() {
+    let matches = Command::new("chroot")
+        .arg(
+            Arg::new("userspec")
+                .long("userspec")
+                .value_name("USER:GROUP")
+                .help("Specify user and group (ID or name) to use."),
+        )
+        .arg(
+            Arg::new("groups")
+                .long("groups")
+                .value_name("G_LIST")
+                .help("Specify supplementary groups as g1,g2,..,gN."),
+        )
+        .arg(
+            Arg::new("skip_chdir")
+                .long("skip-chdir")
+                .action(ArgAction::SetTrue)
+                .help("Do not change working directory to '/'."),
+        )
+        .arg(
+            Arg::new("newroot")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("command")
+                .action(ArgAction::Append)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true)
+                .value_parser(value_parser!(OsString)),
+        )
+        .get_matches();
+    let config = Config::from(&matches);
+    let failure = run(&mut RealSys, &config);
+    eprintln!("chroot: {}", failure.message);
+    process::exit(failure.status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Chroot(PathBuf),
+        Chdir(PathBuf),
+        SetGroups(Vec<u32>),
+        SetGid(u32),
+        SetUid(u32),
+        Exec(Vec<OsString>),
+    }
+
+    #[derive(Default)]
+    struct FakeSys {
+        calls: Vec<Call>,
+        fail_chroot: bool,
+        exec_error: Option<i32>,
+    }
+
+    impl Sys for FakeSys {
+        fn chroot(&mut self, root: &Path) -> io::Result<()> {
+            if self.fail_chroot {
+                return Err(io::Error::from_raw_os_error(libc::EPERM));
+            }
+            self.calls.push(Call::Chroot(root.to_path_buf()));
+            Ok(())
+        }
+
+        fn chdir(&mut self, dir: &Path) -> io::Result<()> {
+            self.calls.push(Call::Chdir(dir.to_path_buf()));
+            Ok(())
+        }
+
+        fn setgroups(&mut self, groups: &[libc::gid_t]) -> io::Result<()> {
+            self.calls.push(Call::SetGroups(groups.to_vec()));
+            Ok(())
+        }
+
+        fn setgid(&mut self, gid: libc::gid_t) -> io::Result<()> {
+            self.calls.push(Call::SetGid(gid));
+            Ok(())
+        }
+
+        fn setuid(&mut self, uid: libc::uid_t) -> io::Result<()> {
+            self.calls.push(Call::SetUid(uid));
+            Ok(())
+        }
+
+        fn lookup_user(&self, name: &str) -> Option<(libc::uid_t, libc::gid_t)> {
+            (name == "alice").then_some((1000, 100))
+        }
+
+        fn lookup_group(&self, name: &str) -> Option<libc::gid_t> {
+            (name == "wheel").then_some(10)
+        }
+
+        fn user_groups(&self, _name: &str, gid: libc::gid_t) -> Vec<libc::gid_t> {
+            vec![gid, 10]
+        }
+
+        fn exec(&mut self, command: &[OsString]) -> io::Error {
+            self.calls.push(Call::Exec(command.to_vec()));
+            io::Error::from_raw_os_error(self.exec_error.unwrap_or(libc::ENOENT))
+        }
+    }
+
+    fn config(userspec: Option<&str>, groups: Option<&str>) -> Config {
+        Config {
+            root: PathBuf::from("/srv/jail"),
+            command: vec![OsString::from("true")],
+            userspec: userspec.map(String::from),
+            groups: groups.map(String::from),
+            skip_chdir: false,
+        }
+    }
+
+    #[test]
+    fn test_privileges_dropped_in_order() {
+        let mut sys = FakeSys::default();
+        run(&mut sys, &config(Some("1000:1000"), Some("20,wheel")));
+        assert_eq!(
+            sys.calls,
+            vec![
+                Call::Chroot(PathBuf::from("/srv/jail")),
+                Call::Chdir(PathBuf::from("/")),
+                Call::SetGroups(vec![20, 10]),
+                Call::SetGid(1000),
+                Call::SetUid(1000),
+                Call::Exec(vec![OsString::from("true")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_name_uses_its_groups() {
+        let mut sys = FakeSys::default();
+        run(&mut sys, &config(Some("alice"), None));
+        assert_eq!(
+            sys.calls[2..5],
+            [
+                Call::SetGroups(vec![100, 10]),
+                Call::SetGid(100),
+                Call::SetUid(1000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_uid_needs_group() {
+        let mut sys = FakeSys::default();
+        let failure = run(&mut sys, &config(Some("4242"), None));
+        assert_eq!(failure.status, EXIT_CANCELED);
+        assert!(sys.calls.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_group() {
+        let mut sys = FakeSys::default();
+        let failure = run(&mut sys, &config(None, Some("nosuchgroup")));
+        assert_eq!(failure.status, EXIT_CANCELED);
+        assert!(sys.calls.is_empty());
+    }
+
+    #[test]
+    fn test_chroot_failure() {
+        let mut sys = FakeSys {
+            fail_chroot: true,
+            ..Default::default()
+        };
+        let failure = run(&mut sys, &config(None, None));
+        assert_eq!(failure.status, EXIT_CANCELED);
+        assert!(sys.calls.is_empty());
+    }
+
+    #[test]
+    fn test_exec_exit_codes() {
+        let mut sys = FakeSys::default();
+        assert_eq!(run(&mut sys, &config(None, None)).status, EXIT_ENOENT);
+        let mut sys = FakeSys {
+            exec_error: Some(libc::EACCES),
+            ..Default::default()
+        };
+        assert_eq!(
+            run(&mut sys, &config(None, None)).status,
+            EXIT_CANNOT_INVOKE
+        );
+    }
+
+    #[test]
+    fn test_skip_chdir() {
+        let mut sys = FakeSys::default();
+        let mut cfg = config(None, None);
+        cfg.skip_chdir = true;
+        assert_eq!(run(&mut sys, &cfg).status, EXIT_CANCELED);
+        assert!(sys.calls.is_empty());
+
+        cfg.root = PathBuf::from("/");
+        run(&mut sys, &cfg);
+        assert!(!sys.calls.contains(&Call::Chdir(PathBuf::from("/"))));
+    }
+}
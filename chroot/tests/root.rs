@@ -0,0 +1,34 @@
+//! These tests need CAP_SYS_CHROOT, so they are ignored by default.
+//! Run them as root with `cargo test -p qoreutils-chroot -- --ignored`.
+
+use std::process::Command;
+
+fn qchroot() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_qchroot"))
+}
+
+#[test]
+#[ignore]
+fn test_chroot_to_old_root() {
+    let out = qchroot().args(["/", "pwd"]).output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"/\n");
+}
+
+#[test]
+#[ignore]
+fn test_userspec_drops_privileges() {
+    let out = qchroot()
+        .args(["--userspec=65534:65534", "--groups=65534", "/", "id", "-u"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(out.stdout, b"65534\n");
+}
+
+#[test]
+#[ignore]
+fn test_missing_command() {
+    let out = qchroot().args(["/", "/nonexistent"]).output().unwrap();
+    assert_eq!(out.status.code(), Some(127));
+}
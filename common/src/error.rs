@@ -0,0 +1,34 @@
+//! Error messages the way the C tools print them.
+
+use std::io;
+
+/// The message for `e` without the ` (os error N)` that Rust appends, so
+/// that `No such file or directory` reads the same as from GNU tools.
+pub fn strerror(e: &io::Error) -> String {
+    let message = e.to_string();
+    match e.raw_os_error() {
+        Some(code) => message
+            .strip_suffix(&format!(" (os error {code})"))
+            .map(str::to_string)
+            .unwrap_or(message),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strerror() {
+        let e = io::Error::from_raw_os_error(libc::ENOENT);
+        assert_eq!(
+            strerror(&e),
+            e.to_string().split(" (os error").next().unwrap()
+        );
+        #[cfg(unix)]
+        assert_eq!(strerror(&e), "No such file or directory");
+        let e = io::Error::other("plain");
+        assert_eq!(strerror(&e), "plain");
+    }
+}
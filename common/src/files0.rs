@@ -0,0 +1,63 @@
+//! Lists of file names for --files0-from.
+
+use std::ffi::OsString;
+use std::io::{self, BufRead};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// Yields the NUL-terminated names of a --files0-from list one at a time,
+/// so that a huge list never has to be held in memory.
+pub struct Files0<R: BufRead> {
+    reader: R,
+}
+
+impl<R: BufRead> Files0<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: BufRead> Iterator for Files0<R> {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut name = Vec::new();
+        match self.reader.read_until(b'\0', &mut name) {
+            Ok(0) => None,
+            Ok(_) => {
+                if name.last() == Some(&b'\0') {
+                    name.pop();
+                }
+                Some(Ok(PathBuf::from(os_string(name))))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn os_string(name: Vec<u8>) -> OsString {
+    OsString::from_vec(name)
+}
+
+/// Names that are not UTF-8 have no other way in.
+#[cfg(not(unix))]
+fn os_string(name: Vec<u8>) -> OsString {
+    String::from_utf8_lossy(&name).into_owned().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files0() {
+        let list = &b"a.txt\0dir/b c.txt\0\0last"[..];
+        let names: Vec<PathBuf> = Files0::new(list).map(|n| n.unwrap()).collect();
+        assert_eq!(
+            names,
+            ["a.txt", "dir/b c.txt", "", "last"].map(PathBuf::from)
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! Shell-style wildcard matching.
+
+use std::ffi::CString;
+
+/// Matches `text` against a shell glob the same way the C library does.
+/// Either side may be any bytes, since file names need not be UTF-8.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (Ok(pattern), Ok(text)) = (CString::new(pattern), CString::new(text)) else {
+        return false;
+    };
+    unsafe { libc::fnmatch(pattern.as_ptr(), text.as_ptr(), 0) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.rs", b"main.rs"));
+        assert!(glob_match(b"xterm*", b"xterm-256color"));
+        assert!(glob_match(b"[ab]?.txt", b"b1.txt"));
+        assert!(glob_match(b"*.rs", b"caf\xe9.rs"));
+        assert!(!glob_match(b"*.rs", b"main.rs.bak"));
+    }
+}
@@ -0,0 +1,12 @@
+//! Pieces shared by several of the utilities.
+
+pub mod error;
+pub mod files0;
+#[cfg(unix)]
+pub mod glob;
+#[cfg(unix)]
+pub mod mode;
+pub mod size;
+pub mod strftime;
+#[cfg(unix)]
+pub mod users;
@@ -0,0 +1,48 @@
+//! `ls -l` style rendering of file modes.
+
+/// Returns the file type letter `ls -l` puts in front of the permissions.
+pub fn type_char(mode: u32) -> char {
+    match mode & libc::S_IFMT {
+        m if m == libc::S_IFDIR => 'd',
+        m if m == libc::S_IFLNK => 'l',
+        m if m == libc::S_IFREG => '-',
+        m if m == libc::S_IFIFO => 'p',
+        m if m == libc::S_IFSOCK => 's',
+        m if m == libc::S_IFBLK => 'b',
+        m if m == libc::S_IFCHR => 'c',
+        _ => '?',
+    }
+}
+
+/// Renders a mode as ten characters, e.g. "drwxr-xr-x" or "-rwsr-x--T".
+pub fn mode_string(mode: u32) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(type_char(mode));
+    // setuid, setgid and sticky each show up in the execute slot of a triplet.
+    for (special, letter, shift) in [(0o4000, 's', 6), (0o2000, 's', 3), (0o1000, 't', 0)] {
+        let bits = (mode >> shift) & 0o7;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => letter,
+            (false, true) => letter.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_string() {
+        assert_eq!(mode_string(libc::S_IFDIR | 0o755), "drwxr-xr-x");
+        assert_eq!(mode_string(libc::S_IFREG | 0o644), "-rw-r--r--");
+        assert_eq!(mode_string(libc::S_IFREG | 0o4750), "-rwsr-x---");
+        assert_eq!(mode_string(libc::S_IFDIR | 0o1776), "drwxrwxrwT");
+        assert_eq!(mode_string(libc::S_IFLNK | 0o777), "lrwxrwxrwx");
+    }
+}
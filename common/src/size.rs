@@ -0,0 +1,149 @@
+//! Sizes with the suffixes coreutils accepts: `K`, `M`, `G`, ... and their
+//! `KiB` spellings are powers of 1024, `KB`, `MB`, ... are powers of 1000.
+
+use std::fmt;
+
+const UNITS: &[u8] = b"KMGTPEZY";
+
+#[derive(Debug, PartialEq)]
+pub struct SizeError(String);
+
+impl fmt::Display for SizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid size '{}'", self.0)
+    }
+}
+
+/// Splits "10KiB" into 10 and the multiplier of "KiB". The number may be
+/// left out ("K" means 1024), as in the coreutils parsers.
+fn split(s: &str) -> Result<(u64, u64), SizeError> {
+    let err = || SizeError(s.to_string());
+    let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+    let (number, suffix) = s.split_at(digits);
+    let number = match number {
+        "" if !suffix.is_empty() => 1,
+        _ => number.parse::<u64>().map_err(|_| err())?,
+    };
+
+    let multiplier = match suffix.as_bytes() {
+        [] => 1,
+        [b'b'] => 512,
+        [u] | [u, b'i', b'B'] => power(1024, *u).ok_or_else(err)?,
+        [u, b'B'] => power(1000, *u).ok_or_else(err)?,
+        _ => return Err(err()),
+    };
+    Ok((number, multiplier))
+}
+
+fn power(base: u64, unit: u8) -> Option<u64> {
+    let exp = UNITS.iter().position(|&u| u == unit.to_ascii_uppercase())?;
+    // 'k' is the only unit that is also accepted in lowercase.
+    if unit.is_ascii_lowercase() && unit != b'k' {
+        return None;
+    }
+    base.checked_pow(exp as u32 + 1)
+}
+
+/// Parses a size such as "4096", "10M" or "1KB" into a number of bytes.
+pub fn parse_size(s: &str) -> Result<u64, SizeError> {
+    let (number, multiplier) = split(s)?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| SizeError(s.to_string()))
+}
+
+/// The unit sizes are shown in, as with --block-size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockSize {
+    bytes: u64,
+    /// What to put after sizes, when the unit was given as a bare suffix
+    /// such as "M" rather than "1M".
+    suffix: Option<String>,
+}
+
+impl BlockSize {
+    pub const fn new(bytes: u64) -> Self {
+        Self {
+            bytes,
+            suffix: None,
+        }
+    }
+
+    /// Parses a block size such as "512", "K", "1M" or "KB".
+    pub fn parse(s: &str) -> Result<Self, SizeError> {
+        let bytes = parse_size(s)?;
+        if bytes == 0 {
+            return Err(SizeError(s.to_string()));
+        }
+        Ok(Self {
+            bytes,
+            suffix: s
+                .starts_with(|c: char| !c.is_ascii_digit())
+                .then(|| s.to_string()),
+        })
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// `n` bytes in these units, rounded up.
+    pub fn format(&self, n: u64) -> String {
+        let units = n.div_ceil(self.bytes);
+        match &self.suffix {
+            Some(suffix) => format!("{units}{suffix}"),
+            None => units.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("10K"), Ok(10 * 1024));
+        assert_eq!(parse_size("10k"), Ok(10 * 1024));
+        assert_eq!(parse_size("2MiB"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_size("3MB"), Ok(3_000_000));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+        assert_eq!(parse_size("2b"), Ok(1024));
+        assert_eq!(parse_size("M"), Ok(1 << 20));
+    }
+
+    #[test]
+    fn test_invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("10m").is_err());
+        assert!(parse_size("1.5K").is_err());
+        assert!(parse_size("100000Y").is_err());
+    }
+
+    #[test]
+    fn test_block_size() {
+        let format = |spec: &str, n| BlockSize::parse(spec).unwrap().format(n);
+        assert_eq!(format("512", 1000), "2");
+        assert_eq!(format("1K", 1024), "1");
+        assert_eq!(format("1K", 1025), "2");
+        assert_eq!(format("1M", 3 << 20), "3");
+        // A bare suffix is shown after the sizes.
+        assert_eq!(format("M", 3 << 20), "3M");
+        assert_eq!(format("K", 0), "0K");
+        assert_eq!(format("KiB", 2048), "2KiB");
+        assert_eq!(format("KB", 2048), "3KB");
+        assert_eq!(format("kB", 2000), "2kB");
+        assert_eq!(format("1KB", 2000), "2");
+        assert_eq!(BlockSize::parse("G").unwrap().bytes(), 1 << 30);
+        assert_eq!(BlockSize::new(1).format(12345), "12345");
+    }
+
+    #[test]
+    fn test_invalid_block_sizes() {
+        for spec in ["", "0", "0K", "-1", "1.5M", "X", "Mi", "1000000Y"] {
+            assert!(BlockSize::parse(spec).is_err(), "{spec}");
+        }
+    }
+}
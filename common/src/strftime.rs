@@ -0,0 +1,24 @@
+//! strftime formats as chrono reads them.
+
+use chrono::format::{Item, StrftimeItems};
+
+/// Whether chrono understands every specifier in `format`. It panics on bad
+/// ones when a time is printed, so formats from the user are checked up
+/// front.
+pub fn is_valid(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|item| item == Item::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid() {
+        assert!(is_valid("%Y-%m-%d %H:%M:%S%.3f"));
+        assert!(is_valid("plain text"));
+        assert!(is_valid("%%"));
+        assert!(!is_valid("%Q"));
+        assert!(!is_valid("trailing %"));
+    }
+}
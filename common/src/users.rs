@@ -0,0 +1,29 @@
+//! User and group name lookups.
+
+use std::ffi::CStr;
+
+/// Returns the login name for a uid, if it has one.
+pub fn user_name(uid: u32) -> Option<String> {
+    let pw = unsafe { libc::getpwuid(uid) };
+    match pw.is_null() {
+        true => None,
+        false => Some(
+            unsafe { CStr::from_ptr((*pw).pw_name) }
+                .to_string_lossy()
+                .into_owned(),
+        ),
+    }
+}
+
+/// Returns the name of a group, if it has one.
+pub fn group_name(gid: u32) -> Option<String> {
+    let gr = unsafe { libc::getgrgid(gid) };
+    match gr.is_null() {
+        true => None,
+        false => Some(
+            unsafe { CStr::from_ptr((*gr).gr_name) }
+                .to_string_lossy()
+                .into_owned(),
+        ),
+    }
+}
@@ -0,0 +1,263 @@
+//! The dircolors database and the LS_COLORS format it compiles to.
+//!
+//! `qdircolors` turns a database into an LS_COLORS string and `qls` reads that
+//! string back, so both sides live here to keep them from drifting apart.
+
+use std::fmt;
+
+use qoreutils_common::glob::glob_match;
+
+/// The database `qdircolors -p` prints and uses when no file is given.
+pub const DEFAULT_DATABASE: &str = include_str!("dircolors.db");
+
+/// Database keywords and the two-letter LS_COLORS codes they stand for.
+const KEYWORDS: &[(&str, &str)] = &[
+    ("NORMAL", "no"),
+    ("NORM", "no"),
+    ("FILE", "fi"),
+    ("RESET", "rs"),
+    ("DIR", "di"),
+    ("LNK", "ln"),
+    ("LINK", "ln"),
+    ("SYMLINK", "ln"),
+    ("ORPHAN", "or"),
+    ("MISSING", "mi"),
+    ("FIFO", "pi"),
+    ("PIPE", "pi"),
+    ("SOCK", "so"),
+    ("BLK", "bd"),
+    ("BLOCK", "bd"),
+    ("CHR", "cd"),
+    ("CHAR", "cd"),
+    ("DOOR", "do"),
+    ("EXEC", "ex"),
+    ("LEFT", "lc"),
+    ("LEFTCODE", "lc"),
+    ("RIGHT", "rc"),
+    ("RIGHTCODE", "rc"),
+    ("END", "ec"),
+    ("ENDCODE", "ec"),
+    ("SUID", "su"),
+    ("SETUID", "su"),
+    ("SGID", "sg"),
+    ("SETGID", "sg"),
+    ("STICKY", "st"),
+    ("OWR", "ow"),
+    ("OTHER_WRITABLE", "ow"),
+    ("OWT", "tw"),
+    ("STICKY_OTHER_WRITABLE", "tw"),
+    ("CAPABILITY", "ca"),
+    ("MULTIHARDLINK", "mh"),
+    ("CLRTOEOL", "cl"),
+];
+
+/// Keywords that are accepted for compatibility but have no effect.
+const IGNORED_KEYWORDS: &[&str] = &["COLOR", "OPTIONS", "EIGHTBIT"];
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.line, self.message)
+    }
+}
+
+/// A set of colors, as found in LS_COLORS.
+#[derive(Debug, Default, PartialEq)]
+pub struct Colors {
+    /// File type indicators such as `di` or `ln`, in the order they were given.
+    pub types: Vec<(String, String)>,
+    /// File name patterns such as `*.tar`, in the order they were given.
+    pub patterns: Vec<(String, String)>,
+}
+
+/// Strips a trailing comment. `#` only starts a comment at the beginning of
+/// the line or after whitespace, so patterns like `*#` survive.
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'#' && (i == 0 || bytes[i - 1].is_ascii_whitespace()) {
+            return &line[..i];
+        }
+    }
+    line
+}
+
+impl Colors {
+    /// Compiles a dircolors database for the given terminal.
+    ///
+    /// Entries that follow a run of `TERM`/`COLORTERM` lines only apply when
+    /// one of those lines matches; entries before the first such line always
+    /// apply.
+    pub fn from_database(db: &str, term: &str, colorterm: &str) -> Result<Self, ParseError> {
+        let mut colors = Colors::default();
+        let mut in_term_lines = false;
+        let mut matched = true;
+
+        for (n, line) in db.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (keyword, arg) = match line.split_once(char::is_whitespace) {
+                Some((k, a)) => (k, a.trim()),
+                None => {
+                    return Err(ParseError {
+                        line: n + 1,
+                        message: "invalid line; missing second token".to_string(),
+                    })
+                }
+            };
+
+            if keyword.eq_ignore_ascii_case("TERM") || keyword.eq_ignore_ascii_case("COLORTERM") {
+                if !in_term_lines {
+                    matched = false;
+                    in_term_lines = true;
+                }
+                let value = if keyword.eq_ignore_ascii_case("TERM") {
+                    term
+                } else {
+                    colorterm
+                };
+                matched |= glob_match(arg.as_bytes(), value.as_bytes());
+                continue;
+            }
+            in_term_lines = false;
+            if !matched {
+                continue;
+            }
+
+            if keyword.starts_with('.') {
+                colors.set_pattern(format!("*{keyword}"), arg);
+            } else if keyword.starts_with('*') {
+                colors.set_pattern(keyword.to_string(), arg);
+            } else if let Some((_, code)) = KEYWORDS
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(keyword))
+            {
+                colors.set_type(code, arg);
+            } else if !IGNORED_KEYWORDS
+                .iter()
+                .any(|k| k.eq_ignore_ascii_case(keyword))
+            {
+                return Err(ParseError {
+                    line: n + 1,
+                    message: format!("unrecognized keyword {keyword}"),
+                });
+            }
+        }
+        Ok(colors)
+    }
+
+    /// Parses an LS_COLORS value. Malformed entries are skipped, as ls does.
+    pub fn from_ls_colors(value: &str) -> Self {
+        let mut colors = Colors::default();
+        for entry in value.split(':') {
+            let Some((key, color)) = entry.split_once('=') else {
+                continue;
+            };
+            if key.starts_with('*') {
+                colors.set_pattern(key.to_string(), color);
+            } else if key.len() == 2 {
+                colors.set_type(key, color);
+            }
+        }
+        colors
+    }
+
+    /// Renders the colors in LS_COLORS syntax, with the trailing colon
+    /// dircolors has always printed.
+    pub fn to_ls_colors(&self) -> String {
+        self.types
+            .iter()
+            .chain(self.patterns.iter())
+            .map(|(k, v)| format!("{k}={v}:"))
+            .collect()
+    }
+
+    fn set_type(&mut self, code: &str, color: &str) {
+        match self.types.iter_mut().find(|(k, _)| k == code) {
+            Some(entry) => entry.1 = color.to_string(),
+            None => self.types.push((code.to_string(), color.to_string())),
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: String, color: &str) {
+        match self.patterns.iter_mut().find(|(k, _)| *k == pattern) {
+            Some(entry) => entry.1 = color.to_string(),
+            None => self.patterns.push((pattern, color.to_string())),
+        }
+    }
+
+    /// Returns the color for a two-letter file type code.
+    pub fn get(&self, code: &str) -> Option<&str> {
+        self.types
+            .iter()
+            .find(|(k, _)| k == code)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the color of the last pattern that matches `name`.
+    pub fn for_name(&self, name: &[u8]) -> Option<&str> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|(pattern, _)| match pattern.strip_prefix('*') {
+                Some(suffix) => name.ends_with(suffix.as_bytes()),
+                None => name == pattern.as_bytes(),
+            })
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let db = Colors::from_database(DEFAULT_DATABASE, "xterm-256color", "").unwrap();
+        assert_eq!(db.get("di"), Some("01;34"));
+        assert_eq!(db.for_name(b"backup.tar"), Some("01;31"));
+        let parsed = Colors::from_ls_colors(&db.to_ls_colors());
+        assert_eq!(db, parsed);
+    }
+
+    #[test]
+    fn test_from_ls_colors() {
+        let colors =
+            Colors::from_ls_colors("di=01;34:bogus:ln=:*.gz=01;31:toolong=1:ex=01;32:di=33");
+        assert_eq!(colors.get("di"), Some("33"));
+        assert_eq!(colors.get("ln"), Some(""));
+        assert_eq!(colors.get("ex"), Some("01;32"));
+        assert_eq!(colors.get("to"), None);
+        assert_eq!(colors.for_name(b"a.tar.gz"), Some("01;31"));
+        assert_eq!(colors.for_name(b"gz"), None);
+    }
+
+    #[test]
+    fn test_term_sections() {
+        let db = "DIR 01;34\nTERM xterm*\nTERM linux\nLINK 01;36\nTERM dumb\nEXEC 01;32\n";
+        let colors = Colors::from_database(db, "linux", "").unwrap();
+        assert_eq!(colors.to_ls_colors(), "di=01;34:ln=01;36:");
+        let colors = Colors::from_database(db, "dumb", "").unwrap();
+        assert_eq!(colors.to_ls_colors(), "di=01;34:ex=01;32:");
+    }
+
+    #[test]
+    fn test_comments_and_patterns() {
+        let db = "# leading comment\n.gz 01;31 # trailing\n*# 00;90\nOPTIONS -F\n";
+        let colors = Colors::from_database(db, "", "").unwrap();
+        assert_eq!(colors.to_ls_colors(), "*.gz=01;31:*#=00;90:");
+    }
+
+    #[test]
+    fn test_unrecognized_keyword() {
+        let err = Colors::from_database("DIR 01;34\nBOGUS 1\n", "", "").unwrap_err();
+        assert_eq!(err.to_string(), "2: unrecognized keyword BOGUS");
+    }
+}
@@ -0,0 +1,119 @@
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use qoreutils_dircolors::{Colors, DEFAULT_DATABASE};
+
+#[derive(Debug)]
+enum Shell {
+    Bourne,
+    C,
+}
+
+#[derive(Debug)]
+struct Config {
+    shell: Shell,
+    print_database: bool,
+    file: Option<String>,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Self {
+        let shell = if options.get_flag("bourne_shell") {
+            Shell::Bourne
+        } else if options.get_flag("c_shell") {
+            Shell::C
+        } else {
+            guess_shell()
+        };
+        Self {
+            shell,
+            print_database: options.get_flag("print_database"),
+            file: options.get_one::<String>("file").cloned(),
+        }
+    }
+}
+
+/// Picks the output syntax from $SHELL when neither -b nor -c is given.
+fn guess_shell() -> Shell {
+    let shell = env::var("SHELL").unwrap_or_default();
+    let name = shell.rsplit('/').next().unwrap_or("");
+    match name {
+        "csh" | "tcsh" => Shell::C,
+        _ => Shell::Bourne,
+    }
+}
+
+fn main() {
+    let matches = Command::new("dircolors")
+        .arg(
+            Arg::new("bourne_shell")
+                .short('b')
+                .long("sh")
+                .visible_alias("bourne-shell")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("c_shell")
+                .help("Output Bourne shell code to set LS_COLORS."),
+        )
+        .arg(
+            Arg::new("c_shell")
+                .short('c')
+                .long("csh")
+                .visible_alias("c-shell")
+                .action(ArgAction::SetTrue)
+                .help("Output C shell code to set LS_COLORS."),
+        )
+        .arg(
+            Arg::new("print_database")
+                .short('p')
+                .long("print-database")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["bourne_shell", "c_shell", "file"])
+                .help("Output defaults."),
+        )
+        .arg(Arg::new("file"))
+        .get_matches();
+    let config = Config::from(&matches);
+
+    if config.print_database {
+        print!("{DEFAULT_DATABASE}");
+        return;
+    }
+
+    let (name, db) = match config.file.as_deref() {
+        None => ("", DEFAULT_DATABASE.to_string()),
+        Some(path) => {
+            let mut db = String::new();
+            let res = match path {
+                "-" => io::stdin().read_to_string(&mut db),
+                _ => fs::File::open(path).and_then(|mut f| f.read_to_string(&mut db)),
+            };
+            if let Err(e) = res {
+                eprintln!("dircolors: {path}: {e}");
+                process::exit(1);
+            }
+            (path, db)
+        }
+    };
+
+    let term = env::var("TERM").unwrap_or_default();
+    let colorterm = env::var("COLORTERM").unwrap_or_default();
+    let colors = match Colors::from_database(&db, &term, &colorterm) {
+        Ok(colors) => colors,
+        Err(e) => {
+            let name = if name.is_empty() { "<internal>" } else { name };
+            eprintln!("dircolors: {name}:{e}");
+            process::exit(1);
+        }
+    };
+
+    // Both shells take the value in single quotes.
+    let value = colors.to_ls_colors().replace('\'', "'\\''");
+    match config.shell {
+        Shell::Bourne => println!("LS_COLORS='{value}';\nexport LS_COLORS"),
+        Shell::C => println!("setenv LS_COLORS '{value}'"),
+    }
+}
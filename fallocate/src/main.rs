@@ -0,0 +1,365 @@
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::process;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use qoreutils_common::size::parse_size;
+
+#[cfg(target_os = "linux")]
+const FALLOC_FL_KEEP_SIZE: i32 = libc::FALLOC_FL_KEEP_SIZE;
+#[cfg(target_os = "linux")]
+const FALLOC_FL_PUNCH_HOLE: i32 = libc::FALLOC_FL_PUNCH_HOLE;
+#[cfg(target_os = "linux")]
+const FALLOC_FL_ZERO_RANGE: i32 = libc::FALLOC_FL_ZERO_RANGE;
+#[cfg(not(target_os = "linux"))]
+const FALLOC_FL_KEEP_SIZE: i32 = 0x01;
+#[cfg(not(target_os = "linux"))]
+const FALLOC_FL_PUNCH_HOLE: i32 = 0x02;
+#[cfg(not(target_os = "linux"))]
+const FALLOC_FL_ZERO_RANGE: i32 = 0x10;
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Allocate,
+    PunchHole,
+    ZeroRange,
+    DigHoles,
+}
+
+#[derive(Debug)]
+struct Config {
+    mode: Mode,
+    keep_size: bool,
+    offset: u64,
+    length: Option<u64>,
+    verbose: bool,
+    path: PathBuf,
+}
+
+fn size_arg(options: &ArgMatches, id: &str) -> Result<Option<u64>, String> {
+    options
+        .get_one::<String>(id)
+        .map(|s| parse_size(s).map_err(|e| e.to_string()))
+        .transpose()
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Result<Self, String> {
+        let mode = if options.get_flag("punch_hole") {
+            Mode::PunchHole
+        } else if options.get_flag("zero_range") {
+            Mode::ZeroRange
+        } else if options.get_flag("dig_holes") {
+            Mode::DigHoles
+        } else {
+            Mode::Allocate
+        };
+        let length = size_arg(options, "length")?;
+        if length == Some(0) {
+            return Err("invalid length value specified".to_string());
+        }
+        if length.is_none() && mode != Mode::DigHoles {
+            return Err("no length argument specified".to_string());
+        }
+        Ok(Self {
+            // Punching a hole never changes the file size.
+            keep_size: options.get_flag("keep_size") || mode == Mode::PunchHole,
+            mode,
+            offset: size_arg(options, "offset")?.unwrap_or(0),
+            length,
+            verbose: options.get_flag("verbose"),
+            path: options.get_one::<PathBuf>("file").unwrap().clone(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sys_fallocate(file: &fs::File, flags: i32, offset: u64, length: u64) -> io::Result<()> {
+    let ret = unsafe { libc::fallocate(file.as_raw_fd(), flags, offset as i64, length as i64) };
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sys_fallocate(_: &fs::File, _: i32, _: u64, _: u64) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn posix_fallocate(file: &fs::File, offset: u64, length: u64) -> io::Result<()> {
+    // posix_fallocate reports errors through its return value, not errno.
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), offset as _, length as _) } {
+        0 => Ok(()),
+        e => Err(io::Error::from_raw_os_error(e)),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn posix_fallocate(_: &fs::File, _: u64, _: u64) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+}
+
+fn is_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS)
+    )
+}
+
+fn allocate(file: &fs::File, config: &Config, flags: i32) -> io::Result<()> {
+    let length = config.length.unwrap();
+    match sys_fallocate(file, flags, config.offset, length) {
+        // Plain preallocation is the one mode POSIX has an answer for.
+        Err(e) if is_unsupported(&e) && flags == 0 => posix_fallocate(file, config.offset, length),
+        res => res,
+    }
+}
+
+/// Finds the runs of all-zero blocks between `offset` and `end` and punches
+/// them out. Returns the number of bytes punched.
+fn dig_holes(file: &mut fs::File, offset: u64, end: u64) -> io::Result<u64> {
+    let block = file.metadata()?.blksize().max(512);
+    // Holes can only be made of whole blocks.
+    let start = offset.div_ceil(block) * block;
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut buf = vec![0u8; block as usize];
+    let mut pos = start;
+    let mut run_start = None;
+    let mut punched = 0;
+    while pos + block <= end {
+        let n = read_full(file, &mut buf)?;
+        let is_zero = n == buf.len() && buf.iter().all(|&b| b == 0);
+        match (is_zero, run_start) {
+            (true, None) => run_start = Some(pos),
+            (false, Some(s)) => {
+                punch(file, s, pos)?;
+                punched += pos - s;
+                run_start = None;
+            }
+            _ => {}
+        }
+        if n < buf.len() {
+            break;
+        }
+        pos += block;
+    }
+    if let Some(s) = run_start {
+        punch(file, s, pos)?;
+        punched += pos - s;
+    }
+    Ok(punched)
+}
+
+fn read_full(file: &mut fs::File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn punch(file: &fs::File, from: u64, to: u64) -> io::Result<()> {
+    sys_fallocate(
+        file,
+        FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE,
+        from,
+        to - from,
+    )
+}
+
+fn run(config: &Config) -> Result<(), String> {
+    // The range has to end where a file offset can still reach.
+    let end = config
+        .length
+        .map(|length| {
+            config
+                .offset
+                .checked_add(length)
+                .filter(|&end| end <= i64::MAX as u64)
+                .ok_or("offset and length are too large")
+        })
+        .transpose()?;
+    let mut options = fs::OpenOptions::new();
+    options.read(true).write(true);
+    if config.mode == Mode::Allocate {
+        options.create(true);
+    }
+    let mut file = options
+        .open(&config.path)
+        .map_err(|e| format!("cannot open {}: {e}", config.path.display()))?;
+
+    let keep = if config.keep_size {
+        FALLOC_FL_KEEP_SIZE
+    } else {
+        0
+    };
+    let res = match config.mode {
+        Mode::Allocate => allocate(&file, config, keep),
+        Mode::PunchHole => allocate(&file, config, FALLOC_FL_PUNCH_HOLE | keep),
+        Mode::ZeroRange => allocate(&file, config, FALLOC_FL_ZERO_RANGE | keep),
+        Mode::DigHoles => {
+            let size = file
+                .metadata()
+                .map_err(|e| format!("{}: {e}", config.path.display()))?
+                .len();
+            let end = end.map_or(size, |end| end.min(size));
+            dig_holes(&mut file, config.offset, end).map(|punched| {
+                if config.verbose {
+                    println!("{}: {punched} B were punched", config.path.display());
+                }
+            })
+        }
+    };
+    res.map_err(|e| match is_unsupported(&e) {
+        true => {
+            "fallocate failed: operation not supported on this filesystem or platform".to_string()
+        }
+        false => format!("fallocate failed: {e}"),
+    })
+}
+
+fn main() {
+    let matches = Command::new("fallocate")
+        .arg(
+            Arg::new("length")
+                .short('l')
+                .long("length")
+                .help("Length of the range, in bytes (suffixes such as K, M, G are accepted)."),
+        )
+        .arg(
+            Arg::new("offset")
+                .short('o')
+                .long("offset")
+                .help("Offset of the range, in bytes."),
+        )
+        .arg(
+            Arg::new("keep_size")
+                .short('n')
+                .long("keep-size")
+                .action(ArgAction::SetTrue)
+                .help("Do not change the apparent size of the file."),
+        )
+        .arg(
+            Arg::new("punch_hole")
+                .short('p')
+                .long("punch-hole")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["zero_range", "dig_holes"])
+                .help("Replace the range with a hole (implies --keep-size)."),
+        )
+        .arg(
+            Arg::new("zero_range")
+                .short('z')
+                .long("zero-range")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("dig_holes")
+                .help("Zero and ensure allocation of the range."),
+        )
+        .arg(
+            Arg::new("dig_holes")
+                .short('d')
+                .long("dig-holes")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("keep_size")
+                .help("Detect zeroes and replace them with holes."),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("Report how much space --dig-holes freed."),
+        )
+        .arg(
+            Arg::new("file")
+                .required(true)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .get_matches();
+    let res = Config::from(&matches).and_then(|config| run(&config));
+    if let Err(e) = res {
+        eprintln!("fallocate: {e}");
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn config(mode: Mode, path: PathBuf, length: Option<u64>) -> Config {
+        Config {
+            keep_size: mode == Mode::PunchHole,
+            mode,
+            offset: 0,
+            length,
+            verbose: false,
+            path,
+        }
+    }
+
+    #[test]
+    fn test_allocate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        run(&config(Mode::Allocate, path.clone(), Some(1 << 20))).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 1 << 20);
+    }
+
+    #[test]
+    fn test_range_too_large() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        for mode in [Mode::Allocate, Mode::DigHoles] {
+            let config = Config {
+                offset: u64::MAX - 1,
+                ..config(mode, path.clone(), Some(2))
+            };
+            assert_eq!(run(&config).unwrap_err(), "offset and length are too large");
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_dig_holes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(b"head").unwrap();
+        file.write_all(&vec![0u8; 4 << 20]).unwrap();
+        file.write_all(b"tail").unwrap();
+        file.sync_all().unwrap();
+        let before = fs::metadata(&path).unwrap();
+
+        match run(&config(Mode::DigHoles, path.clone(), None)) {
+            Ok(()) => {}
+            // Not every filesystem the tests may run on can punch holes.
+            Err(e) if e.contains("not supported") => return,
+            Err(e) => panic!("{e}"),
+        }
+        let after = fs::metadata(&path).unwrap();
+        assert_eq!(after.len(), before.len());
+        assert!(after.blocks() < before.blocks());
+        let content = fs::read(&path).unwrap();
+        assert!(content.starts_with(b"head") && content.ends_with(b"tail"));
+        assert!(content[4..content.len() - 4].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_punch_hole_needs_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing");
+        assert!(run(&config(Mode::PunchHole, path.clone(), Some(4096))).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,286 @@
+use std::ffi::{CString, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+
+// The sysexits.h codes util-linux flock uses.
+const EX_USAGE: i32 = 64;
+const EX_NOINPUT: i32 = 66;
+const EX_OSERR: i32 = 71;
+
+// How often a lock with a timeout is retried.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+enum Target {
+    Path(PathBuf),
+    Fd(i32),
+}
+
+#[derive(Debug)]
+struct Config {
+    target: Target,
+    command: Vec<OsString>,
+    operation: i32,
+    nonblock: bool,
+    timeout: Option<Duration>,
+    conflict_exit_code: i32,
+    close_before_exec: bool,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Result<Self, String> {
+        let target = options.get_one::<OsString>("target").unwrap();
+        let mut command: Vec<OsString> = options
+            .get_many::<OsString>("command")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default();
+        if let Some(c) = options.get_one::<OsString>("shell_command") {
+            if !command.is_empty() {
+                return Err("-c takes only one argument".to_string());
+            }
+            command = vec!["/bin/sh".into(), "-c".into(), c.clone()];
+        }
+
+        // A lone number is a file descriptor the caller already opened.
+        let target = match (command.is_empty(), target.to_str().map(str::parse::<i32>)) {
+            (true, Some(Ok(fd))) => Target::Fd(fd),
+            (true, _) => return Err("requires file descriptor, file or directory".to_string()),
+            (false, _) => Target::Path(PathBuf::from(target)),
+        };
+
+        let operation = if options.get_flag("unlock") {
+            libc::LOCK_UN
+        } else if options.get_flag("shared") {
+            libc::LOCK_SH
+        } else {
+            libc::LOCK_EX
+        };
+        let timeout = match options.get_one::<f64>("timeout") {
+            Some(secs) if *secs < 0.0 || !secs.is_finite() => {
+                return Err(format!("invalid timeout value: {secs}"))
+            }
+            Some(secs) => Some(Duration::from_secs_f64(*secs)),
+            None => None,
+        };
+        Ok(Self {
+            target,
+            command,
+            operation,
+            // A zero timeout is the same as not waiting at all.
+            nonblock: options.get_flag("nonblock") || timeout == Some(Duration::ZERO),
+            timeout,
+            conflict_exit_code: *options.get_one::<i32>("conflict_exit_code").unwrap(),
+            close_before_exec: options.get_flag("close"),
+        })
+    }
+}
+
+/// Opens the lock file, creating it if needed. Directories can be locked
+/// too, but only opened read-only.
+fn open_lock_file(path: &Path) -> io::Result<i32> {
+    let cpath = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let flags = libc::O_RDONLY | libc::O_NOCTTY | libc::O_CLOEXEC;
+    let mut fd = unsafe { libc::open(cpath.as_ptr(), flags | libc::O_CREAT, 0o666) };
+    if fd < 0 && io::Error::last_os_error().raw_os_error() == Some(libc::EISDIR) {
+        fd = unsafe { libc::open(cpath.as_ptr(), flags) };
+    }
+    match fd {
+        fd if fd < 0 => Err(io::Error::last_os_error()),
+        fd => Ok(fd),
+    }
+}
+
+fn try_flock(fd: i32, operation: i32) -> io::Result<bool> {
+    match unsafe { libc::flock(fd, operation | libc::LOCK_NB) } {
+        0 => Ok(true),
+        _ => match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(false),
+            e => Err(e),
+        },
+    }
+}
+
+/// Takes the lock, retrying until the deadline when there is one. Returns
+/// false if the lock could not be had in time.
+fn lock(fd: i32, config: &Config) -> io::Result<bool> {
+    if config.nonblock {
+        return try_flock(fd, config.operation);
+    }
+    let Some(timeout) = config.timeout else {
+        return loop {
+            match unsafe { libc::flock(fd, config.operation) } {
+                0 => break Ok(true),
+                _ => match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::Interrupted => continue,
+                    e => break Err(e),
+                },
+            }
+        };
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if try_flock(fd, config.operation)? {
+            return Ok(true);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+fn run_command(fd: i32, config: &Config) -> i32 {
+    // Unless -o was given the command inherits the descriptor, and with it
+    // the lock, so that its own children keep holding it too.
+    if !config.close_before_exec {
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFD);
+            libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC);
+        }
+    }
+    let status = process::Command::new(&config.command[0])
+        .args(&config.command[1..])
+        .status();
+    match status {
+        Ok(status) => match (status.code(), status.signal()) {
+            (Some(code), _) => code,
+            (None, Some(sig)) => 128 + sig,
+            (None, None) => EX_OSERR,
+        },
+        Err(e) => {
+            eprintln!(
+                "flock: failed to execute {}: {e}",
+                config.command[0].to_string_lossy()
+            );
+            match e.kind() {
+                io::ErrorKind::NotFound => 127,
+                _ => 126,
+            }
+        }
+    }
+}
+
+fn flock(config: &Config) -> i32 {
+    let fd = match &config.target {
+        Target::Fd(fd) => *fd,
+        Target::Path(path) => match open_lock_file(path) {
+            Ok(fd) => fd,
+            Err(e) => {
+                eprintln!("flock: cannot open lock file {}: {e}", path.display());
+                return EX_NOINPUT;
+            }
+        },
+    };
+
+    match lock(fd, config) {
+        Ok(true) => {}
+        Ok(false) => return config.conflict_exit_code,
+        Err(e) => {
+            eprintln!("flock: {e}");
+            return 1;
+        }
+    }
+
+    match config.command.is_empty() {
+        true => 0,
+        false => run_command(fd, config),
+    }
+}
+
+fn main() {
+    let matches = Command::new("flock")
+        .arg(
+            Arg::new("shared")
+                .short('s')
+                .long("shared")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("exclusive")
+                .help("Get a shared lock."),
+        )
+        .arg(
+            Arg::new("exclusive")
+                .short('x')
+                .short_alias('e')
+                .long("exclusive")
+                .action(ArgAction::SetTrue)
+                .help("Get an exclusive lock (the default)."),
+        )
+        .arg(
+            Arg::new("unlock")
+                .short('u')
+                .long("unlock")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["shared", "exclusive"])
+                .help("Remove a lock."),
+        )
+        .arg(
+            Arg::new("nonblock")
+                .short('n')
+                .long("nonblock")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("timeout")
+                .help("Fail rather than wait."),
+        )
+        .arg(
+            Arg::new("timeout")
+                .short('w')
+                .long("timeout")
+                .value_name("SECS")
+                .value_parser(value_parser!(f64))
+                .help("Wait for a limited amount of time."),
+        )
+        .arg(
+            Arg::new("conflict_exit_code")
+                .short('E')
+                .long("conflict-exit-code")
+                .value_name("NUMBER")
+                .default_value("1")
+                .value_parser(value_parser!(i32).range(0..=255))
+                .help("Exit code after conflict or timeout."),
+        )
+        .arg(
+            Arg::new("close")
+                .short('o')
+                .long("close")
+                .action(ArgAction::SetTrue)
+                .help("Close file descriptor before running command."),
+        )
+        .arg(
+            Arg::new("shell_command")
+                .short('c')
+                .long("command")
+                .value_parser(value_parser!(OsString))
+                .help("Run a single command string through the shell."),
+        )
+        .arg(
+            Arg::new("target")
+                .required(true)
+                .value_name("FILE|FD")
+                .value_parser(value_parser!(OsString)),
+        )
+        .arg(
+            Arg::new("command")
+                .action(ArgAction::Append)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true)
+                .value_parser(value_parser!(OsString)),
+        )
+        .get_matches();
+    let config = match Config::from(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("flock: {e}");
+            process::exit(EX_USAGE);
+        }
+    };
+    process::exit(flock(&config));
+}
@@ -0,0 +1,125 @@
+use std::path::Path;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn qflock() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_qflock"))
+}
+
+/// Starts a qflock that holds `lock` for `secs` seconds, and waits until it
+/// has the lock.
+fn hold(lock: &Path, secs: u32) -> Child {
+    let ready = lock.with_extension("ready");
+    let child = qflock()
+        .arg(lock)
+        .arg("-c")
+        .arg(format!("touch {}; sleep {secs}", ready.display()))
+        .spawn()
+        .unwrap();
+    while !ready.exists() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    child
+}
+
+#[test]
+fn test_exclusion() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("lock");
+    let mut holder = hold(&lock, 1);
+
+    let status = qflock().arg("-n").arg(&lock).arg("true").status().unwrap();
+    assert_eq!(status.code(), Some(1));
+    let status = qflock()
+        .args(["-n", "-E", "42"])
+        .arg(&lock)
+        .arg("true")
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(42));
+
+    // A second blocking locker only gets in once the first is done.
+    let start = Instant::now();
+    let status = qflock().arg(&lock).arg("true").status().unwrap();
+    assert!(status.success());
+    assert!(holder.try_wait().unwrap().is_some());
+    assert!(start.elapsed() > Duration::from_millis(200));
+}
+
+#[test]
+fn test_shared_locks_coexist() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("lock");
+    let ready = lock.with_extension("ready");
+    let mut holder = qflock()
+        .arg("-s")
+        .arg(&lock)
+        .arg("-c")
+        .arg(format!("touch {}; sleep 1", ready.display()))
+        .spawn()
+        .unwrap();
+    while !ready.exists() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let status = qflock()
+        .args(["-s", "-n"])
+        .arg(&lock)
+        .arg("true")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = qflock()
+        .args(["-x", "-n"])
+        .arg(&lock)
+        .arg("true")
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(1));
+    holder.wait().unwrap();
+}
+
+#[test]
+fn test_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("lock");
+    let mut holder = hold(&lock, 2);
+
+    let start = Instant::now();
+    let status = qflock()
+        .args(["-w", "0.3"])
+        .arg(&lock)
+        .arg("true")
+        .status()
+        .unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(status.code(), Some(1));
+    assert!(elapsed >= Duration::from_millis(300));
+    assert!(elapsed < Duration::from_secs(2));
+    holder.wait().unwrap();
+}
+
+#[test]
+fn test_exit_status_propagates() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("lock");
+    let status = qflock().arg(&lock).args(["-c", "exit 3"]).status().unwrap();
+    assert_eq!(status.code(), Some(3));
+    let status = qflock().arg(&lock).arg("/nonexistent").status().unwrap();
+    assert_eq!(status.code(), Some(127));
+}
+
+#[test]
+fn test_fd_form() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("lock");
+    let mut holder = hold(&lock, 1);
+    let script = format!(
+        "exec 9>{}; {} -n 9",
+        lock.display(),
+        env!("CARGO_BIN_EXE_qflock")
+    );
+    let status = Command::new("sh").args(["-c", &script]).status().unwrap();
+    assert_eq!(status.code(), Some(1));
+    holder.wait().unwrap();
+}
@@ -0,0 +1,40 @@
+use std::fs;
+use std::process::{Command, Stdio};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// How many files the listed directory holds.
+const ENTRIES: usize = 100_000;
+
+fn bench_listing(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..ENTRIES {
+        fs::File::create(dir.path().join(format!("file-{i:06}"))).unwrap();
+    }
+
+    let mut group = c.benchmark_group("listing");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(ENTRIES as u64));
+    for (name, args) in [
+        ("names", &[][..]),
+        ("unsorted", &["-U"]),
+        ("long", &["-l"]),
+        ("by_size", &["-S"]),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let status = Command::new(env!("CARGO_BIN_EXE_qls"))
+                    .args(args)
+                    .arg(dir.path())
+                    .stdout(Stdio::null())
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_listing);
+criterion_main!(benches);
@@ -0,0 +1,135 @@
+//! Coloring names from LS_COLORS, as with --color.
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+use qoreutils_dircolors::Colors;
+
+/// The colors used for whatever LS_COLORS leaves out.
+const DEFAULT_COLORS: &str = "di=01;34:ln=01;36:pi=40;33:so=01;35:bd=40;33;01:cd=40;33;01:\
+    su=37;41:sg=30;43:tw=30;42:ow=34;42:st=37;44:ex=01;32";
+
+#[derive(Debug, Default)]
+pub struct Palette {
+    colors: Colors,
+    defaults: Colors,
+}
+
+impl Palette {
+    /// The palette LS_COLORS asks for, on top of the built-in one.
+    pub fn from_env() -> Self {
+        Self::new(&std::env::var("LS_COLORS").unwrap_or_default())
+    }
+
+    pub fn new(ls_colors: &str) -> Self {
+        Self {
+            colors: Colors::from_ls_colors(ls_colors),
+            defaults: Colors::from_ls_colors(DEFAULT_COLORS),
+        }
+    }
+
+    fn get(&self, code: &str) -> Option<&str> {
+        self.colors.get(code).or_else(|| self.defaults.get(code))
+    }
+
+    /// The color for a file called `name` at `path`, where `meta` is the
+    /// metadata of the file itself rather than of what it may link to.
+    pub fn for_file(&self, name: &[u8], path: &Path, meta: &fs::Metadata) -> Option<&str> {
+        let file_type = meta.file_type();
+        let mode = meta.mode();
+        let code = if file_type.is_dir() {
+            match (mode & 0o1000 != 0, mode & 0o002 != 0) {
+                (true, true) => "tw",
+                (false, true) => "ow",
+                (true, false) => "st",
+                (false, false) => "di",
+            }
+        } else if file_type.is_symlink() {
+            match fs::metadata(path).is_err() && self.get("or").is_some() {
+                true => "or",
+                false => "ln",
+            }
+        } else if file_type.is_fifo() {
+            "pi"
+        } else if file_type.is_socket() {
+            "so"
+        } else if file_type.is_block_device() {
+            "bd"
+        } else if file_type.is_char_device() {
+            "cd"
+        } else if mode & 0o4000 != 0 && self.get("su").is_some() {
+            "su"
+        } else if mode & 0o2000 != 0 && self.get("sg").is_some() {
+            "sg"
+        } else if mode & 0o111 != 0 && self.get("ex").is_some() {
+            "ex"
+        } else {
+            return self.colors.for_name(name).or_else(|| self.get("fi"));
+        };
+        self.get(code)
+    }
+
+    /// The color for the target of a link that does not exist.
+    pub fn missing(&self) -> Option<&str> {
+        self.get("mi").or_else(|| self.get("or"))
+    }
+}
+
+/// Wraps `text` in the escape sequences for `color`.
+pub fn paint(text: &[u8], color: Option<&str>) -> Vec<u8> {
+    match color {
+        Some(color) if !color.is_empty() => {
+            [format!("\x1b[{color}m").as_bytes(), text, b"\x1b[0m"].concat()
+        }
+        _ => text.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn test_for_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        fs::write(path("plain"), "").unwrap();
+        fs::write(path("archive.tar"), "").unwrap();
+        fs::write(path("run"), "").unwrap();
+        fs::set_permissions(path("run"), fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("plain", path("good")).unwrap();
+        symlink("nowhere", path("broken")).unwrap();
+
+        let palette = Palette::new("di=01;33:*.tar=01;31:or=41:bogus:ln");
+        let color = |name: &str| {
+            let meta = fs::symlink_metadata(path(name)).unwrap();
+            palette
+                .for_file(name.as_bytes(), &path(name), &meta)
+                .map(str::to_string)
+        };
+        assert_eq!(color("plain"), None);
+        assert_eq!(color("archive.tar").as_deref(), Some("01;31"));
+        assert_eq!(color("run").as_deref(), Some("01;32"));
+        assert_eq!(color("good").as_deref(), Some("01;36"));
+        assert_eq!(color("broken").as_deref(), Some("41"));
+        let meta = fs::metadata(dir.path()).unwrap();
+        assert_eq!(palette.for_file(b"", dir.path(), &meta), Some("01;33"));
+
+        // Without `or`, a broken link is still colored as a link.
+        let palette = Palette::new("");
+        let meta = fs::symlink_metadata(path("broken")).unwrap();
+        assert_eq!(
+            palette.for_file(b"broken", &path("broken"), &meta),
+            Some("01;36")
+        );
+    }
+
+    #[test]
+    fn test_paint() {
+        assert_eq!(paint(b"dir", Some("01;34")), b"\x1b[01;34mdir\x1b[0m");
+        assert_eq!(paint(b"file", Some("")), b"file");
+        assert_eq!(paint(b"file", None), b"file");
+    }
+}
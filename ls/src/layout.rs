@@ -0,0 +1,238 @@
+//! Laying names out in columns, the way `ls` does on a terminal.
+
+/// Columns are kept this far apart.
+const GUTTER: usize = 2;
+
+/// Something to lay out, with how many columns it takes up on screen,
+/// which is not its length once it holds escape sequences.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub text: Vec<u8>,
+    pub width: usize,
+}
+
+impl Cell {
+    pub fn plain(text: Vec<u8>) -> Self {
+        Self {
+            width: String::from_utf8_lossy(&text).chars().count(),
+            text,
+        }
+    }
+}
+
+/// Which way cells run through the grid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Filling each column top to bottom, as with -C.
+    Down,
+    /// Filling each row left to right, as with -x.
+    Across,
+}
+
+/// The cells of a grid, laid out in `direction`.
+struct Grid<'a> {
+    cells: &'a [Cell],
+    rows: usize,
+    columns: usize,
+    direction: Direction,
+}
+
+impl<'a> Grid<'a> {
+    /// A grid at most `columns` wide.
+    fn new(cells: &'a [Cell], columns: usize, direction: Direction) -> Self {
+        let rows = cells.len().div_ceil(columns);
+        Self {
+            cells,
+            rows,
+            // Filling columns first may leave fewer of them.
+            columns: match direction {
+                Direction::Down => cells.len().div_ceil(rows),
+                Direction::Across => columns,
+            },
+            direction,
+        }
+    }
+
+    fn get(&self, row: usize, column: usize) -> Option<&'a Cell> {
+        let i = match self.direction {
+            Direction::Down => column * self.rows + row,
+            Direction::Across => row * self.columns + column,
+        };
+        self.cells.get(i)
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        (0..self.columns)
+            .map(|column| {
+                (0..self.rows)
+                    .filter_map(|row| self.get(row, column))
+                    .map(|c| c.width)
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+}
+
+/// Whether columns this wide fit in `line_width`.
+fn fits(widths: &[usize], line_width: usize) -> bool {
+    let total: usize = widths.iter().sum::<usize>() + GUTTER * widths.len().saturating_sub(1);
+    total <= line_width
+}
+
+/// Lays `cells` out in as many columns as fit in `line_width`. A single
+/// column is used when even two do not fit.
+pub fn grid(cells: &[Cell], line_width: usize, direction: Direction) -> Vec<Vec<u8>> {
+    if cells.is_empty() {
+        return Vec::new();
+    }
+    // No more columns fit than there is room for if every cell were as
+    // narrow as the narrowest, which keeps this from trying them all.
+    let narrowest = cells.iter().map(|c| c.width).min().unwrap_or(0);
+    let most = ((line_width + GUTTER) / (narrowest + GUTTER)).clamp(1, cells.len());
+    let grid = (1..=most)
+        .rev()
+        .map(|columns| Grid::new(cells, columns, direction))
+        .find(|grid| fits(&grid.widths(), line_width))
+        .unwrap_or_else(|| Grid::new(cells, 1, direction));
+    let widths = grid.widths();
+    (0..grid.rows)
+        .map(|row| {
+            let mut line = Vec::new();
+            let in_row: Vec<(usize, &Cell)> = (0..grid.columns)
+                .filter_map(|column| Some((column, grid.get(row, column)?)))
+                .collect();
+            for (n, (column, cell)) in in_row.iter().enumerate() {
+                line.extend_from_slice(&cell.text);
+                if n + 1 < in_row.len() {
+                    let pad = widths[*column] - cell.width + GUTTER;
+                    line.extend(std::iter::repeat_n(b' ', pad));
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+/// Lays `cells` out as a list separated by commas, starting a new line
+/// rather than going past `line_width`, which needs room for the comma too.
+pub fn commas(cells: &[Cell], line_width: usize) -> Vec<Vec<u8>> {
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    let mut pos = 0;
+    for (i, cell) in cells.iter().enumerate() {
+        if i > 0 {
+            line.push(b',');
+            if pos + cell.width + 2 < line_width {
+                line.push(b' ');
+                pos += 2;
+            } else {
+                lines.push(std::mem::take(&mut line));
+                pos = 0;
+            }
+        }
+        line.extend_from_slice(&cell.text);
+        pos += cell.width;
+    }
+    if !cells.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(names: &[&str]) -> Vec<Cell> {
+        names
+            .iter()
+            .map(|n| Cell::plain(n.as_bytes().to_vec()))
+            .collect()
+    }
+
+    fn strings(lines: Vec<Vec<u8>>) -> Vec<String> {
+        lines
+            .into_iter()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect()
+    }
+
+    fn columns(cells: &[Cell], line_width: usize) -> Vec<String> {
+        strings(grid(cells, line_width, Direction::Down))
+    }
+
+    fn across(cells: &[Cell], line_width: usize) -> Vec<String> {
+        strings(grid(cells, line_width, Direction::Across))
+    }
+
+    fn commas(cells: &[Cell], line_width: usize) -> Vec<String> {
+        strings(super::commas(cells, line_width))
+    }
+
+    #[test]
+    fn test_columns() {
+        let names = cells(&["a", "bbbbbb", "cc", "ddd", "e", "ffff", "g"]);
+        assert_eq!(
+            columns(&names, 20),
+            ["a       cc   e     g", "bbbbbb  ddd  ffff"]
+        );
+        assert_eq!(columns(&names, 80), ["a  bbbbbb  cc  ddd  e  ffff  g"]);
+        // Exactly filling the line is fine.
+        assert_eq!(columns(&names, 30), ["a  bbbbbb  cc  ddd  e  ffff  g"]);
+        assert_eq!(columns(&names, 29).len(), 2);
+        assert_eq!(
+            columns(&names, 19),
+            ["a       ddd   g", "bbbbbb  e", "cc      ffff"]
+        );
+    }
+
+    #[test]
+    fn test_across() {
+        let names = cells(&["a", "bbbbbb", "cc", "ddd", "e", "ffff", "g"]);
+        assert_eq!(across(&names, 20), ["a  bbbbbb  cc  ddd", "e  ffff    g"]);
+        assert_eq!(
+            across(&names, 17),
+            ["a    bbbbbb  cc", "ddd  e       ffff", "g"]
+        );
+        assert_eq!(across(&names, 80), columns(&names, 80));
+
+        // With names all the same width, only the order differs from -C.
+        let names = cells(&["aa", "bb", "cc", "dd", "ee"]);
+        assert_eq!(across(&names, 10), ["aa  bb  cc", "dd  ee"]);
+        assert_eq!(columns(&names, 10), ["aa  cc  ee", "bb  dd"]);
+    }
+
+    #[test]
+    fn test_narrow_terminal() {
+        let names = cells(&["a-long-name", "another-long-one"]);
+        assert_eq!(columns(&names, 10), ["a-long-name", "another-long-one"]);
+        assert_eq!(across(&names, 10), ["a-long-name", "another-long-one"]);
+        assert_eq!(columns(&[], 80), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_width_is_not_length() {
+        let names = vec![
+            Cell {
+                text: b"\x1b[01;34mdir\x1b[0m".to_vec(),
+                width: 3,
+            },
+            Cell::plain(b"file".to_vec()),
+        ];
+        assert_eq!(columns(&names, 9), ["\x1b[01;34mdir\x1b[0m  file"]);
+    }
+
+    #[test]
+    fn test_commas() {
+        let names = cells(&["alpha", "beta", "gamma", "delta", "epsilon", "z"]);
+        assert_eq!(
+            commas(&names, 20),
+            ["alpha, beta, gamma,", "delta, epsilon, z"]
+        );
+        // A name that does not fit anywhere gets a line to itself.
+        let names = cells(&["a", "a-very-long-name-indeed", "b"]);
+        assert_eq!(commas(&names, 20), ["a,", "a-very-long-name-indeed,", "b"]);
+        assert_eq!(commas(&[], 20), Vec::<String>::new());
+    }
+}
@@ -1,20 +1,1056 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::env;
+use std::ffi::{CString, OsStr, OsString};
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::ops::ControlFlow;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
 
-use clap::{Arg, ArgAction, ArgMatches, Command};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use clap::parser::ValueSource;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use qoreutils_common::error::strerror;
+use qoreutils_common::glob::glob_match;
+use qoreutils_common::mode::mode_string;
+use qoreutils_common::size::BlockSize;
+use qoreutils_common::strftime;
+use qoreutils_common::users::{group_name, user_name};
 
-#[derive(Debug)]
+use color::Palette;
+use layout::{Cell, Direction};
+
+mod color;
+mod layout;
+mod quote;
+
+/// The unit -s and totals count in by default.
+static KIB: BlockSize = BlockSize::new(1024);
+
+/// Times further back than this, or in the future, show the year instead of
+/// the time of day.
+const SIX_MONTHS: Duration = Duration::from_secs(31_556_952 / 2);
+
+/// Which of a file's timestamps to show and sort by.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum TimeField {
+    #[default]
+    Modified,
+    /// As with -u.
+    Accessed,
+    /// The inode change time, as with -c.
+    Changed,
+    /// When the file was created, which not every file system records.
+    Birth,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Sort {
+    #[default]
+    Name,
+    /// The order the directory holds entries in, as with --sort=none.
+    None,
+    /// Newest first, as with -t.
+    Time,
+    /// Largest first, as with -S.
+    Size,
+}
+
+/// How to print names outside of the long format.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Format {
+    #[default]
+    OneLine,
+    /// Filling each column top to bottom, as with -C.
+    Columns,
+    /// Filling each row left to right, as with -x.
+    Across,
+    /// Separated by commas, as with -m.
+    Commas,
+}
+
+/// Which characters to put after names to tell what kind of file they are.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Indicator {
+    #[default]
+    None,
+    /// Only `/` for directories, as with -p.
+    Slash,
+    /// Everything but `*` for executables, as with --file-type.
+    FileType,
+    /// As with -F.
+    Classify,
+}
+
+/// Which symlinks to show the targets of rather than the links themselves.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Dereference {
+    #[default]
+    None,
+    /// Operands that point to directories, unless the links themselves are
+    /// what the output is about, as with -l or -F.
+    DirOperands,
+    /// Operands, as with -H.
+    Operands,
+    /// Everything, as with -L.
+    All,
+}
+
+/// Whether to follow a particular symlink.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Follow {
+    Never,
+    /// Only if it points to a directory.
+    Dirs,
+    Always,
+}
+
+/// How the long format shows times: a strftime format for times within the
+/// last six months, and one for the rest.
+#[derive(Debug, Clone, PartialEq)]
+struct TimeStyle {
+    recent: String,
+    older: String,
+}
+
+impl Default for TimeStyle {
+    fn default() -> Self {
+        Self::new("%b %e %H:%M", "%b %e  %Y")
+    }
+}
+
+impl TimeStyle {
+    fn new(recent: &str, older: &str) -> Self {
+        Self {
+            recent: recent.to_string(),
+            older: older.to_string(),
+        }
+    }
+
+    /// Parses the argument to --time-style. A `+FORMAT` may hold a second
+    /// format for recent times after a newline.
+    fn parse(style: &str) -> Result<Self, String> {
+        let parsed = match style {
+            "full-iso" => Self::new("%Y-%m-%d %H:%M:%S.%f %z", "%Y-%m-%d %H:%M:%S.%f %z"),
+            "long-iso" => Self::new("%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M"),
+            "iso" => Self::new("%m-%d %H:%M", "%Y-%m-%d "),
+            "locale" => Self::default(),
+            _ => match style.strip_prefix('+') {
+                Some(format) => match format.split_once('\n') {
+                    Some((older, recent)) => Self::new(recent, older),
+                    None => Self::new(format, format),
+                },
+                None => return Err(format!("invalid argument '{style}' for '--time-style'")),
+            },
+        };
+        if !strftime::is_valid(&parsed.recent) || !strftime::is_valid(&parsed.older) {
+            return Err(format!("invalid time style format '{style}'"));
+        }
+        Ok(parsed)
+    }
+}
+
+#[derive(Debug, Default)]
 struct Config {
     include_dot_files: bool,
+    /// List `.` and `..` as well, as with -a but not -A.
+    dot_entries: bool,
+    /// Globs for names to leave out, from --ignore, and from --hide unless
+    /// -a or -A says to show everything.
+    ignore: Vec<String>,
+    long: bool,
+    /// Show owners and groups as numbers, as with -n.
+    numeric_ids: bool,
+    /// Leave the owner out of the long format, as with -g.
+    no_owner: bool,
+    /// Leave the group out of the long format, as with -o.
+    no_group: bool,
+    /// Show how much space each file takes up, as with -s.
+    size: bool,
+    /// The unit for -s and totals, if not 1K blocks.
+    block_size: Option<BlockSize>,
+    /// The unit for sizes in the long format, if not bytes.
+    file_block_size: Option<BlockSize>,
+    recursive: bool,
+    sort: Sort,
+    /// Whether names compare regardless of case, as with --fold-case.
+    fold_case: bool,
+    reverse: bool,
+    group_directories_first: bool,
+    time: TimeField,
+    time_style: TimeStyle,
+    format: Format,
+    indicator: Indicator,
+    dereference: Dereference,
+    /// The colors to show names in, if any.
+    colors: Option<Palette>,
+    quoting: quote::Style,
+    /// Show unprintable characters as `?` where the quoting style leaves
+    /// them be, as with -q.
+    hide_control: bool,
+    /// How wide a line may get when laying out columns.
+    width: usize,
+    /// Whether lines end in NUL rather than newline, as with --zero.
+    zero: bool,
 }
 
 impl Config {
-    pub fn from(options: &ArgMatches) -> Self {
+    pub fn from(options: &ArgMatches) -> Result<Self, String> {
+        let numeric_ids = options.get_flag("numeric_ids");
+        // Each format option overrides the ones before it.
+        let last_format = [
+            "long",
+            "numeric_ids",
+            "no_owner",
+            "no_group",
+            "full_time",
+            "one_per_line",
+            "columns",
+            "across",
+            "commas",
+        ]
+        .into_iter()
+        .filter(|id| options.get_flag(id))
+        .max_by_key(|id| options.index_of(id));
+        let long = matches!(
+            last_format,
+            Some("long" | "numeric_ids" | "no_owner" | "no_group" | "full_time")
+        );
+        // Likewise for the sort options, and for the time ones.
+        let last_sort = ["sort_time", "sort_size", "unsorted", "all_unsorted", "sort"]
+            .into_iter()
+            .filter(|id| options.value_source(id) == Some(ValueSource::CommandLine))
+            .max_by_key(|id| options.index_of(id));
+        let last_time = ["atime", "ctime", "time"]
+            .into_iter()
+            .filter(|id| options.value_source(id) == Some(ValueSource::CommandLine))
+            .max_by_key(|id| options.index_of(id));
+        let terminal = io::stdout().is_terminal();
+        let last_quoting = ["escape", "literal", "quoting_style"]
+            .into_iter()
+            .filter(|id| options.value_source(id) == Some(ValueSource::CommandLine))
+            .max_by_key(|id| options.index_of(id));
+        // Output meant for other programs is left as it is.
+        let zero = options.get_flag("zero");
+        let last_indicator = ["classify", "file_type", "slash", "indicator_style"]
+            .into_iter()
+            .filter(|id| options.value_source(id) == Some(ValueSource::CommandLine))
+            .max_by_key(|id| options.index_of(id));
+        let indicator = match last_indicator {
+            Some("classify") => Indicator::Classify,
+            Some("file_type") => Indicator::FileType,
+            Some("slash") => Indicator::Slash,
+            Some(_) => match options
+                .get_one::<String>("indicator_style")
+                .map(String::as_str)
+            {
+                Some("classify") => Indicator::Classify,
+                Some("file-type") => Indicator::FileType,
+                Some("slash") => Indicator::Slash,
+                _ => Indicator::None,
+            },
+            None => Indicator::None,
+        };
+        // -f is -aU without colors.
+        let all_unsorted = options.get_flag("all_unsorted");
+        let dot_entries = options.get_flag("include_dot_files") || all_unsorted;
+        let include_dot_files = dot_entries || options.get_flag("almost_all");
+        let patterns = |id| {
+            options
+                .get_many::<String>(id)
+                .into_iter()
+                .flatten()
+                .cloned()
+        };
+        let mut ignore: Vec<String> = patterns("ignore").collect();
+        if !include_dot_files {
+            ignore.extend(patterns("hide"));
+        }
+        // --block-size is for every size, and takes over from the
+        // environment, whereas -k is only for -s and totals.
+        let file_block_size = match options.get_one::<String>("block_size") {
+            Some(spec) => Some(
+                BlockSize::parse(spec)
+                    .map_err(|_| format!("invalid --block-size argument '{spec}'"))?,
+            ),
+            None => ["LS_BLOCK_SIZE", "BLOCK_SIZE"]
+                .into_iter()
+                .find_map(|var| env::var(var).ok())
+                .and_then(|spec| BlockSize::parse(&spec).ok()),
+        };
+        let kibibytes = options.get_flag("kibibytes")
+            && options.index_of("kibibytes") > options.index_of("block_size");
+        let mut config = Self {
+            include_dot_files,
+            dot_entries,
+            ignore,
+            long,
+            numeric_ids,
+            no_owner: options.get_flag("no_owner"),
+            no_group: options.get_flag("no_group"),
+            size: options.get_flag("size"),
+            block_size: match kibibytes {
+                true => None,
+                false => file_block_size.clone(),
+            },
+            file_block_size,
+            recursive: options.get_flag("recursive"),
+            sort: match last_sort {
+                Some("sort_time") => Sort::Time,
+                Some("sort_size") => Sort::Size,
+                Some("unsorted" | "all_unsorted") => Sort::None,
+                Some(_) => match options.get_one::<String>("sort").map(String::as_str) {
+                    Some("none") => Sort::None,
+                    Some("time") => Sort::Time,
+                    Some("size") => Sort::Size,
+                    _ => Sort::Name,
+                },
+                None => Sort::Name,
+            },
+            fold_case: options.get_flag("fold_case"),
+            reverse: options.get_flag("reverse"),
+            group_directories_first: options.get_flag("group_directories_first"),
+            time: match last_time {
+                Some("atime") => TimeField::Accessed,
+                Some("ctime") => TimeField::Changed,
+                Some(_) => match options.get_one::<String>("time").map(String::as_str) {
+                    Some("atime" | "access" | "use") => TimeField::Accessed,
+                    Some("ctime" | "status") => TimeField::Changed,
+                    Some("birth" | "creation") => TimeField::Birth,
+                    _ => TimeField::Modified,
+                },
+                None => TimeField::Modified,
+            },
+            // --full-time is --time-style=full-iso, unless one comes after it.
+            time_style: match options.get_one::<String>("time_style") {
+                _ if options.get_flag("full_time")
+                    && options.index_of("full_time") > options.index_of("time_style") =>
+                {
+                    TimeStyle::parse("full-iso")?
+                }
+                Some(style) => TimeStyle::parse(style)?,
+                None => TimeStyle::default(),
+            },
+            format: match last_format {
+                _ if zero => Format::OneLine,
+                Some("columns") => Format::Columns,
+                Some("across") => Format::Across,
+                Some("commas") => Format::Commas,
+                None if terminal => Format::Columns,
+                _ => Format::OneLine,
+            },
+            indicator,
+            dereference: if options.get_flag("dereference") {
+                Dereference::All
+            } else if options.get_flag("dereference_command_line") {
+                Dereference::Operands
+            } else if long || matches!(indicator, Indicator::FileType | Indicator::Classify) {
+                Dereference::None
+            } else {
+                Dereference::DirOperands
+            },
+            colors: match options.get_one::<String>("color").map(String::as_str) {
+                _ if zero || all_unsorted => None,
+                Some("always" | "yes" | "force") => Some(Palette::from_env()),
+                Some("auto" | "tty" | "if-tty") if terminal => Some(Palette::from_env()),
+                _ => None,
+            },
+            quoting: match last_quoting {
+                _ if zero => quote::Style::Literal,
+                Some("escape") => quote::Style::Escape,
+                Some("literal") => quote::Style::Literal,
+                Some(_) => match options
+                    .get_one::<String>("quoting_style")
+                    .map(String::as_str)
+                {
+                    Some("shell") => quote::Style::Shell,
+                    Some("shell-always") => quote::Style::ShellAlways,
+                    Some("shell-escape") => quote::Style::ShellEscape,
+                    Some("c") => quote::Style::C,
+                    Some("escape") => quote::Style::Escape,
+                    _ => quote::Style::Literal,
+                },
+                None if terminal => quote::Style::ShellEscape,
+                None => quote::Style::Literal,
+            },
+            hide_control: !zero && (options.get_flag("hide_control_chars") || terminal),
+            width: terminal_width(),
+            zero,
+        };
+        // Columns need every name first, which streaming does without.
+        if config.streams() {
+            config.format = Format::OneLine;
+        }
+        Ok(config)
+    }
+}
+
+impl Config {
+    /// Whether entries can be written out as they are read, which only
+    /// works when there is nothing to sort and nothing to line up.
+    fn streams(&self) -> bool {
+        self.sort == Sort::None
+            && !self.long
+            && !self.size
+            && !self.group_directories_first
+            && self.format != Format::Commas
+    }
+
+    fn block_size(&self) -> &BlockSize {
+        self.block_size.as_ref().unwrap_or(&KIB)
+    }
+
+    /// The byte each line of output ends in.
+    fn eol(&self) -> u8 {
+        match self.zero {
+            true => b'\0',
+            false => b'\n',
+        }
+    }
+
+    /// Whether a directory entry called `name` is left out of listings.
+    fn hidden(&self, name: &OsStr) -> bool {
+        let name = name.as_bytes();
+        (name.starts_with(b".") && !self.include_dot_files)
+            || self.ignore.iter().any(|p| glob_match(p.as_bytes(), name))
+    }
+}
+
+/// The width of the terminal on stdout, or failing that $COLUMNS, or 80.
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_col > 0
+    {
+        return size.ws_col as usize;
+    }
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|&c| c > 0)
+        .unwrap_or(80)
+}
+
+/// A directory entry, with the type and metadata of the entry itself rather
+/// than of what it may link to, unless it was followed.
+struct Entry {
+    name: OsString,
+    path: PathBuf,
+    file_type: fs::FileType,
+    /// Only read when `needs_metadata` says so.
+    meta: Option<fs::Metadata>,
+}
+
+impl Entry {
+    fn new(name: OsString, path: PathBuf, meta: fs::Metadata) -> Self {
         Self {
-            include_dot_files: options.get_flag("include_dot_files"),
+            name,
+            path,
+            file_type: meta.file_type(),
+            meta: Some(meta),
         }
     }
+
+    fn meta(&self) -> &fs::Metadata {
+        self.meta.as_ref().expect("metadata was not read")
+    }
+
+    /// The time in `field`, if the file has one.
+    fn time(&self, field: TimeField) -> Option<SystemTime> {
+        let (secs, nsecs) = match field {
+            TimeField::Modified => (self.meta().mtime(), self.meta().mtime_nsec()),
+            TimeField::Accessed => (self.meta().atime(), self.meta().atime_nsec()),
+            TimeField::Changed => (self.meta().ctime(), self.meta().ctime_nsec()),
+            TimeField::Birth => return self.meta().created().ok(),
+        };
+        let since_epoch = Duration::new(secs.unsigned_abs(), nsecs as u32);
+        Some(match secs < 0 {
+            true => SystemTime::UNIX_EPOCH - since_epoch,
+            false => SystemTime::UNIX_EPOCH + since_epoch,
+        })
+    }
+
+    /// The time -t sorts by, which is the modification time for files
+    /// without the one asked for.
+    fn sort_time(&self, field: TimeField) -> SystemTime {
+        self.time(field)
+            .or_else(|| self.time(TimeField::Modified))
+            .expect("every file has a modification time")
+    }
+}
+
+/// The character -F, --file-type or -p puts after a name, if any. The mode
+/// is only looked at for -F.
+fn indicator(file_type: fs::FileType, mode: u32, style: Indicator) -> Option<char> {
+    if style == Indicator::None {
+        None
+    } else if file_type.is_dir() {
+        Some('/')
+    } else if style == Indicator::Slash {
+        None
+    } else if file_type.is_symlink() {
+        Some('@')
+    } else if file_type.is_fifo() {
+        Some('|')
+    } else if file_type.is_socket() {
+        Some('=')
+    } else if style == Indicator::Classify && file_type.is_file() && mode & 0o111 != 0 {
+        Some('*')
+    } else {
+        None
+    }
+}
+
+/// A name the way it is written out.
+fn shown_name(name: &OsStr, config: &Config) -> Vec<u8> {
+    quote::quote(name.as_bytes(), config.quoting, config.hide_control)
+}
+
+/// An entry's name the way it is shown, with anything that goes with it.
+fn name_cell(entry: &Entry, config: &Config) -> Cell {
+    let mut cell = Cell::plain(shown_name(&entry.name, config));
+    if let Some(colors) = &config.colors {
+        let color = colors.for_file(entry.name.as_bytes(), &entry.path, entry.meta());
+        cell.text = color::paint(&cell.text, color);
+    }
+    if config.indicator == Indicator::None || (config.long && entry.file_type.is_symlink()) {
+        // The target gets the indicator instead.
+        return cell;
+    }
+    // Without metadata there is only the file type to go on, which is all
+    // -p needs.
+    let mode = entry.meta.as_ref().map_or(0, |meta| meta.mode());
+    if let Some(c) = indicator(entry.file_type, mode, config.indicator) {
+        cell.text.push(c as u8);
+        cell.width += 1;
+    }
+    cell
+}
+
+/// The ` -> target` that follows a symlink in the long format.
+fn link_target(entry: &Entry, config: &Config) -> Vec<u8> {
+    let Ok(target) = fs::read_link(&entry.path) else {
+        return Vec::new();
+    };
+    let mut text = shown_name(target.as_os_str(), config);
+    let meta = fs::metadata(&entry.path);
+    if let Some(colors) = &config.colors {
+        let color = match &meta {
+            Ok(meta) => colors.for_file(target.as_os_str().as_bytes(), &entry.path, meta),
+            Err(_) => colors.missing(),
+        };
+        text = color::paint(&text, color);
+    }
+    if let Ok(meta) = &meta {
+        let c = indicator(meta.file_type(), meta.mode(), config.indicator);
+        text.extend(c.map(|c| c as u8));
+    }
+    [b" -> ".as_slice(), &text].concat()
+}
+
+/// The names of `entries` the way they are shown outside the long format.
+fn name_cells(entries: &[Entry], config: &Config) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = entries.iter().map(|e| name_cell(e, config)).collect();
+    if config.size {
+        for (cell, size) in cells.iter_mut().zip(block_column(entries, config)) {
+            cell.width += size.len();
+            cell.text.splice(0..0, size.into_bytes());
+        }
+    }
+    cells
+}
+
+/// Whether listing with `config` needs more than the names and types of
+/// entries, which is all a directory holds. Looking up the rest costs a
+/// system call per entry.
+fn needs_metadata(config: &Config) -> bool {
+    config.long
+        || config.size
+        || matches!(config.sort, Sort::Time | Sort::Size)
+        || !matches!(config.indicator, Indicator::None | Indicator::Slash)
+        || config.colors.is_some()
+        || config.dereference == Dereference::All
+}
+
+/// Compares names byte by byte, or with `fold_case` regardless of case
+/// first, falling back to the bytes so that the order is always the same.
+fn compare_names(a: &OsStr, b: &OsStr, fold_case: bool) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if !fold_case {
+        return a.cmp(b);
+    }
+    let folded = match (std::str::from_utf8(a), std::str::from_utf8(b)) {
+        (Ok(a), Ok(b)) => a
+            .chars()
+            .flat_map(char::to_lowercase)
+            .cmp(b.chars().flat_map(char::to_lowercase)),
+        _ => a
+            .iter()
+            .map(u8::to_ascii_lowercase)
+            .cmp(b.iter().map(u8::to_ascii_lowercase)),
+    };
+    folded.then_with(|| a.cmp(b))
+}
+
+/// The order entries are listed in.
+fn compare(a: &Entry, b: &Entry, config: &Config) -> Ordering {
+    let by_name = || compare_names(&a.name, &b.name, config.fold_case);
+    let order = match config.sort {
+        Sort::Name => by_name(),
+        Sort::None => Ordering::Equal,
+        Sort::Time => b
+            .sort_time(config.time)
+            .cmp(&a.sort_time(config.time))
+            .then_with(by_name),
+        Sort::Size => b.meta().len().cmp(&a.meta().len()).then_with(by_name),
+    };
+    let order = match config.reverse {
+        true => order.reverse(),
+        false => order,
+    };
+    match config.group_directories_first {
+        true => b.file_type.is_dir().cmp(&a.file_type.is_dir()).then(order),
+        false => order,
+    }
+}
+
+/// The space a file takes up on disk, in bytes.
+fn allocated(meta: &fs::Metadata) -> u64 {
+    meta.blocks() * 512
+}
+
+/// The `total` of `ls -l` and `ls -s`: the space the entries take up.
+fn total_blocks(entries: &[Entry], config: &Config) -> String {
+    let bytes = entries.iter().map(|e| allocated(e.meta())).sum();
+    config.block_size().format(bytes)
+}
+
+/// The -s column for each entry, all as wide as the widest.
+fn block_column(entries: &[Entry], config: &Config) -> Vec<String> {
+    let sizes: Vec<String> = entries
+        .iter()
+        .map(|e| config.block_size().format(allocated(e.meta())))
+        .collect();
+    let width = sizes.iter().map(String::len).max().unwrap_or(0);
+    sizes.iter().map(|s| format!("{s:>width$} ")).collect()
+}
+
+/// Renders a time in `tz` the way `ls -l` does, by default "Mmm dd HH:MM"
+/// within the last six months and "Mmm dd  yyyy" otherwise.
+fn format_time<Tz: TimeZone>(
+    time: SystemTime,
+    now: SystemTime,
+    style: &TimeStyle,
+    tz: &Tz,
+) -> String
+where
+    Tz::Offset: fmt::Display,
+{
+    let recent = now.duration_since(time).is_ok_and(|age| age < SIX_MONTHS);
+    let local = DateTime::<Utc>::from(time).with_timezone(tz);
+    match recent {
+        true => local.format(&style.recent).to_string(),
+        false => local.format(&style.older).to_string(),
+    }
+}
+
+/// The size column, which for devices holds their major and minor numbers.
+fn format_size(meta: &fs::Metadata, config: &Config) -> String {
+    let file_type = meta.file_type();
+    match file_type.is_block_device() || file_type.is_char_device() {
+        true => {
+            let rdev = meta.rdev();
+            format!("{}, {}", libc::major(rdev), libc::minor(rdev))
+        }
+        false => match &config.file_block_size {
+            Some(block_size) => block_size.format(meta.len()),
+            None => meta.len().to_string(),
+        },
+    }
+}
+
+/// Whether the file at `path` has an access control list beyond what its
+/// mode says, for the `+` after the mode in the long format. Any trouble
+/// finding out, such as a file system without extended attributes, counts
+/// as no.
+#[cfg(target_os = "linux")]
+fn has_acl(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    [c"system.posix_acl_access", c"system.posix_acl_default"]
+        .iter()
+        .any(|name| unsafe {
+            libc::getxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) > 0
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn has_acl(_: &Path) -> bool {
+    false
+}
+
+/// Renders entries in the long format, with every column as wide as its
+/// widest value.
+fn long_lines(entries: &[Entry], config: &Config, now: SystemTime) -> Vec<Vec<u8>> {
+    let mut users = HashMap::new();
+    let mut groups = HashMap::new();
+    // Which columns come before the name, and which of those line up on the
+    // right rather than the left.
+    let right: Vec<bool> = [
+        Some(false),
+        Some(true),
+        (!config.no_owner).then_some(false),
+        (!config.no_group).then_some(false),
+        Some(true),
+        Some(false),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|e| {
+            let meta = e.meta();
+            let mut mode = mode_string(meta.mode());
+            if !meta.file_type().is_symlink() && has_acl(&e.path) {
+                mode.push('+');
+            }
+            let mut row = vec![mode, meta.nlink().to_string()];
+            if !config.no_owner {
+                let uid = meta.uid();
+                let user = users
+                    .entry(uid)
+                    .or_insert_with(|| match config.numeric_ids {
+                        true => uid.to_string(),
+                        false => user_name(uid).unwrap_or_else(|| uid.to_string()),
+                    });
+                row.push(user.clone());
+            }
+            if !config.no_group {
+                let gid = meta.gid();
+                let group = groups
+                    .entry(gid)
+                    .or_insert_with(|| match config.numeric_ids {
+                        true => gid.to_string(),
+                        false => group_name(gid).unwrap_or_else(|| gid.to_string()),
+                    });
+                row.push(group.clone());
+            }
+            row.push(format_size(meta, config));
+            row.push(match e.time(config.time) {
+                Some(time) => format_time(time, now, &config.time_style, &Local),
+                None => "?".to_string(),
+            });
+            row
+        })
+        .collect();
+    let sizes = match config.size {
+        true => block_column(entries, config),
+        false => vec![String::new(); entries.len()],
+    };
+    let mut widths = vec![0; right.len()];
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+    rows.iter()
+        .zip(entries)
+        .zip(sizes)
+        .map(|((row, e), blocks)| {
+            let mut line = blocks;
+            for ((column, &width), &right) in row.iter().zip(&widths).zip(&right) {
+                match right {
+                    true => line.push_str(&format!("{column:>width$} ")),
+                    false => line.push_str(&format!("{column:<width$} ")),
+                }
+            }
+            let mut line = line.into_bytes();
+            line.extend(name_cell(e, config).text);
+            if e.file_type.is_symlink() {
+                line.extend(link_target(e, config));
+            }
+            line
+        })
+        .collect()
+}
+
+/// Where the listing goes, and how it is going.
+struct Lister<'a, W: Write> {
+    config: &'a Config,
+    out: W,
+    now: SystemTime,
+    /// The (dev, ino) of every directory being listed under -R, which may
+    /// loop once symlinks are followed.
+    ancestors: Vec<(u64, u64)>,
+    /// The exit status so far: 1 after minor trouble, 2 after serious.
+    status: i32,
+}
+
+impl<W: Write> Lister<'_, W> {
+    /// Notes that something went wrong. Only trouble with what was asked for
+    /// on the command line is serious.
+    fn fail(&mut self, serious: bool) {
+        self.status = self.status.max(if serious { 2 } else { 1 });
+    }
+
+    /// Looks up a file, following it if it is a symlink and `follow` says
+    /// so. A link that cannot be followed is reported and listed as itself.
+    fn entry(&mut self, name: OsString, path: PathBuf, follow: Follow) -> io::Result<Entry> {
+        let mut meta = fs::symlink_metadata(&path)?;
+        if meta.is_symlink() && follow != Follow::Never {
+            match fs::metadata(&path) {
+                Ok(target) if follow == Follow::Always || target.is_dir() => meta = target,
+                Ok(_) => {}
+                Err(e) if follow == Follow::Always => {
+                    eprintln!("ls: cannot access '{}': {}", path.display(), strerror(&e));
+                    self.fail(false);
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(Entry::new(name, path, meta))
+    }
+
+    /// Reads the entries of `dir` worth listing, in the order the directory
+    /// holds them, handing each to `each` until it breaks off. An entry that
+    /// cannot be looked up, such as one removed since it was read, is
+    /// reported and left out.
+    fn read_dir<B>(
+        &mut self,
+        dir: &Path,
+        mut each: impl FnMut(&mut Self, Entry) -> ControlFlow<B>,
+    ) -> io::Result<ControlFlow<B>> {
+        let config = self.config;
+        let follow = match config.dereference {
+            Dereference::All => Follow::Always,
+            _ => Follow::Never,
+        };
+        if config.dot_entries {
+            // read_dir leaves these out.
+            for (name, path) in [(".", dir.to_path_buf()), ("..", dir.join(".."))] {
+                if config.hidden(name.as_ref()) {
+                    continue;
+                }
+                let meta = fs::metadata(&path)?;
+                if let ControlFlow::Break(b) = each(self, Entry::new(name.into(), path, meta)) {
+                    return Ok(ControlFlow::Break(b));
+                }
+            }
+        }
+        for dirent in fs::read_dir(dir)? {
+            let dirent = match dirent {
+                Ok(dirent) => dirent,
+                Err(e) => {
+                    eprintln!(
+                        "ls: reading directory '{}': {}",
+                        dir.display(),
+                        strerror(&e)
+                    );
+                    self.fail(false);
+                    break;
+                }
+            };
+            let name = dirent.file_name();
+            if config.hidden(&name) {
+                continue;
+            }
+            let path = dirent.path();
+            let entry = match needs_metadata(config) {
+                true => self.entry(name, path.clone(), follow),
+                false => dirent.file_type().map(|file_type| Entry {
+                    file_type,
+                    path: path.clone(),
+                    name,
+                    meta: None,
+                }),
+            };
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("ls: cannot access '{}': {}", path.display(), strerror(&e));
+                    self.fail(false);
+                    continue;
+                }
+            };
+            if let ControlFlow::Break(b) = each(self, entry) {
+                return Ok(ControlFlow::Break(b));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Reads the entries of `dir` worth listing, in the order they are listed.
+    fn list_dir(&mut self, dir: &Path) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let ControlFlow::Continue(()) = self.read_dir(dir, |_, entry| {
+            entries.push(entry);
+            ControlFlow::<Infallible>::Continue(())
+        })?;
+        entries.sort_by(|a, b| compare(a, b, self.config));
+        Ok(entries)
+    }
+
+    /// Writes out the entries of `dir` as they are read, returning the
+    /// subdirectories among them for -R. A write error breaks off.
+    fn stream_dir(&mut self, dir: &Path) -> io::Result<ControlFlow<io::Error, Vec<Entry>>> {
+        let mut subdirs = Vec::new();
+        let read = self.read_dir(dir, |lister, entry| {
+            let written = lister
+                .out
+                .write_all(&name_cell(&entry, lister.config).text)
+                .and_then(|()| lister.end_line());
+            if let Err(e) = written {
+                return ControlFlow::Break(e);
+            }
+            if lister.config.recursive && entry.file_type.is_dir() {
+                subdirs.push(entry);
+            }
+            ControlFlow::Continue(())
+        })?;
+        Ok(match read {
+            ControlFlow::Continue(()) => ControlFlow::Continue(subdirs),
+            ControlFlow::Break(e) => ControlFlow::Break(e),
+        })
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        self.out.write_all(&[self.config.eol()])
+    }
+
+    fn print_entries(&mut self, entries: &[Entry], total: bool) -> io::Result<()> {
+        let config = self.config;
+        if total && (config.long || config.size) {
+            write!(self.out, "total {}", total_blocks(entries, config))?;
+            self.end_line()?;
+        }
+        let lines = if config.long {
+            long_lines(entries, config, self.now)
+        } else {
+            let cells = name_cells(entries, config);
+            match config.format {
+                Format::OneLine => cells.into_iter().map(|cell| cell.text).collect(),
+                Format::Columns => layout::grid(&cells, config.width, Direction::Down),
+                Format::Across => layout::grid(&cells, config.width, Direction::Across),
+                Format::Commas => layout::commas(&cells, config.width),
+            }
+        };
+        for line in lines {
+            self.out.write_all(&line)?;
+            self.end_line()?;
+        }
+        Ok(())
+    }
+
+    /// Starts the section for a directory.
+    fn print_header(&mut self, dir: &Path) -> io::Result<()> {
+        self.out
+            .write_all(&shown_name(dir.as_os_str(), self.config))?;
+        self.out.write_all(b":")?;
+        self.end_line()
+    }
+
+    /// Lists a directory in a section of its own and, under -R, its
+    /// subdirectories after it, depth first. The section is only headed by
+    /// the directory's name when there are others. Without metadata there
+    /// were no symlinks followed to get here, so there can be no loop.
+    fn list(&mut self, dir: &Path, meta: Option<&fs::Metadata>, header: bool) -> io::Result<()> {
+        if header {
+            self.print_header(dir)?;
+        }
+        let key = meta.map(|meta| (meta.dev(), meta.ino()));
+        if key.is_some_and(|key| self.ancestors.contains(&key)) {
+            eprintln!(
+                "ls: {}: not listing already-listed directory",
+                dir.display()
+            );
+            self.fail(true);
+            return Ok(());
+        }
+        let listed = match self.config.streams() {
+            true => self.stream_dir(dir),
+            false => self.list_dir(dir).map(ControlFlow::Continue),
+        };
+        let entries = match listed {
+            Ok(ControlFlow::Continue(entries)) => entries,
+            Ok(ControlFlow::Break(e)) => return Err(e),
+            Err(e) => {
+                eprintln!(
+                    "ls: cannot open directory '{}': {}",
+                    dir.display(),
+                    strerror(&e)
+                );
+                // Only operands are listed outside of -R's recursion.
+                let operand = self.ancestors.is_empty();
+                self.fail(operand);
+                return Ok(());
+            }
+        };
+        if !self.config.streams() {
+            self.print_entries(&entries, true)?;
+        }
+        if !self.config.recursive {
+            return Ok(());
+        }
+        self.ancestors.extend(key);
+        let subdirs = entries
+            .iter()
+            .filter(|e| e.file_type.is_dir() && e.name != "." && e.name != "..");
+        for entry in subdirs {
+            self.end_line()?;
+            self.list(&dir.join(&entry.name), entry.meta.as_ref(), true)?;
+        }
+        if key.is_some() {
+            self.ancestors.pop();
+        }
+        Ok(())
+    }
+
+    /// Lists the command line operands: files first, then directories.
+    fn list_operands(&mut self, operands: &[PathBuf]) -> io::Result<()> {
+        let follow = match self.config.dereference {
+            Dereference::None => Follow::Never,
+            Dereference::DirOperands => Follow::Dirs,
+            Dereference::Operands | Dereference::All => Follow::Always,
+        };
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for operand in operands {
+            match self.entry(operand.into(), operand.clone(), follow) {
+                Ok(entry) if entry.file_type.is_dir() => dirs.push(entry),
+                Ok(entry) => files.push(entry),
+                Err(e) => {
+                    eprintln!(
+                        "ls: cannot access '{}': {}",
+                        operand.display(),
+                        strerror(&e)
+                    );
+                    self.fail(true);
+                }
+            }
+        }
+        files.sort_by(|a, b| compare(a, b, self.config));
+        dirs.sort_by(|a, b| compare(a, b, self.config));
+        if !files.is_empty() {
+            self.print_entries(&files, false)?;
+        }
+        let header = operands.len() > 1 || self.config.recursive;
+        for (i, dir) in dirs.iter().enumerate() {
+            if i > 0 || !files.is_empty() {
+                self.end_line()?;
+            }
+            self.list(&dir.path, dir.meta.as_ref(), header)?;
+        }
+        Ok(())
+    }
 }
 
 fn main() {
@@ -22,30 +1058,558 @@ fn main() {
         .arg(
             Arg::new("include_dot_files")
                 .short('a')
+                .long("all")
                 .action(ArgAction::SetTrue)
+                .overrides_with("almost_all")
                 .help("Do not ingore hidden files (files with names that start with '.'). "),
         )
-        .arg(Arg::new("paths").action(ArgAction::Append));
+        .arg(
+            Arg::new("almost_all")
+                .short('A')
+                .long("almost-all")
+                .action(ArgAction::SetTrue)
+                .overrides_with("include_dot_files")
+                .help("Like -a, but leave out '.' and '..'."),
+        )
+        .arg(
+            Arg::new("ignore")
+                .short('I')
+                .long("ignore")
+                .value_name("PATTERN")
+                .action(ArgAction::Append)
+                .help("Do not list entries matching the shell PATTERN."),
+        )
+        .arg(
+            Arg::new("hide")
+                .long("hide")
+                .value_name("PATTERN")
+                .action(ArgAction::Append)
+                .help("Do not list entries matching the shell PATTERN, unless -a or -A is given."),
+        )
+        .arg(
+            Arg::new("long")
+                .short('l')
+                .action(ArgAction::SetTrue)
+                .help("Use a long listing format."),
+        )
+        .arg(
+            Arg::new("numeric_ids")
+                .short('n')
+                .long("numeric-uid-gid")
+                .action(ArgAction::SetTrue)
+                .help("Like -l, but list numeric user and group IDs."),
+        )
+        .arg(
+            Arg::new("no_owner")
+                .short('g')
+                .action(ArgAction::SetTrue)
+                .help("Like -l, but do not list owners."),
+        )
+        .arg(
+            Arg::new("no_group")
+                .short('o')
+                .action(ArgAction::SetTrue)
+                .help("Like -l, but do not list groups."),
+        )
+        .arg(
+            Arg::new("size")
+                .short('s')
+                .long("size")
+                .action(ArgAction::SetTrue)
+                .help("Show the space each file takes up, in 1K blocks."),
+        )
+        .arg(
+            Arg::new("block_size")
+                .long("block-size")
+                .value_name("SIZE")
+                .help("Show sizes in units of SIZE bytes, such as 512, 1M or KB. A bare suffix, such as M, is shown after each size."),
+        )
+        .arg(
+            Arg::new("kibibytes")
+                .short('k')
+                .long("kibibytes")
+                .action(ArgAction::SetTrue)
+                .help("Count the space files take up in 1K blocks, whatever the block size is otherwise."),
+        )
+        .arg(
+            Arg::new("recursive")
+                .short('R')
+                .long("recursive")
+                .action(ArgAction::SetTrue)
+                .help("List subdirectories recursively."),
+        )
+        .arg(
+            Arg::new("sort_time")
+                .short('t')
+                .action(ArgAction::SetTrue)
+                .overrides_with("sort_size")
+                .help("Sort by time, newest first."),
+        )
+        .arg(
+            Arg::new("sort_size")
+                .short('S')
+                .action(ArgAction::SetTrue)
+                .overrides_with("sort_time")
+                .help("Sort by size, largest first. Of -S and -t the last one given counts."),
+        )
+        .arg(
+            Arg::new("unsorted")
+                .short('U')
+                .action(ArgAction::SetTrue)
+                .help("Do not sort; list entries in the order the directory holds them."),
+        )
+        .arg(
+            Arg::new("all_unsorted")
+                .short('f')
+                .action(ArgAction::SetTrue)
+                .help("Like -aU, without colors."),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .value_name("WORD")
+                .value_parser(["name", "none", "size", "time"])
+                .help("Sort by WORD rather than by name. Overrides -S and -t, or is overridden by them if given last."),
+        )
+        .arg(
+            Arg::new("fold_case")
+                .long("fold-case")
+                .action(ArgAction::SetTrue)
+                .help("Compare names regardless of case, as far as sorting by name goes."),
+        )
+        .arg(
+            Arg::new("reverse")
+                .short('r')
+                .long("reverse")
+                .action(ArgAction::SetTrue)
+                .help("Reverse the order of the sort."),
+        )
+        .arg(
+            Arg::new("group_directories_first")
+                .long("group-directories-first")
+                .action(ArgAction::SetTrue)
+                .help("List directories before files, each sorted as usual."),
+        )
+        .arg(
+            Arg::new("atime")
+                .short('u')
+                .action(ArgAction::SetTrue)
+                .overrides_with("ctime")
+                .help("Show and sort by the time of last access instead of modification."),
+        )
+        .arg(
+            Arg::new("ctime")
+                .short('c')
+                .action(ArgAction::SetTrue)
+                .overrides_with("atime")
+                .help("Show and sort by the time of last status change instead of modification."),
+        )
+        .arg(
+            Arg::new("time")
+                .long("time")
+                .value_name("WORD")
+                .value_parser([
+                    "atime",
+                    "access",
+                    "use",
+                    "ctime",
+                    "status",
+                    "birth",
+                    "creation",
+                    "mtime",
+                    "modification",
+                ])
+                .help("Show and sort by the time WORD names instead of modification. Of this, -u and -c the last one given counts."),
+        )
+        .arg(
+            Arg::new("time_style")
+                .long("time-style")
+                .value_name("STYLE")
+                .help("How to show times in the long format: full-iso, long-iso, iso, locale or +FORMAT."),
+        )
+        .arg(
+            Arg::new("full_time")
+                .long("full-time")
+                .action(ArgAction::SetTrue)
+                .help("Like -l --time-style=full-iso."),
+        )
+        .arg(
+            Arg::new("zero")
+                .long("zero")
+                .action(ArgAction::SetTrue)
+                .help("End each line with NUL rather than newline, and print names as they are, one per line."),
+        )
+        .arg(
+            Arg::new("one_per_line")
+                .short('1')
+                .action(ArgAction::SetTrue)
+                .help("List one file per line."),
+        )
+        .arg(
+            Arg::new("columns")
+                .short('C')
+                .action(ArgAction::SetTrue)
+                .help("List entries by columns, even when not writing to a terminal."),
+        )
+        .arg(
+            Arg::new("across")
+                .short('x')
+                .action(ArgAction::SetTrue)
+                .help("List entries by lines instead of by columns."),
+        )
+        .arg(
+            Arg::new("commas")
+                .short('m')
+                .action(ArgAction::SetTrue)
+                .help("List entries separated by commas, filling each line."),
+        )
+        .arg(
+            Arg::new("hide_control_chars")
+                .short('q')
+                .long("hide-control-chars")
+                .action(ArgAction::SetTrue)
+                .overrides_with("escape")
+                .help("Show unprintable characters in names as '?'. The default on a terminal."),
+        )
+        .arg(
+            Arg::new("escape")
+                .short('b')
+                .long("escape")
+                .action(ArgAction::SetTrue)
+                .overrides_with("hide_control_chars")
+                .help("Show unprintable characters in names as C-style escapes."),
+        )
+        .arg(
+            Arg::new("literal")
+                .short('N')
+                .long("literal")
+                .action(ArgAction::SetTrue)
+                .help("Print names as they are, without quoting them."),
+        )
+        .arg(
+            Arg::new("quoting_style")
+                .long("quoting-style")
+                .value_name("WORD")
+                .value_parser(["literal", "shell", "shell-always", "shell-escape", "c", "escape"])
+                .help("Quote names in the style WORD. Of this, -N and -b the last one given counts; on a terminal the default is shell-escape."),
+        )
+        .arg(
+            Arg::new("classify")
+                .short('F')
+                .long("classify")
+                .action(ArgAction::SetTrue)
+                .help("Append an indicator of the file type to names: one of */=@|."),
+        )
+        .arg(
+            Arg::new("file_type")
+                .long("file-type")
+                .action(ArgAction::SetTrue)
+                .help("Likewise, except do not append '*'."),
+        )
+        .arg(
+            Arg::new("slash")
+                .short('p')
+                .action(ArgAction::SetTrue)
+                .help("Append '/' to directories and nothing to anything else."),
+        )
+        .arg(
+            Arg::new("indicator_style")
+                .long("indicator-style")
+                .value_name("WORD")
+                .value_parser(["none", "slash", "file-type", "classify"])
+                .help("Append indicators in the style WORD. Of this, -F, --file-type and -p the last one given counts."),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("auto")
+                .value_parser([
+                    "always", "yes", "force", "never", "no", "none", "auto", "tty", "if-tty",
+                ])
+                .help("Color names by file type: 'always', 'never' or 'auto' (on a terminal)."),
+        )
+        .arg(
+            Arg::new("dereference")
+                .short('L')
+                .long("dereference")
+                .action(ArgAction::SetTrue)
+                .overrides_with("dereference_command_line")
+                .help("Show what symlinks point to rather than the links themselves."),
+        )
+        .arg(
+            Arg::new("dereference_command_line")
+                .short('H')
+                .long("dereference-command-line")
+                .action(ArgAction::SetTrue)
+                .overrides_with("dereference")
+                .help("Follow symlinks given on the command line."),
+        )
+        .arg(
+            Arg::new("paths")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(PathBuf)),
+        );
 
-    let args: Vec<String> = env::args().skip(1).collect();
-    let matches = cmd.get_matches_from(&args);
-    let config = Config::from(&matches);
-    let dirs = matches
-        .get_many::<String>("paths")
-        .map(|v| v.map(Path::new).collect())
-        .unwrap_or(vec![Path::new(".")]);
+    let matches = cmd.get_matches();
+    let config = match Config::from(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("ls: {e}");
+            process::exit(2);
+        }
+    };
+    let operands = matches
+        .get_many::<PathBuf>("paths")
+        .map(|v| v.cloned().collect())
+        .unwrap_or(vec![PathBuf::from(".")]);
 
-    for d in dirs {
-        println!("{}:", d.canonicalize().unwrap().display());
-        match fs::read_dir(d) {
-            Ok(res) => res.for_each(|f| {
-                let fname = f.unwrap().file_name();
-                let fname = fname.to_str().unwrap();
-                if !fname.starts_with('.') || config.include_dot_files {
-                    println!("{}", fname);
-                }
-            }),
-            Err(e) => eprintln!("error {}", e),
+    let mut lister = Lister {
+        config: &config,
+        out: io::stdout().lock(),
+        now: SystemTime::now(),
+        ancestors: Vec::new(),
+        status: 0,
+    };
+    let res = lister.list_operands(&operands);
+    match res.and_then(|_| lister.out.flush()) {
+        Ok(()) => {}
+        // Whoever was reading has gone away, as in `ls | head`.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            eprintln!("ls: write error: {}", strerror(&e));
+            process::exit(2);
+        }
+    }
+    process::exit(lister.status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn list_dir(dir: &Path, config: &Config) -> io::Result<Vec<Entry>> {
+        let mut lister = Lister {
+            config,
+            out: io::sink(),
+            now: SystemTime::now(),
+            ancestors: Vec::new(),
+            status: 0,
+        };
+        lister.list_dir(dir)
+    }
+
+    #[test]
+    fn test_format_time() {
+        let style = TimeStyle::default();
+        let now = SystemTime::now();
+        let recent = format_time(now - Duration::from_secs(3600), now, &style, &Local);
+        assert_eq!(recent.len(), "Jan  1 12:00".len());
+        assert_eq!(&recent[9..10], ":");
+
+        let old = format_time(now - Duration::from_secs(400 * 86400), now, &style, &Local);
+        assert_eq!(old.len(), "Jan  1  2024".len());
+        assert!(old[8..].trim().parse::<i32>().is_ok());
+
+        // Times in the future get the year too.
+        let future = format_time(now + Duration::from_secs(3600), now, &style, &Local);
+        assert_eq!(future.len(), "Jan  1  2024".len());
+    }
+
+    #[test]
+    fn test_time_styles() {
+        // 2024-05-02 13:37:05.25 UTC, seen from two hours east.
+        let time = SystemTime::UNIX_EPOCH + Duration::new(1_714_657_025, 250_000_000);
+        let tz = chrono::FixedOffset::east_opt(2 * 3600).unwrap();
+        let soon = time + Duration::from_secs(86400);
+        let later = time + Duration::from_secs(400 * 86400);
+        let format = |style: &str, now| {
+            let style = TimeStyle::parse(style).unwrap();
+            format_time(time, now, &style, &tz)
+        };
+
+        assert_eq!(
+            format("full-iso", soon),
+            "2024-05-02 15:37:05.250000000 +0200"
+        );
+        assert_eq!(format("long-iso", soon), "2024-05-02 15:37");
+        assert_eq!(format("iso", soon), "05-02 15:37");
+        assert_eq!(format("iso", later), "2024-05-02 ");
+        assert_eq!(format("locale", soon), "May  2 15:37");
+        assert_eq!(format("locale", later), "May  2  2024");
+        assert_eq!(format("+%d.%m.%Y", soon), "02.05.2024");
+        assert_eq!(format("+%Y\n%H:%M", soon), "15:37");
+        assert_eq!(format("+%Y\n%H:%M", later), "2024");
+
+        assert_eq!(
+            TimeStyle::parse("bogus"),
+            Err("invalid argument 'bogus' for '--time-style'".to_string())
+        );
+        assert_eq!(
+            TimeStyle::parse("+%Q"),
+            Err("invalid time style format '+%Q'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_long_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("small"), "12345").unwrap();
+        fs::write(dir.path().join("large"), vec![0; 1000]).unwrap();
+        fs::set_permissions(dir.path().join("large"), fs::Permissions::from_mode(0o4755)).unwrap();
+        fs::set_permissions(dir.path().join("small"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::set_permissions(dir.path().join("sub"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let config = Config {
+            long: true,
+            ..Default::default()
+        };
+        let entries = list_dir(dir.path(), &config).unwrap();
+        let lines: Vec<String> = long_lines(&entries, &config, SystemTime::now())
+            .into_iter()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect();
+        let fields: Vec<Vec<&str>> = lines
+            .iter()
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(fields[0][0], "-rwsr-xr-x");
+        assert_eq!(fields[0][4], "1000");
+        assert_eq!(fields[0][8], "large");
+        assert_eq!(fields[1][0], "-rw-r--r--");
+        assert_eq!(fields[1][4], "5");
+        assert_eq!(fields[2][0], "drwxr-xr-x");
+        assert_eq!(fields[2][1], "2");
+        assert_eq!(fields[2][8], "sub");
+
+        // Sizes are right-aligned, so the names line up.
+        let name_column = lines[0].find("large").unwrap();
+        assert_eq!(lines[1].find("small").unwrap(), name_column);
+        assert_eq!(lines[2].find("sub").unwrap(), name_column);
+    }
+
+    #[test]
+    fn test_shown_name() {
+        let name = OsStr::from_bytes(b"a b\n\x1b\xff\\\xc3\xa9");
+        let shown = |quoting, hide_control| {
+            let config = Config {
+                quoting,
+                hide_control,
+                ..Default::default()
+            };
+            String::from_utf8(shown_name(name, &config)).unwrap()
+        };
+        assert_eq!(shown(quote::Style::Literal, true), "a b???\\\u{e9}");
+        assert_eq!(
+            shown(quote::Style::Escape, true),
+            "a\\ b\\n\\033\\377\\\\\u{e9}"
+        );
+        let config = Config::default();
+        assert_eq!(shown_name(name, &config), name.as_bytes());
+    }
+
+    #[test]
+    fn test_needs_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("file"), "").unwrap();
+        fs::create_dir(dir.path().join("dir")).unwrap();
+        std::os::unix::fs::symlink("dir", dir.path().join("link")).unwrap();
+
+        let fast = list_dir(dir.path(), &Config::default()).unwrap();
+        assert!(fast.iter().all(|e| e.meta.is_none()));
+        let by_size = Config {
+            sort: Sort::Size,
+            reverse: true,
+            ..Default::default()
+        };
+        assert!(needs_metadata(&by_size));
+        let mut slow = list_dir(dir.path(), &by_size).unwrap();
+        slow.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(fast.len(), slow.len());
+        for (fast, slow) in fast.iter().zip(&slow) {
+            assert_eq!(fast.name, slow.name);
+            assert_eq!(fast.file_type, slow.file_type);
+            assert_eq!(fast.file_type, slow.meta().file_type());
         }
     }
+
+    #[test]
+    fn test_compare_names() {
+        let sorted = |fold_case| {
+            let mut names = ["b", "B", "a", "_c", "A", "\u{e9}", "\u{c9}"].map(OsStr::new);
+            names.sort_by(|a, b| compare_names(a, b, fold_case));
+            names.map(|n| n.to_str().unwrap())
+        };
+        assert_eq!(
+            sorted(false),
+            ["A", "B", "_c", "a", "b", "\u{c9}", "\u{e9}"]
+        );
+        // Names that differ only in case still come in the same order.
+        assert_eq!(sorted(true), ["_c", "A", "a", "B", "b", "\u{c9}", "\u{e9}"]);
+
+        // Names that are not UTF-8 fold only ASCII.
+        let a = OsStr::from_bytes(b"A\xff");
+        let b = OsStr::from_bytes(b"a\xfe");
+        assert_eq!(compare_names(a, b, false), Ordering::Less);
+        assert_eq!(compare_names(a, b, true), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_reverse() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, size) in [("a", 1), ("b", 2), ("c", 2), ("d", 0)] {
+            fs::write(dir.path().join(name), vec![0; size]).unwrap();
+        }
+        let names = |config: &Config| -> Vec<String> {
+            let entries = list_dir(dir.path(), config).unwrap();
+            entries
+                .into_iter()
+                .map(|e| e.name.into_string().unwrap())
+                .collect()
+        };
+        let reverse = Config {
+            reverse: true,
+            ..Default::default()
+        };
+        assert_eq!(names(&reverse), ["d", "c", "b", "a"]);
+
+        // Ties are broken by name, and that order is reversed too.
+        let by_size = Config {
+            sort: Sort::Size,
+            ..Default::default()
+        };
+        assert_eq!(names(&by_size), ["b", "c", "a", "d"]);
+        let reverse_by_size = Config {
+            sort: Sort::Size,
+            reverse: true,
+            ..Default::default()
+        };
+        assert_eq!(names(&reverse_by_size), ["d", "a", "c", "b"]);
+    }
+
+    #[test]
+    fn test_total_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a"), vec![1; 10000]).unwrap();
+        fs::write(dir.path().join("b"), "").unwrap();
+        let config = Config {
+            size: true,
+            ..Default::default()
+        };
+        let entries = list_dir(dir.path(), &config).unwrap();
+        let sectors: u64 = entries.iter().map(|e| e.meta().blocks()).sum();
+        let total = total_blocks(&entries, &config);
+        assert_eq!(total, sectors.div_ceil(2).to_string());
+        assert!(total.parse::<u64>().unwrap() >= 10);
+        let column = block_column(&entries, &config);
+        assert_eq!(
+            column[0].trim_end(),
+            entries[0].meta().blocks().div_ceil(2).to_string()
+        );
+        assert_eq!(column[1], format!("{:>w$} ", 0, w = column[0].len() - 1));
+    }
 }
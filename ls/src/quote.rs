@@ -0,0 +1,231 @@
+//! Writing names out so that odd characters in them show, and so that they
+//! can be pasted back into a shell, as with --quoting-style.
+
+/// The ways names can be written out.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// Byte for byte.
+    #[default]
+    Literal,
+    /// In single quotes if the shell would need them.
+    Shell,
+    /// Always in single quotes.
+    ShellAlways,
+    /// Like `Shell`, with unprintable characters written as `$'\n'`.
+    ShellEscape,
+    /// In double quotes, with C-style backslash escapes.
+    C,
+    /// With C-style backslash escapes but no quotes, as with -b.
+    Escape,
+}
+
+/// Characters that mean something to the shell wherever they are.
+const SHELL_SPECIAL: &[u8] = b" \t\n!\"$&'()*;<=>?[\\]^`{|}";
+
+/// A piece of a name: a character that can be printed as it is, or the
+/// bytes of one that cannot, which includes bytes that are not UTF-8.
+enum Unit<'a> {
+    Printable(&'a str),
+    Unprintable(&'a [u8]),
+}
+
+fn units(name: &[u8]) -> Vec<Unit<'_>> {
+    let mut units = Vec::new();
+    for chunk in name.utf8_chunks() {
+        let valid = chunk.valid();
+        for (i, c) in valid.char_indices() {
+            let text = &valid[i..i + c.len_utf8()];
+            units.push(match c.is_control() {
+                true => Unit::Unprintable(text.as_bytes()),
+                false => Unit::Printable(text),
+            });
+        }
+        units.extend(chunk.invalid().chunks(1).map(Unit::Unprintable));
+    }
+    units
+}
+
+/// Writes `b` the way C would in a string.
+fn escape_byte(b: u8, out: &mut Vec<u8>) {
+    let named = match b {
+        0x07 => b'a',
+        0x08 => b'b',
+        b'\t' => b't',
+        b'\n' => b'n',
+        0x0b => b'v',
+        0x0c => b'f',
+        b'\r' => b'r',
+        _ => {
+            out.extend(format!("\\{b:03o}").bytes());
+            return;
+        }
+    };
+    out.extend([b'\\', named]);
+}
+
+/// Whether the shell would take `name` for something other than itself.
+fn needs_shell_quotes(name: &[u8], units: &[Unit]) -> bool {
+    name.is_empty()
+        || name.starts_with(b"#")
+        || name.starts_with(b"~")
+        || name.iter().any(|b| SHELL_SPECIAL.contains(b))
+        || units.iter().any(|u| matches!(u, Unit::Unprintable(_)))
+}
+
+/// Writes `text` in single quotes, closing them around any single quote in
+/// it, which the shell reads as `'\''`.
+fn single_quote(text: &[u8], out: &mut Vec<u8>) {
+    out.push(b'\'');
+    for &b in text {
+        match b {
+            b'\'' => out.extend(b"'\\''"),
+            _ => out.push(b),
+        }
+    }
+    out.push(b'\'');
+}
+
+/// `name` written out in `style`. With `hide_control`, unprintable
+/// characters that the style would leave as they are become `?`.
+pub fn quote(name: &[u8], style: Style, hide_control: bool) -> Vec<u8> {
+    let units = units(name);
+    // The name with unprintable characters as `?` if need be, for the
+    // styles that do not escape them.
+    let plain = || -> Vec<u8> {
+        units
+            .iter()
+            .flat_map(|u| match u {
+                Unit::Printable(text) => text.as_bytes(),
+                Unit::Unprintable(_) if hide_control => b"?",
+                Unit::Unprintable(bytes) => bytes,
+            })
+            .copied()
+            .collect()
+    };
+    let mut out = Vec::new();
+    match style {
+        Style::Literal => return plain(),
+        Style::Shell if !needs_shell_quotes(name, &units) => return plain(),
+        Style::Shell | Style::ShellAlways => single_quote(&plain(), &mut out),
+        Style::ShellEscape if !needs_shell_quotes(name, &units) => return plain(),
+        Style::ShellEscape => {
+            // Runs of printable characters go in single quotes, and the rest
+            // in $'...', which the shell joins back up.
+            let mut run = Vec::new();
+            let mut escaped = Vec::new();
+            for unit in &units {
+                match unit {
+                    Unit::Printable(text) => {
+                        if !escaped.is_empty() {
+                            out.extend(b"$'");
+                            out.append(&mut escaped);
+                            out.push(b'\'');
+                        }
+                        run.extend(text.as_bytes());
+                    }
+                    Unit::Unprintable(bytes) => {
+                        if !run.is_empty() {
+                            single_quote(&run, &mut out);
+                            run.clear();
+                        }
+                        bytes.iter().for_each(|&b| escape_byte(b, &mut escaped));
+                    }
+                }
+            }
+            if !run.is_empty() {
+                single_quote(&run, &mut out);
+            }
+            if !escaped.is_empty() {
+                out.extend(b"$'");
+                out.append(&mut escaped);
+                out.push(b'\'');
+            }
+        }
+        Style::C | Style::Escape => {
+            let c = style == Style::C;
+            if c {
+                out.push(b'"');
+            }
+            for unit in &units {
+                match unit {
+                    Unit::Printable("\\") => out.extend(b"\\\\"),
+                    Unit::Printable("\"") if c => out.extend(b"\\\""),
+                    Unit::Printable(" ") if !c => out.extend(b"\\ "),
+                    Unit::Printable(text) => out.extend(text.as_bytes()),
+                    Unit::Unprintable(bytes) => {
+                        bytes.iter().for_each(|&b| escape_byte(b, &mut out));
+                    }
+                }
+            }
+            if c {
+                out.push(b'"');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quoted(name: &[u8], style: Style) -> String {
+        String::from_utf8(quote(name, style, false)).unwrap()
+    }
+
+    #[test]
+    fn test_literal() {
+        let name = b"a b\n\x1b\xff\\\xc3\xa9";
+        assert_eq!(quote(name, Style::Literal, false), name);
+        assert_eq!(
+            quote(name, Style::Literal, true),
+            "a b???\\\u{e9}".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_shell() {
+        assert_eq!(quoted(b"plain.txt", Style::Shell), "plain.txt");
+        assert_eq!(quoted(b"my file", Style::Shell), "'my file'");
+        assert_eq!(quoted(b"it's", Style::Shell), "'it'\\''s'");
+        assert_eq!(quoted(b"#notes", Style::Shell), "'#notes'");
+        assert_eq!(quoted(b"a#b", Style::Shell), "a#b");
+        assert_eq!(quoted(b"", Style::Shell), "''");
+        // Control characters are left alone unless hidden.
+        assert_eq!(quoted(b"a\nb", Style::Shell), "'a\nb'");
+        assert_eq!(quote(b"a\nb", Style::Shell, true), b"'a?b'");
+
+        assert_eq!(quoted(b"plain.txt", Style::ShellAlways), "'plain.txt'");
+        assert_eq!(quoted(b"it's", Style::ShellAlways), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_shell_escape() {
+        assert_eq!(quoted(b"plain.txt", Style::ShellEscape), "plain.txt");
+        assert_eq!(quoted(b"my file", Style::ShellEscape), "'my file'");
+        assert_eq!(quoted(b"it's", Style::ShellEscape), "'it'\\''s'");
+        assert_eq!(quoted(b"a\nb", Style::ShellEscape), "'a'$'\\n''b'");
+        assert_eq!(quoted(b"\t\x1bx", Style::ShellEscape), "$'\\t\\033''x'");
+        assert_eq!(quoted(b"x\xff\xfe", Style::ShellEscape), "'x'$'\\377\\376'");
+        // There is nothing left for -q to hide.
+        assert_eq!(quote(b"a\nb", Style::ShellEscape, true), b"'a'$'\\n''b'");
+        assert_eq!(
+            quoted("caf\u{e9}".as_bytes(), Style::ShellEscape),
+            "caf\u{e9}"
+        );
+    }
+
+    #[test]
+    fn test_c_and_escape() {
+        let name = b"a b\n\x1b\xff\\\"\xc3\xa9";
+        assert_eq!(quoted(name, Style::C), "\"a b\\n\\033\\377\\\\\\\"\u{e9}\"");
+        assert_eq!(
+            quoted(name, Style::Escape),
+            "a\\ b\\n\\033\\377\\\\\"\u{e9}"
+        );
+        assert_eq!(
+            quoted(b"\x07\x08\x0b\x0c\r", Style::Escape),
+            "\\a\\b\\v\\f\\r"
+        );
+    }
+}
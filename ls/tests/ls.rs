@@ -0,0 +1,959 @@
+use std::ffi::{CString, OsString};
+use std::fs::{self, FileTimes};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+fn qls() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_qls"))
+}
+
+/// Runs qls and returns its stdout, which it must have finished cleanly.
+fn stdout(cmd: &mut Command) -> String {
+    let output = cmd.output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn blocks(path: &Path) -> u64 {
+    fs::symlink_metadata(path).unwrap().blocks()
+}
+
+#[test]
+fn test_long_listing() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a"), "hello").unwrap();
+    fs::write(dir.path().join("b"), vec![0; 20000]).unwrap();
+    fs::write(dir.path().join(".hidden"), "").unwrap();
+
+    let out = stdout(qls().arg("-l").arg(dir.path()));
+    let lines: Vec<&str> = out.lines().collect();
+    let sectors = blocks(&dir.path().join("a")) + blocks(&dir.path().join("b"));
+    assert_eq!(lines[0], format!("total {}", sectors.div_ceil(2)));
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("-rw-r--r-- 1 "));
+    assert!(lines[1].ends_with(" a"));
+    assert!(lines[2].ends_with(" b"));
+    let sizes: Vec<&str> = lines[1..]
+        .iter()
+        .map(|l| l.split_whitespace().nth(4).unwrap())
+        .collect();
+    assert_eq!(sizes, ["5", "20000"]);
+}
+
+#[test]
+fn test_recursive() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    fs::create_dir_all(root.join("a/deeper")).unwrap();
+    fs::write(root.join("a/file"), "").unwrap();
+    fs::write(root.join("a/deeper/.hidden"), "").unwrap();
+    fs::create_dir(root.join("b")).unwrap();
+    fs::write(root.join("top"), "").unwrap();
+    symlink("a", root.join("link")).unwrap();
+
+    let out = stdout(qls().arg("-R").arg(&root));
+    let r = root.display();
+    assert_eq!(
+        out,
+        format!("{r}:\na\nb\nlink\ntop\n\n{r}/a:\ndeeper\nfile\n\n{r}/a/deeper:\n\n{r}/b:\n")
+    );
+
+    let out = stdout(qls().args(["-R", "-a"]).arg(root.join("a")));
+    assert!(out.ends_with(&format!("{r}/a/deeper:\n.\n..\n.hidden\n")));
+}
+
+#[test]
+fn test_recursive_unreadable() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    fs::create_dir_all(root.join("locked/inner")).unwrap();
+    fs::create_dir(root.join("open")).unwrap();
+    fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+    // Permissions keep nothing from root.
+    if fs::read_dir(root.join("locked")).is_ok() {
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+        return;
+    }
+    let output = qls().arg("-R").arg(&root).output().unwrap();
+    fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let out = String::from_utf8(output.stdout).unwrap();
+    assert!(out.ends_with(&format!("{}/open:\n", root.display())));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "ls: cannot open directory '{}/locked': Permission denied\n",
+            root.display()
+        )
+    );
+}
+
+#[test]
+fn test_entries_that_cannot_be_looked_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let shut = dir.path().join("shut");
+    fs::create_dir(&shut).unwrap();
+    for name in ["a", "b"] {
+        fs::write(shut.join(name), "").unwrap();
+    }
+    // Names can be read without search permission, but nothing about them.
+    fs::set_permissions(&shut, fs::Permissions::from_mode(0o644)).unwrap();
+    if fs::symlink_metadata(shut.join("a")).is_ok() {
+        return;
+    }
+    let output = qls()
+        .arg("-l")
+        .arg("shut")
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    fs::set_permissions(&shut, fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let mut errors: Vec<&str> = std::str::from_utf8(&output.stderr)
+        .unwrap()
+        .lines()
+        .collect();
+    errors.sort();
+    assert_eq!(
+        errors,
+        [
+            "ls: cannot access 'shut/a': Permission denied",
+            "ls: cannot access 'shut/b': Permission denied",
+        ]
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "total 0\n");
+}
+
+/// Sets the access and modification times of `path` to `atime` and
+/// `mtime` seconds after a moment in September 2001.
+fn set_times(path: &Path, atime: u64, mtime: u64) {
+    let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    fs::File::open(path)
+        .unwrap()
+        .set_times(
+            FileTimes::new()
+                .set_accessed(epoch + Duration::from_secs(atime))
+                .set_modified(epoch + Duration::from_secs(mtime)),
+        )
+        .unwrap();
+}
+
+#[test]
+fn test_sort_by_time() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["old", "new", "tie-b", "tie-a"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    set_times(&dir.path().join("old"), 4000, 1000);
+    set_times(&dir.path().join("new"), 1000, 3000);
+    set_times(&dir.path().join("tie-b"), 2000, 2000);
+    set_times(&dir.path().join("tie-a"), 2000, 2000);
+
+    let names = |args: &[&str]| -> Vec<String> {
+        stdout(qls().args(args).arg(dir.path()))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(names(&[]), ["new", "old", "tie-a", "tie-b"]);
+    assert_eq!(names(&["-t"]), ["new", "tie-a", "tie-b", "old"]);
+    assert_eq!(names(&["-tu"]), ["old", "tie-a", "tie-b", "new"]);
+    assert_eq!(names(&["-t", "--time=atime"]), names(&["-tu"]));
+    assert_eq!(names(&["-t", "--time=use"]), names(&["-tu"]));
+    // The last of -u, -c and --time wins.
+    assert_eq!(names(&["-tu", "--time=mtime"]), names(&["-t"]));
+    assert_eq!(names(&["-t", "--time=access", "-c"]), names(&["-tc"]));
+
+    // Changing the mode makes "old" the most recently changed.
+    thread::sleep(Duration::from_millis(10));
+    fs::set_permissions(dir.path().join("old"), fs::Permissions::from_mode(0o600)).unwrap();
+    assert_eq!(names(&["-tc"])[0], "old");
+
+    // -l shows the time being sorted by.
+    let out = stdout(qls().args(["-l", "-u"]).arg(dir.path()));
+    assert!(out.lines().any(|l| l.ends_with(" 2001 new")), "{out}");
+    let out = stdout(
+        qls()
+            .args(["-l", "--time=atime", "--time-style=+%s"])
+            .arg(dir.path().join("new")),
+    );
+    assert!(out.contains(" 1000001000 "), "{out}");
+}
+
+#[test]
+fn test_birth_time() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["a", "b"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    set_times(&dir.path().join("a"), 0, 2000);
+    set_times(&dir.path().join("b"), 0, 1000);
+
+    // Not every file system records when files were made, tmpfs among
+    // them on older kernels, so this only checks that asking is harmless.
+    let out = stdout(
+        qls()
+            .args(["-l", "--time=birth", "--time-style=+%s"])
+            .arg(dir.path()),
+    );
+    for line in out.lines().skip(1) {
+        let time = line.split_whitespace().nth(5).unwrap();
+        assert!(time == "?" || time.parse::<u64>().is_ok(), "{out}");
+    }
+    let out = stdout(qls().args(["-t", "--time=creation"]).arg(dir.path()));
+    assert_eq!(out.lines().count(), 2);
+}
+
+#[test]
+fn test_full_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    fs::write(&path, "").unwrap();
+    let mtime = SystemTime::UNIX_EPOCH + Duration::new(1_000_000_000, 123_456_789);
+    fs::File::open(&path)
+        .unwrap()
+        .set_times(FileTimes::new().set_modified(mtime))
+        .unwrap();
+
+    let time = |args: &[&str]| -> String {
+        let out = stdout(qls().env("TZ", "UTC").args(args).arg(&path));
+        let fields: Vec<&str> = out.split_whitespace().collect();
+        fields[5..fields.len() - 1].join(" ")
+    };
+    assert_eq!(
+        time(&["--full-time"]),
+        "2001-09-09 01:46:40.123456789 +0000"
+    );
+    // Whichever of --full-time and --time-style comes last wins.
+    assert_eq!(
+        time(&["--time-style=long-iso", "--full-time"]),
+        time(&["--full-time"])
+    );
+    assert_eq!(
+        time(&["--full-time", "--time-style=long-iso"]),
+        "2001-09-09 01:46"
+    );
+}
+
+#[test]
+fn test_sort_by_size() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("empty"), "").unwrap();
+    fs::write(dir.path().join("ten"), [0; 10]).unwrap();
+    fs::write(dir.path().join("thousand"), [0; 1000]).unwrap();
+    fs::write(dir.path().join("also-ten"), [0; 10]).unwrap();
+    set_times(&dir.path().join("empty"), 0, 2000);
+
+    let names = |args: &[&str]| -> Vec<String> {
+        stdout(qls().args(args).arg(dir.path()))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(names(&["-S"]), ["thousand", "also-ten", "ten", "empty"]);
+    // The last of -S and -t wins.
+    assert_eq!(names(&["-t", "-S"])[0], "thousand");
+    assert_eq!(names(&["-S", "-t"])[3], "empty");
+    // So does --sort.
+    assert_eq!(names(&["--sort=size"]), names(&["-S"]));
+    assert_eq!(names(&["-S", "--sort=name"])[0], "also-ten");
+    assert_eq!(names(&["--sort=name", "-S"])[0], "thousand");
+}
+
+#[test]
+fn test_unsorted() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["b", "a", ".hidden", "c"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub/inner"), "").unwrap();
+    let names = |args: &[&str]| -> Vec<String> {
+        stdout(qls().args(args).arg(dir.path()))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    let sorted = |mut names: Vec<String>| {
+        names.sort();
+        names
+    };
+
+    // -f is -aU, so everything comes out in whatever order the directory
+    // holds it, starting with the two read_dir leaves out.
+    let all = names(&["-f"]);
+    assert_eq!(all[..2], [".", ".."]);
+    assert_eq!(sorted(all), [".", "..", ".hidden", "a", "b", "c", "sub"]);
+    assert_eq!(sorted(names(&["-U"])), ["a", "b", "c", "sub"]);
+    // Columns fall back to one name a line, and colors go.
+    assert_eq!(names(&["-U", "-C"]), names(&["-U"]));
+    assert_eq!(names(&["-f", "--color=always"]), names(&["-f"]));
+    // A later sort option wins.
+    assert_eq!(names(&["-U", "--sort=name"]), ["a", "b", "c", "sub"]);
+
+    let out = stdout(qls().args(["-U", "-R"]).arg(dir.path()));
+    assert!(out.ends_with(&format!("\n\n{}/sub:\ninner\n", dir.path().display())));
+}
+
+#[test]
+fn test_fold_case() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["beta", "Alpha", "alpha", "Gamma"] {
+        fs::write(dir.path().join(name), name).unwrap();
+    }
+    let names = |args: &[&str]| -> Vec<String> {
+        stdout(qls().args(args).arg(dir.path()))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(names(&[]), ["Alpha", "Gamma", "alpha", "beta"]);
+    assert_eq!(names(&["--fold-case"]), ["Alpha", "alpha", "beta", "Gamma"]);
+    assert_eq!(
+        names(&["--fold-case", "-r"]),
+        ["Gamma", "beta", "alpha", "Alpha"]
+    );
+    assert_eq!(names(&["--sort=none"]).len(), 4);
+}
+
+#[test]
+fn test_reverse() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["b", "a", "c"] {
+        fs::write(dir.path().join(name), name).unwrap();
+    }
+    let out = stdout(qls().arg("-r").arg(dir.path()));
+    assert_eq!(out.lines().collect::<Vec<_>>(), ["c", "b", "a"]);
+}
+
+#[test]
+fn test_output_format() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["alpha", "beta", "gamma", "delta"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    let names = |args: &[&str]| {
+        let out = stdout(qls().env("COLUMNS", "20").args(args).arg(dir.path()));
+        out.lines().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    // A pipe gets one name per line unless asked otherwise.
+    assert_eq!(names(&[]), ["alpha", "beta", "delta", "gamma"]);
+    assert_eq!(names(&["-C"]), ["alpha  delta", "beta   gamma"]);
+    assert_eq!(names(&["-1", "-C"]), ["alpha  delta", "beta   gamma"]);
+    assert_eq!(names(&["-C", "-1"]), ["alpha", "beta", "delta", "gamma"]);
+
+    assert_eq!(names(&["-m"]), ["alpha, beta, delta,", "gamma"]);
+    // As many columns as fit, even if that leaves the last row short.
+    assert_eq!(names(&["-x"]), ["alpha  beta  delta", "gamma"]);
+    assert_eq!(names(&["-x", "-C"]), ["alpha  delta", "beta   gamma"]);
+
+    // Of these and -l, the last one given counts.
+    let long = names(&["-C", "-m", "-l"]);
+    assert_eq!(long.len(), 5);
+    assert!(long[1].ends_with(" alpha"));
+    assert_eq!(names(&["-l", "-C"]), ["alpha  delta", "beta   gamma"]);
+    assert_eq!(
+        names(&["-l", "-m", "-1"]),
+        ["alpha", "beta", "delta", "gamma"]
+    );
+}
+
+#[test]
+fn test_indicators() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("dir")).unwrap();
+    fs::write(dir.path().join("exec"), "").unwrap();
+    fs::set_permissions(dir.path().join("exec"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.path().join("file"), "").unwrap();
+    symlink("file", dir.path().join("link")).unwrap();
+    let fifo = CString::new(dir.path().join("pipe").as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+    let out = stdout(qls().arg("-F").arg(dir.path()));
+    assert_eq!(
+        out.lines().collect::<Vec<_>>(),
+        ["dir/", "exec*", "file", "link@", "pipe|"]
+    );
+    let out = stdout(qls().arg("--file-type").arg(dir.path()));
+    assert_eq!(
+        out.lines().collect::<Vec<_>>(),
+        ["dir/", "exec", "file", "link@", "pipe|"]
+    );
+    let out = stdout(qls().args(["-l", "-F"]).arg(dir.path()));
+    assert!(out.lines().nth(1).unwrap().ends_with(" dir/"));
+
+    let names = |args: &[&str]| -> Vec<String> {
+        stdout(qls().args(args).arg(dir.path()))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(names(&["-p"]), ["dir/", "exec", "file", "link", "pipe"]);
+    assert_eq!(names(&["--indicator-style=slash"]), names(&["-p"]));
+    // The last of them wins.
+    assert_eq!(names(&["-F", "-p"]), names(&["-p"]));
+    assert_eq!(names(&["-p", "--indicator-style=classify"]), names(&["-F"]));
+    assert_eq!(names(&["-F", "--indicator-style=none"])[0], "dir");
+
+    // Colors take up no room, while the slash does.
+    let plain = stdout(
+        qls()
+            .args(["-C", "-p"])
+            .env("COLUMNS", "20")
+            .arg(dir.path()),
+    );
+    let colored = stdout(
+        qls()
+            .args(["-C", "-p", "--color=always"])
+            .env("COLUMNS", "20")
+            .arg(dir.path()),
+    );
+    assert_eq!(plain, "dir/  file  pipe\nexec  link\n");
+    let mut stripped = String::new();
+    for (i, part) in colored.split('\x1b').enumerate() {
+        match i {
+            0 => stripped.push_str(part),
+            _ => stripped.push_str(&part[part.find('m').unwrap() + 1..]),
+        }
+    }
+    assert_eq!(stripped, plain);
+}
+
+#[test]
+fn test_color() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("dir")).unwrap();
+    fs::write(dir.path().join("file"), "").unwrap();
+
+    let out = stdout(
+        qls()
+            .args(["--color=always", "-F"])
+            .env("LS_COLORS", "di=01;33")
+            .arg(dir.path()),
+    );
+    assert_eq!(out, "\x1b[01;33mdir\x1b[0m/\nfile\n");
+    let out = stdout(
+        qls()
+            .arg("--color=always")
+            .env_remove("LS_COLORS")
+            .arg(dir.path()),
+    );
+    assert!(out.contains("\x1b[01;34mdir\x1b[0m\n"), "{out:?}");
+
+    // A pipe is not a terminal, so auto means no colors.
+    for arg in ["--color", "--color=auto", "--color=never"] {
+        let out = stdout(qls().arg(arg).arg(dir.path()));
+        assert!(!out.contains('\x1b'), "{arg}: {out:?}");
+    }
+}
+
+#[test]
+fn test_all() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join(".hidden"), "").unwrap();
+    fs::write(dir.path().join("file"), "").unwrap();
+    let names = |arg: &str| {
+        let out = stdout(qls().arg(arg).arg(dir.path()));
+        out.lines().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    assert_eq!(names("-a"), [".", "..", ".hidden", "file"]);
+    assert_eq!(names("-A"), [".hidden", "file"]);
+    let out = stdout(qls().args(["-a", "-l"]).arg(dir.path()));
+    assert!(out.lines().nth(1).unwrap().starts_with("d"));
+    assert!(out.lines().nth(1).unwrap().ends_with(" ."));
+    // -R does not go round in circles through them.
+    let out = stdout(qls().args(["-a", "-R"]).arg(dir.path()));
+    assert_eq!(out.lines().count(), 5);
+}
+
+#[test]
+fn test_group_directories_first() {
+    let dir = tempfile::tempdir().unwrap();
+    for (i, name) in ["b", "d", "a", "c"].iter().enumerate() {
+        let path = dir.path().join(name);
+        match *name < "c" {
+            true => fs::write(&path, "").unwrap(),
+            false => fs::create_dir(&path).unwrap(),
+        }
+        set_times(&path, i as u64, i as u64);
+    }
+    symlink("c", dir.path().join("e")).unwrap();
+    let names = |args: &[&str]| {
+        let out = stdout(qls().args(args).arg(dir.path()));
+        out.lines().collect::<String>()
+    };
+
+    assert_eq!(names(&["--group-directories-first"]), "cdabe");
+    assert_eq!(names(&["--group-directories-first", "-r"]), "dceba");
+    // The link itself was made just now.
+    assert_eq!(names(&["--group-directories-first", "-t"]), "cdeab");
+}
+
+#[test]
+fn test_allocated_size() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("normal"), vec![1; 100_000]).unwrap();
+    let sparse = fs::File::create(dir.path().join("sparse")).unwrap();
+    sparse.set_len(1 << 30).unwrap();
+
+    let out = stdout(qls().arg("-s").arg(dir.path()));
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 3);
+    let sizes: Vec<u64> = lines[1..]
+        .iter()
+        .map(|l| l.split_whitespace().next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(lines[0], format!("total {}", sizes.iter().sum::<u64>()));
+    assert!(lines[1].ends_with(" normal"));
+    assert!(sizes[0] >= 98);
+    assert!(lines[2].ends_with(" sparse"));
+    assert!(sizes[1] * 1024 < 1 << 30);
+
+    let out = stdout(qls().args(["-s", "-l"]).arg(dir.path()));
+    let line = out.lines().nth(1).unwrap();
+    assert!(line.starts_with(&format!(
+        "{:>w$} -rw-",
+        sizes[0],
+        w = lines[1].find(' ').unwrap()
+    )));
+}
+
+#[test]
+fn test_block_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    fs::write(&path, vec![1; 100_000]).unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(5 << 19)
+        .unwrap();
+    let sectors = blocks(&path);
+
+    let field = |args: &[&str], env: &[(&str, &str)], n: usize| -> String {
+        let out = stdout(
+            qls()
+                .env_remove("LS_BLOCK_SIZE")
+                .env_remove("BLOCK_SIZE")
+                .envs(env.iter().copied())
+                .args(args)
+                .arg(&path),
+        );
+        out.split_whitespace().nth(n).unwrap().to_string()
+    };
+    let size = |args: &[&str], env: &[(&str, &str)]| field(args, env, 4);
+    let allocated = |args: &[&str], env: &[(&str, &str)]| field(args, env, 0);
+
+    // 2.5M, rounded up.
+    assert_eq!(size(&["-l"], &[]), (5 << 19).to_string());
+    assert_eq!(size(&["-l", "--block-size=1M"], &[]), "3");
+    assert_eq!(size(&["-l", "--block-size=M"], &[]), "3M");
+    assert_eq!(size(&["-l", "--block-size=MiB"], &[]), "3MiB");
+    assert_eq!(size(&["-l", "--block-size=KB"], &[]), "2622KB");
+    assert_eq!(size(&["-l", "--block-size=512"], &[]), "5120");
+
+    assert_eq!(allocated(&["-s"], &[]), sectors.div_ceil(2).to_string());
+    assert_eq!(
+        allocated(&["-s", "--block-size=512"], &[]),
+        sectors.to_string()
+    );
+    assert_eq!(
+        allocated(&["-s", "--block-size=512", "-k"], &[]),
+        allocated(&["-s"], &[])
+    );
+
+    // LS_BLOCK_SIZE comes before BLOCK_SIZE, and the option before both.
+    let env = [("LS_BLOCK_SIZE", "1M"), ("BLOCK_SIZE", "K")];
+    assert_eq!(size(&["-l"], &env), "3");
+    assert_eq!(size(&["-l"], &env[1..]), "2560K");
+    assert_eq!(size(&["-l", "--block-size=512"], &env), "5120");
+    // -k only goes for -s and totals.
+    assert_eq!(size(&["-l", "-k"], &env), "3");
+    assert_eq!(allocated(&["-s", "-k"], &env), allocated(&["-s"], &[]));
+    // Nonsense in the environment is ignored; on the command line it is not.
+    assert_eq!(
+        size(&["-l"], &[("BLOCK_SIZE", "bogus")]),
+        (5 << 19).to_string()
+    );
+    let output = qls().arg("--block-size=0").arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "ls: invalid --block-size argument '0'\n"
+    );
+}
+
+#[test]
+fn test_non_utf8_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = OsString::from_vec(b"bad\xffname".to_vec());
+    fs::write(dir.path().join(&name), "").unwrap();
+    fs::write(dir.path().join("bad\u{fffd}name"), "").unwrap();
+
+    let output = qls().arg(dir.path()).output().unwrap();
+    assert!(output.status.success());
+    let names: Vec<&[u8]> = output.stdout.split(|&b| b == b'\n').collect();
+    // Sorted on the bytes, and 0xff comes after the 0xef U+FFFD starts with.
+    assert_eq!(names, ["bad\u{fffd}name".as_bytes(), name.as_bytes(), b""]);
+}
+
+#[test]
+fn test_zero() {
+    let dir = tempfile::tempdir().unwrap();
+    let odd = OsString::from_vec(b"bad\xff name".to_vec());
+    fs::write(dir.path().join(&odd), "").unwrap();
+    fs::write(dir.path().join("new\nline"), "").unwrap();
+    fs::write(dir.path().join("plain"), "").unwrap();
+
+    let output = qls()
+        .args(["--zero", "-C", "-b", "--color=always"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let names: Vec<&[u8]> = output.stdout.split(|&b| b == b'\0').collect();
+    assert_eq!(names, [odd.as_bytes(), b"new\nline", b"plain", b""]);
+
+    // Long lines are NUL-terminated as a whole, and so is the total.
+    let output = qls()
+        .args(["-l", "--zero"])
+        .arg(dir.path())
+        .output()
+        .unwrap();
+    let lines: Vec<&[u8]> = output.stdout.split(|&b| b == b'\0').collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], b"total 0");
+    assert!(lines[2].ends_with(b" new\nline"));
+    // The only newline is the one in the name.
+    assert_eq!(output.stdout.iter().filter(|&&b| b == b'\n').count(), 1);
+}
+
+#[test]
+fn test_numeric_ids() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "").unwrap();
+    let meta = fs::metadata(dir.path().join("file")).unwrap();
+
+    let out = stdout(qls().arg("-n").arg(dir.path()));
+    let lines: Vec<&str> = out.lines().collect();
+    assert!(lines[0].starts_with("total "));
+    let fields: Vec<&str> = lines[1].split_whitespace().collect();
+    assert_eq!(fields[2], meta.uid().to_string());
+    assert_eq!(fields[3], meta.gid().to_string());
+    assert_eq!(fields[8], "file");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_acl_marker() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["acl", "plain"] {
+        fs::write(dir.path().join(name), "").unwrap();
+        fs::set_permissions(dir.path().join(name), fs::Permissions::from_mode(0o644)).unwrap();
+    }
+    // Not every system has setfacl, nor every file system ACLs.
+    let acl = Command::new("setfacl")
+        .args(["-m", "u:0:r"])
+        .arg(dir.path().join("acl"))
+        .status()
+        .is_ok_and(|status| status.success());
+
+    let out = stdout(qls().arg("-l").arg(dir.path()));
+    let lines: Vec<&str> = out.lines().collect();
+    if acl {
+        assert!(lines[1].starts_with("-rw-r--r--+ 1 "), "{out}");
+        // The mode column widens for everything else to line up.
+        assert!(lines[2].starts_with("-rw-r--r--  1 "), "{out}");
+    } else {
+        assert!(lines[1].starts_with("-rw-r--r-- 1 "), "{out}");
+        assert!(lines[2].starts_with("-rw-r--r-- 1 "), "{out}");
+    }
+}
+
+#[test]
+fn test_owner_and_group_columns() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file");
+    fs::write(&path, "12345").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    let meta = fs::metadata(&path).unwrap();
+    let (uid, gid) = (meta.uid(), meta.gid());
+
+    let line = |args: &[&str]| -> String {
+        let out = stdout(
+            qls()
+                .args(["-n", "--time-style=+then"])
+                .args(args)
+                .arg(&path),
+        );
+        out.trim_end().to_string()
+    };
+    assert_eq!(
+        line(&[]),
+        format!("-rw-r--r-- 1 {uid} {gid} 5 then {}", path.display())
+    );
+    assert_eq!(
+        line(&["-g"]),
+        format!("-rw-r--r-- 1 {gid} 5 then {}", path.display())
+    );
+    assert_eq!(
+        line(&["-o"]),
+        format!("-rw-r--r-- 1 {uid} 5 then {}", path.display())
+    );
+    assert_eq!(
+        line(&["-g", "-o"]),
+        format!("-rw-r--r-- 1 5 then {}", path.display())
+    );
+
+    // Either implies -l by itself.
+    let out = stdout(qls().args(["-g", "-o", "--time-style=+then"]).arg(&path));
+    assert_eq!(out, format!("-rw-r--r-- 1 5 then {}\n", path.display()));
+}
+
+#[test]
+fn test_symlinks() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir(root.join("dir")).unwrap();
+    fs::write(root.join("dir/file"), "").unwrap();
+    symlink("dir/file", root.join("good")).unwrap();
+    symlink("nowhere", root.join("dangling")).unwrap();
+    symlink("dir", root.join("dirlink")).unwrap();
+    let lines = |args: &[&str], path: &Path| {
+        let output = qls().args(args).arg(path).output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        (
+            stdout.lines().map(str::to_string).collect::<Vec<_>>(),
+            stderr,
+            output.status.code(),
+        )
+    };
+
+    let (out, err, code) = lines(&["-l"], root);
+    assert_eq!((err.as_str(), code), ("", Some(0)));
+    assert!(out[1].starts_with('l') && out[1].ends_with(" dangling -> nowhere"));
+    assert!(out[3].starts_with('l') && out[3].ends_with(" dirlink -> dir"));
+    assert!(out[4].starts_with('l') && out[4].ends_with(" good -> dir/file"));
+
+    let (out, err, code) = lines(&["-lL"], root);
+    assert_eq!(
+        err,
+        format!(
+            "ls: cannot access '{}': No such file or directory\n",
+            root.join("dangling").display()
+        )
+    );
+    assert_eq!(code, Some(1));
+    assert!(out[1].starts_with('l') && out[1].ends_with(" dangling -> nowhere"));
+    assert!(out[3].starts_with('d') && out[3].ends_with(" dirlink"));
+    assert!(out[4].starts_with('-') && out[4].ends_with(" good"));
+
+    // A symlink to a directory given as an operand is listed as the link
+    // under -l, unless -H or -L says to follow it.
+    let dirlink = root.join("dirlink");
+    let (out, _, _) = lines(&["-l"], &dirlink);
+    assert_eq!(out.len(), 1);
+    assert!(out[0].ends_with(&format!("{} -> dir", dirlink.display())));
+    for flag in ["-lH", "-lL"] {
+        let (out, _, code) = lines(&[flag], &dirlink);
+        assert_eq!(out.len(), 2, "{flag}");
+        assert!(out[1].ends_with(" file"), "{flag}");
+        assert_eq!(code, Some(0));
+    }
+    let (out, _, _) = lines(&[], &dirlink);
+    assert_eq!(out, ["file"]);
+
+    // -H leaves the entries inside alone.
+    let (out, err, _) = lines(&["-lH"], root);
+    assert_eq!(err, "");
+    assert!(out[4].ends_with(" good -> dir/file"));
+    let (out, _, _) = lines(&["-lH"], &root.join("dangling"));
+    assert!(out[0].ends_with(" -> nowhere"));
+}
+
+#[test]
+fn test_missing_operand() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("good")).unwrap();
+    fs::write(dir.path().join("good/a"), "").unwrap();
+    fs::create_dir(dir.path().join("other")).unwrap();
+    fs::write(dir.path().join("other/b"), "").unwrap();
+
+    let output = qls()
+        .current_dir(dir.path())
+        .args(["good", "bad", "other"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "good:\na\n\nother:\nb\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "ls: cannot access 'bad': No such file or directory\n"
+    );
+}
+
+#[test]
+fn test_control_characters() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("new\nline"), "").unwrap();
+    fs::write(dir.path().join("esc\x1b[2J"), "").unwrap();
+    let names = |args: &[&str]| stdout(qls().args(args).arg(dir.path()));
+
+    // Piped output gets the names as they are.
+    assert_eq!(names(&[]), "esc\x1b[2J\nnew\nline\n");
+    assert_eq!(names(&["-q"]), "esc?[2J\nnew?line\n");
+    assert_eq!(names(&["-b"]), "esc\\033[2J\nnew\\nline\n");
+    assert_eq!(names(&["-b", "-q"]), "esc?[2J\nnew?line\n");
+}
+
+#[test]
+fn test_quoting_styles() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["it's", "my file", "new\nline", "plain"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    let names = |args: &[&str]| -> Vec<String> {
+        stdout(qls().args(args).arg(dir.path()))
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+    assert_eq!(names(&[]), ["it's", "my file", "new", "line", "plain"]);
+    assert_eq!(
+        names(&["--quoting-style=shell"]),
+        ["'it'\\''s'", "'my file'", "'new", "line'", "plain"]
+    );
+    assert_eq!(
+        names(&["--quoting-style=shell-always"]),
+        ["'it'\\''s'", "'my file'", "'new", "line'", "'plain'"]
+    );
+    assert_eq!(
+        names(&["--quoting-style=shell-escape"]),
+        ["'it'\\''s'", "'my file'", "'new'$'\\n''line'", "plain"]
+    );
+    assert_eq!(
+        names(&["--quoting-style=c"]),
+        ["\"it's\"", "\"my file\"", "\"new\\nline\"", "\"plain\""]
+    );
+    assert_eq!(
+        names(&["--quoting-style=escape"]),
+        ["it's", "my\\ file", "new\\nline", "plain"]
+    );
+    // The last of -N, -b and --quoting-style wins.
+    assert_eq!(names(&["--quoting-style=c", "-N"]), names(&[]));
+    assert_eq!(
+        names(&["--literal", "-b"]),
+        names(&["--quoting-style=escape"])
+    );
+    assert_eq!(
+        names(&["-b", "--quoting-style=c"]),
+        names(&["--quoting-style=c"])
+    );
+
+    // Columns are as wide as the quoted names.
+    let out = stdout(
+        qls()
+            .args(["-C", "--quoting-style=c"])
+            .env("COLUMNS", "30")
+            .arg(dir.path()),
+    );
+    assert_eq!(out, "\"it's\"     \"new\\nline\"\n\"my file\"  \"plain\"\n");
+}
+
+#[test]
+fn test_ignore_and_hide() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["main.c", "main.o", "util.o", "notes.txt", ".config"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    let names = |args: &[&str]| {
+        let out = stdout(qls().args(args).arg(dir.path()));
+        out.lines().map(str::to_string).collect::<Vec<_>>()
+    };
+
+    assert_eq!(names(&["--ignore=*.o"]), ["main.c", "notes.txt"]);
+    assert_eq!(names(&["-I", "*.o", "-I", "*.txt"]), ["main.c"]);
+    assert_eq!(names(&["-A", "--ignore=[.m]*"]), ["notes.txt", "util.o"]);
+    assert_eq!(names(&["--hide=main.?"]), ["notes.txt", "util.o"]);
+    assert_eq!(
+        names(&["--hide=.*", "-A"]),
+        [".config", "main.c", "main.o", "notes.txt", "util.o"]
+    );
+    assert_eq!(names(&["--hide=.*", "-a"])[..3], [".", "..", ".config"]);
+
+    // Operands are listed regardless.
+    let out = stdout(qls().arg("-I*.o").arg(dir.path().join("main.o")));
+    assert!(out.ends_with("main.o\n"));
+}
+
+#[test]
+fn test_headers() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("a/sub")).unwrap();
+    fs::create_dir(root.join("b")).unwrap();
+    fs::write(root.join("a/file"), "").unwrap();
+    fs::write(root.join("b/other"), "").unwrap();
+    let ls = |args: &[&str]| stdout(qls().current_dir(root).args(args));
+
+    assert_eq!(ls(&["a"]), "file\nsub\n");
+    assert_eq!(ls(&[]), "a\nb\n");
+    assert_eq!(ls(&["a", "b"]), "a:\nfile\nsub\n\nb:\nother\n");
+    assert_eq!(ls(&["a/file", "b"]), "a/file\n\nb:\nother\n");
+    assert_eq!(ls(&["-R", "a"]), "a:\nfile\nsub\n\na/sub:\n");
+}
+
+#[test]
+fn test_fast_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("a/b")).unwrap();
+    for name in ["x", "a/y", "a/b/z", "a/.hidden"] {
+        fs::write(root.join(name), "").unwrap();
+    }
+
+    // -L has to look up every entry, but there is nothing here for it to
+    // follow, so only the way there differs.
+    for args in [
+        &["-R"][..],
+        &["-RA"],
+        &["-R1"],
+        &["-RC"],
+        &["-Rx", "--group-directories-first"],
+    ] {
+        let fast = stdout(qls().args(args).arg(root));
+        let slow = stdout(qls().args(args).arg("-L").arg(root));
+        assert_eq!(fast, slow, "{args:?}");
+    }
+}
+
+#[test]
+fn test_closed_output() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..10_000 {
+        fs::write(dir.path().join(format!("{i:0>40}")), "").unwrap();
+    }
+    // More than a pipe holds, so that ls is still writing when the reader
+    // goes away.
+    let mut child = qls()
+        .arg(dir.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stderr.is_empty(), "{output:?}");
+}
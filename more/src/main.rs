@@ -0,0 +1,635 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::process;
+use std::sync::OnceLock;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use qoreutils_common::error::strerror;
+use regex::bytes::Regex;
+
+#[derive(Debug)]
+struct Config {
+    line_numbers: bool,
+    paths: Vec<String>,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Self {
+        Self {
+            line_numbers: options.get_flag("line_numbers"),
+            paths: options
+                .get_many::<String>("paths")
+                .map(|v| v.cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// What the pager needs from a terminal. The real one is /dev/tty in raw
+/// mode; the tests use a fixed-size fake with scripted keypresses.
+trait Terminal {
+    /// Returns (rows, columns).
+    fn size(&self) -> (usize, usize);
+    /// Returns the next keypress, or None once no more input is coming.
+    fn read_key(&mut self) -> Option<u8>;
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()>;
+}
+
+// Width of the "%6d " prefix -N puts in front of every line.
+const NUMBER_WIDTH: usize = 7;
+const CLEAR_SCREEN: &[u8] = b"\x1b[H\x1b[2J";
+const CLEAR_LINE: &[u8] = b"\r\x1b[K";
+
+struct Pager<I> {
+    /// The lines read so far, kept for going back.
+    lines: Vec<Vec<u8>>,
+    /// Where the rest of the lines come from, read only as far as shown.
+    rest: I,
+    /// How many lines there are, once `rest` has run out.
+    total: Option<usize>,
+    line_numbers: bool,
+    /// The first line on the screen.
+    top: usize,
+    /// The line after the last one on the screen.
+    end: usize,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Pager<I> {
+    fn new(rest: I, line_numbers: bool) -> Self {
+        Self {
+            lines: Vec::new(),
+            rest,
+            total: None,
+            line_numbers,
+            top: 0,
+            end: 0,
+        }
+    }
+
+    /// Whether there is a line `i`, reading up to it if need be.
+    fn has(&mut self, i: usize) -> bool {
+        while self.lines.len() <= i && self.total.is_none() {
+            match self.rest.next() {
+                Some(line) => self.lines.push(line),
+                None => self.total = Some(self.lines.len()),
+            }
+        }
+        i < self.lines.len()
+    }
+
+    /// How many screen rows a line takes once long lines wrap.
+    fn height(&self, line: &[u8], cols: usize) -> usize {
+        let mut width = if self.line_numbers { NUMBER_WIDTH } else { 0 };
+        for c in String::from_utf8_lossy(line).chars() {
+            width += match c {
+                '\t' => 8 - width % 8,
+                c if c.is_control() => 0,
+                _ => 1,
+            };
+        }
+        width.div_ceil(cols.max(1)).max(1)
+    }
+
+    /// Returns the first line of a screen whose last line is `end - 1`.
+    fn screen_start(&self, end: usize, rows: usize, cols: usize) -> usize {
+        let mut start = end;
+        let mut used = 0;
+        while start > 0 {
+            let h = self.height(&self.lines[start - 1], cols);
+            if used + h > rows && used > 0 {
+                break;
+            }
+            used += h;
+            start -= 1;
+        }
+        start
+    }
+
+    fn write_line<T: Terminal>(&self, term: &mut T, i: usize) -> io::Result<()> {
+        if self.line_numbers {
+            term.write_all(format!("{:>6} ", i + 1).as_bytes())?;
+        }
+        term.write_all(&self.lines[i])?;
+        term.write_all(b"\n")
+    }
+
+    /// Prints lines starting at `end` until `rows` screen rows are used.
+    fn advance<T: Terminal>(&mut self, term: &mut T, rows: usize, cols: usize) -> io::Result<()> {
+        let mut used = 0;
+        while self.has(self.end) {
+            let h = self.height(&self.lines[self.end], cols);
+            if used + h > rows && used > 0 {
+                break;
+            }
+            self.write_line(term, self.end)?;
+            used += h;
+            self.end += 1;
+        }
+        self.top = self.screen_start(self.end, rows, cols);
+        Ok(())
+    }
+
+    /// Clears the screen and draws a page starting at `top`.
+    fn redraw<T: Terminal>(
+        &mut self,
+        term: &mut T,
+        top: usize,
+        rows: usize,
+        cols: usize,
+    ) -> io::Result<()> {
+        term.write_all(CLEAR_SCREEN)?;
+        self.end = top;
+        self.advance(term, rows, cols)
+    }
+
+    /// Shows how far in the text is, once the end of it is known.
+    fn prompt<T: Terminal>(&self, term: &mut T, message: Option<&str>) -> io::Result<()> {
+        let mut text = String::from("\x1b[7m--More--");
+        if let Some(total) = self.total {
+            text += &format!("({}%)", self.end * 100 / total.max(1));
+        }
+        if let Some(m) = message {
+            text += &format!("[{m}]");
+        }
+        text += "\x1b[m";
+        term.write_all(text.as_bytes())
+    }
+
+    /// Reads a search pattern typed after '/', up to Enter.
+    fn read_pattern<T: Terminal>(term: &mut T) -> io::Result<Option<String>> {
+        term.write_all(CLEAR_LINE)?;
+        term.write_all(b"/")?;
+        let mut pattern = Vec::new();
+        loop {
+            match term.read_key() {
+                None | Some(0x1b) => return Ok(None),
+                Some(b'\n') | Some(b'\r') => break,
+                Some(0x7f) | Some(0x08) => {
+                    if pattern.pop().is_some() {
+                        term.write_all(b"\x08 \x08")?;
+                    }
+                }
+                Some(c) => {
+                    pattern.push(c);
+                    term.write_all(&[c])?;
+                }
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&pattern).into_owned()))
+    }
+
+    fn run<T: Terminal>(&mut self, term: &mut T) -> io::Result<()> {
+        let (rows, cols) = term.size();
+        // The last row is kept for the prompt.
+        let page = rows.saturating_sub(1).max(1);
+        self.advance(term, page, cols)?;
+
+        let mut message = None;
+        while self.has(self.end) {
+            self.prompt(term, message.take())?;
+            let key = term.read_key();
+            term.write_all(CLEAR_LINE)?;
+            match key {
+                None | Some(b'q') | Some(b'Q') => break,
+                Some(b' ') | Some(b'f') => self.advance(term, page, cols)?,
+                Some(b'\n') | Some(b'\r') => self.advance(term, 1, cols)?,
+                Some(b'b') | Some(0x02) => {
+                    let top = self.screen_start(self.top, page, cols);
+                    self.redraw(term, top, page, cols)?;
+                }
+                Some(b'/') => {
+                    let Some(pattern) = Self::read_pattern(term)? else {
+                        term.write_all(CLEAR_LINE)?;
+                        continue;
+                    };
+                    term.write_all(CLEAR_LINE)?;
+                    let re = match Regex::new(&pattern) {
+                        Ok(re) => re,
+                        Err(_) => {
+                            message = Some("Invalid pattern");
+                            continue;
+                        }
+                    };
+                    let mut found = self.top + 1;
+                    while self.has(found) && !re.is_match(&self.lines[found]) {
+                        found += 1;
+                    }
+                    match self.has(found).then_some(found) {
+                        Some(i) => {
+                            term.write_all(b"...skipping\n")?;
+                            self.end = i;
+                            self.advance(term, page, cols)?;
+                        }
+                        None => message = Some("Pattern not found"),
+                    }
+                }
+                Some(_) => message = Some("Press space to continue, 'q' to quit."),
+            }
+        }
+        Ok(())
+    }
+}
+
+// The terminal settings to put back on exit, kept where the SIGINT handler
+// can reach them.
+static SAVED_TERMIOS: OnceLock<(i32, libc::termios)> = OnceLock::new();
+
+fn restore_terminal() {
+    if let Some((fd, termios)) = SAVED_TERMIOS.get() {
+        unsafe { libc::tcsetattr(*fd, libc::TCSANOW, termios) };
+    }
+}
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    restore_terminal();
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        libc::raise(libc::SIGINT);
+    }
+}
+
+/// The controlling terminal in non-canonical, no-echo mode.
+struct Tty {
+    input: fs::File,
+    output: io::Stdout,
+}
+
+impl Tty {
+    fn open() -> io::Result<Self> {
+        // Keys come from the terminal itself since stdin may be the text.
+        let input = fs::File::open("/dev/tty")?;
+        let fd = input.as_raw_fd();
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        SAVED_TERMIOS.get_or_init(|| (fd, termios));
+        unsafe { libc::signal(libc::SIGINT, on_sigint as *const () as libc::sighandler_t) };
+
+        let mut raw = termios;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            input,
+            output: io::stdout(),
+        })
+    }
+}
+
+impl Drop for Tty {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+impl Terminal for Tty {
+    fn size(&self) -> (usize, usize) {
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0;
+        if ok && ws.ws_row > 0 && ws.ws_col > 0 {
+            (ws.ws_row as usize, ws.ws_col as usize)
+        } else {
+            (24, 80)
+        }
+    }
+
+    fn read_key(&mut self) -> Option<u8> {
+        let mut key = [0u8];
+        match self.input.read(&mut key) {
+            Ok(1) => Some(key[0]),
+            _ => None,
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.output.write_all(buf)?;
+        self.output.flush()
+    }
+}
+
+/// The text to page: stdin, or the files in turn, with more's banner in
+/// front of each when there are several of them. Files that cannot be
+/// read are reported and passed over.
+struct Input<'a> {
+    paths: std::slice::Iter<'a, String>,
+    banners: bool,
+    /// What is being read, and its name for messages.
+    reader: Option<(Box<dyn BufRead>, &'a str)>,
+    /// Lines to give out before reading on, which is where banners go.
+    pending: VecDeque<Vec<u8>>,
+    /// Whether everything could be read.
+    ok: bool,
+}
+
+impl<'a> Input<'a> {
+    fn new(paths: &'a [String]) -> Self {
+        let stdin = || (Box::new(io::stdin().lock()) as Box<dyn BufRead>, "stdin");
+        Self {
+            paths: paths.iter(),
+            banners: paths.len() > 1,
+            reader: paths.is_empty().then(stdin),
+            pending: VecDeque::new(),
+            ok: true,
+        }
+    }
+
+    /// Opens the next file that can be, queueing its banner. Returns false
+    /// once there are none left.
+    fn next_file(&mut self) -> bool {
+        while let Some(path) = self.paths.next() {
+            match fs::File::open(path) {
+                Ok(file) => {
+                    if self.banners {
+                        let rule = b"::::::::::::::".to_vec();
+                        self.pending
+                            .extend([rule.clone(), path.as_bytes().to_vec(), rule]);
+                    }
+                    self.reader = Some((Box::new(io::BufReader::new(file)), path));
+                    return true;
+                }
+                Err(e) => self.fail(path, e),
+            }
+        }
+        false
+    }
+
+    fn fail(&mut self, name: &str, e: io::Error) {
+        eprintln!("more: {name}: {}", strerror(&e));
+        self.ok = false;
+    }
+
+    /// Writes the input out byte for byte, banners and all, for when the
+    /// output is not a terminal.
+    fn copy_to<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        loop {
+            for line in self.pending.drain(..) {
+                out.write_all(&line)?;
+                out.write_all(b"\n")?;
+            }
+            let Some((reader, name)) = &mut self.reader else {
+                if !self.next_file() {
+                    return Ok(());
+                }
+                continue;
+            };
+            let n = match reader.fill_buf() {
+                Ok([]) => {
+                    self.reader = None;
+                    continue;
+                }
+                Ok(buf) => {
+                    out.write_all(buf)?;
+                    buf.len()
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let name = *name;
+                    self.reader = None;
+                    self.fail(name, e);
+                    continue;
+                }
+            };
+            reader.consume(n);
+        }
+    }
+}
+
+impl Iterator for Input<'_> {
+    type Item = Vec<u8>;
+
+    /// The next line, without its newline.
+    fn next(&mut self) -> Option<Vec<u8>> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(line);
+            }
+            let Some((reader, name)) = &mut self.reader else {
+                if !self.next_file() {
+                    return None;
+                }
+                continue;
+            };
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => self.reader = None,
+                Ok(_) => {
+                    if line.ends_with(b"\n") {
+                        line.pop();
+                    }
+                    return Some(line);
+                }
+                Err(e) => {
+                    let name = *name;
+                    self.reader = None;
+                    self.fail(name, e);
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let matches = Command::new("more")
+        .arg(
+            Arg::new("line_numbers")
+                .short('N')
+                .long("line-numbers")
+                .action(ArgAction::SetTrue)
+                .help("Number the lines of the output."),
+        )
+        .arg(Arg::new("paths").action(ArgAction::Append))
+        .get_matches();
+    let config = Config::from(&matches);
+    let mut input = Input::new(&config.paths);
+
+    let is_tty = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    let res = match is_tty.then(Tty::open) {
+        Some(Ok(mut tty)) => Pager::new(&mut input, config.line_numbers).run(&mut tty),
+        // Not a terminal: behave like cat.
+        _ => input.copy_to(&mut io::stdout().lock()),
+    };
+    if let Err(e) = res {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("more: {}", strerror(&e));
+        }
+        process::exit(1);
+    }
+    if !input.ok {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeTerminal {
+        rows: usize,
+        cols: usize,
+        keys: Vec<u8>,
+        screen: Vec<u8>,
+    }
+
+    impl FakeTerminal {
+        fn new(rows: usize, cols: usize, keys: &str) -> Self {
+            Self {
+                rows,
+                cols,
+                keys: keys.bytes().rev().collect(),
+                screen: Vec::new(),
+            }
+        }
+
+        /// The text written, with prompts and escape sequences removed.
+        fn text(&self) -> String {
+            let s = String::from_utf8_lossy(&self.screen);
+            let re = regex::Regex::new(r"\x1b\[7m.*?\x1b\[m|\x1b\[[0-9;]*[A-Za-z]|\r").unwrap();
+            re.replace_all(&s, "").into_owned()
+        }
+    }
+
+    impl Terminal for FakeTerminal {
+        fn size(&self) -> (usize, usize) {
+            (self.rows, self.cols)
+        }
+
+        fn read_key(&mut self) -> Option<u8> {
+            self.keys.pop()
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+            self.screen.extend_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    fn numbered(n: usize) -> Vec<Vec<u8>> {
+        (1..=n).map(|i| format!("line {i}").into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_first_page_then_quit() {
+        let lines = numbered(10);
+        let mut term = FakeTerminal::new(5, 80, "q");
+        Pager::new(lines.into_iter(), false).run(&mut term).unwrap();
+        assert_eq!(term.text(), "line 1\nline 2\nline 3\nline 4\n");
+        // How far in that is cannot be known before the end has been read.
+        assert!(String::from_utf8_lossy(&term.screen).contains("--More--\x1b[m"));
+    }
+
+    #[test]
+    fn test_space_and_enter() {
+        let lines = numbered(10);
+        let mut term = FakeTerminal::new(5, 80, " \nq");
+        Pager::new(lines.into_iter(), false).run(&mut term).unwrap();
+        let expected: String = (1..=9).map(|i| format!("line {i}\n")).collect();
+        assert_eq!(term.text(), expected);
+    }
+
+    #[test]
+    fn test_stops_at_end() {
+        let lines = numbered(6);
+        let mut term = FakeTerminal::new(5, 80, " q");
+        Pager::new(lines.into_iter(), false).run(&mut term).unwrap();
+        // The space reaches the end, so 'q' is never read.
+        assert_eq!(term.keys, vec![b'q']);
+    }
+
+    #[test]
+    fn test_wrapped_lines_use_more_rows() {
+        let lines = vec![vec![b'x'; 25], b"short".to_vec(), b"next".to_vec()];
+        let mut term = FakeTerminal::new(4, 10, "q");
+        Pager::new(lines.into_iter(), false).run(&mut term).unwrap();
+        // 25 columns on a 10 column screen take all three rows.
+        assert_eq!(term.text(), format!("{}\n", "x".repeat(25)));
+    }
+
+    #[test]
+    fn test_back() {
+        let lines = numbered(10);
+        let mut term = FakeTerminal::new(4, 80, " bq");
+        Pager::new(lines.into_iter(), false).run(&mut term).unwrap();
+        assert!(term.text().ends_with("line 6\nline 1\nline 2\nline 3\n"));
+    }
+
+    #[test]
+    fn test_search() {
+        let lines = numbered(20);
+        let mut term = FakeTerminal::new(4, 80, "/ne 1[5]\nq");
+        Pager::new(lines.clone().into_iter(), false)
+            .run(&mut term)
+            .unwrap();
+        assert!(term
+            .text()
+            .ends_with("...skipping\nline 15\nline 16\nline 17\n"));
+
+        let mut term = FakeTerminal::new(4, 80, "/nomatch\nq");
+        Pager::new(lines.into_iter(), false).run(&mut term).unwrap();
+        // Looking for it read to the end.
+        assert!(String::from_utf8_lossy(&term.screen).contains("--More--(15%)[Pattern not found]"));
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let lines = numbered(3);
+        let mut term = FakeTerminal::new(5, 80, "");
+        Pager::new(lines.into_iter(), true).run(&mut term).unwrap();
+        assert_eq!(term.text(), "     1 line 1\n     2 line 2\n     3 line 3\n");
+    }
+
+    #[test]
+    fn test_reads_only_what_it_shows() {
+        let mut read = 0;
+        let lines = numbered(1000).into_iter().inspect(|_| read += 1);
+        let mut term = FakeTerminal::new(5, 80, "q");
+        Pager::new(lines, false).run(&mut term).unwrap();
+        // The page, and one more to know there is more.
+        assert_eq!(read, 5);
+    }
+
+    #[test]
+    fn test_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a").display().to_string();
+        let b = dir.path().join("b").display().to_string();
+        let missing = dir.path().join("missing").display().to_string();
+        fs::write(&a, "one\ntwo").unwrap();
+        fs::write(&b, "three\n").unwrap();
+
+        // Not a terminal: nothing is added, not even a last newline.
+        let mut out = Vec::new();
+        let one = [a.clone()];
+        let mut input = Input::new(&one);
+        input.copy_to(&mut out).unwrap();
+        assert_eq!(out, b"one\ntwo");
+        assert!(input.ok);
+
+        let rule = "::::::::::::::";
+        let paths = [a.clone(), missing, b.clone()];
+        let mut out = Vec::new();
+        let mut input = Input::new(&paths);
+        input.copy_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("{rule}\n{a}\n{rule}\none\ntwo{rule}\n{b}\n{rule}\nthree\n")
+        );
+        assert!(!input.ok);
+
+        let mut input = Input::new(&paths);
+        let lines: Vec<String> = input
+            .by_ref()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            [rule, &a, rule, "one", "two", rule, &b, rule, "three"]
+        );
+        assert!(!input.ok);
+    }
+}
@@ -0,0 +1,323 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use qoreutils_common::error::strerror;
+use qoreutils_common::mode::{mode_string, type_char};
+use qoreutils_common::users::{group_name, user_name};
+
+// The same limit the kernel puts on nested symlinks.
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+#[derive(Debug, Default)]
+struct Config {
+    modes: bool,
+    owners: bool,
+    mountpoints: bool,
+    nosymlinks: bool,
+    paths: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Self {
+        let long = options.get_flag("long");
+        Self {
+            modes: long || options.get_flag("modes"),
+            owners: long || options.get_flag("owners"),
+            mountpoints: options.get_flag("mountpoints"),
+            nosymlinks: options.get_flag("nosymlinks"),
+            paths: options
+                .get_many::<PathBuf>("paths")
+                .map(|v| v.cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+struct Resolver<'a, W: Write> {
+    config: &'a Config,
+    out: W,
+    /// The (dev, ino) of every symlink currently being followed.
+    links: Vec<(u64, u64)>,
+}
+
+fn is_mountpoint(path: &Path, meta: &fs::Metadata) -> bool {
+    match fs::metadata(path.join("..")) {
+        Ok(parent) => parent.dev() != meta.dev() || parent.ino() == meta.ino(),
+        Err(_) => false,
+    }
+}
+
+impl<W: Write> Resolver<'_, W> {
+    fn entry(
+        &mut self,
+        level: usize,
+        full: &Path,
+        name: &str,
+        meta: &fs::Metadata,
+    ) -> io::Result<()> {
+        let indent = " ".repeat(level * 2 + 1);
+        let mount = self.config.mountpoints && meta.is_dir() && is_mountpoint(full, meta);
+        let mut mode = match self.config.modes {
+            true => mode_string(meta.mode()),
+            false => type_char(meta.mode()).to_string(),
+        };
+        if mount {
+            mode.replace_range(0..1, "D");
+        }
+        write!(self.out, "{indent}{mode} ")?;
+        if self.config.owners {
+            let user = user_name(meta.uid()).unwrap_or_else(|| meta.uid().to_string());
+            let group = group_name(meta.gid()).unwrap_or_else(|| meta.gid().to_string());
+            write!(self.out, "{user} {group} ")?;
+        }
+        write!(self.out, "{name}")
+    }
+
+    /// Prints a component that could not be looked at.
+    fn unknown(&mut self, level: usize, name: &str) -> io::Result<()> {
+        let indent = " ".repeat(level * 2 + 1);
+        let mode = if self.config.modes { "??????????" } else { "?" };
+        write!(self.out, "{indent}{mode} ")?;
+        if self.config.owners {
+            write!(self.out, "? ? ")?;
+        }
+        writeln!(self.out, "{name}")
+    }
+
+    /// Prints one line per component of `path`, resolved relative to `base`,
+    /// following symlinks one indentation level deeper. Returns the directory
+    /// the path ends up in.
+    fn walk(
+        &mut self,
+        path: &Path,
+        mut base: PathBuf,
+        level: usize,
+    ) -> io::Result<Result<PathBuf, String>> {
+        let components: Vec<Component> = path.components().collect();
+        for (i, component) in components.iter().enumerate() {
+            let (full, name) = match component {
+                Component::RootDir => (PathBuf::from("/"), "/".to_string()),
+                Component::CurDir => (base.clone(), ".".to_string()),
+                Component::ParentDir => (base.join(".."), "..".to_string()),
+                Component::Normal(n) => (base.join(n), n.to_string_lossy().into_owned()),
+                Component::Prefix(_) => continue,
+            };
+            let meta = match fs::symlink_metadata(&full) {
+                Ok(meta) => meta,
+                Err(e) => {
+                    // Later components cannot be looked at either, but are
+                    // still worth showing.
+                    self.unknown(level, &name)?;
+                    for rest in &components[i + 1..] {
+                        self.unknown(level, &rest.as_os_str().to_string_lossy())?;
+                    }
+                    return Ok(Err(format!("{}: {e}", full.display())));
+                }
+            };
+
+            if !meta.file_type().is_symlink() || self.config.nosymlinks {
+                self.entry(level, &full, &name, &meta)?;
+                writeln!(self.out)?;
+                base = full;
+                continue;
+            }
+
+            self.entry(level, &full, &name, &meta)?;
+            // It may have gone since it was looked at.
+            let target = match fs::read_link(&full) {
+                Ok(target) => target,
+                Err(e) => {
+                    writeln!(self.out)?;
+                    return Ok(Err(format!("{}: {}", full.display(), strerror(&e))));
+                }
+            };
+            writeln!(self.out, " -> {}", target.display())?;
+            let key = (meta.dev(), meta.ino());
+            if self.links.contains(&key) {
+                return Ok(Err(format!("{}: symlink loop detected", full.display())));
+            }
+            if self.links.len() >= MAX_SYMLINK_DEPTH {
+                return Ok(Err(format!(
+                    "{}: maximum symlinks depth exceeded",
+                    full.display()
+                )));
+            }
+            self.links.push(key);
+            let resolved = self.walk(&target, base.clone(), level + 1)?;
+            self.links.pop();
+            base = match resolved {
+                Ok(resolved) => resolved,
+                Err(e) => return Ok(Err(e)),
+            };
+        }
+        Ok(Ok(base))
+    }
+
+    /// Explains one operand. Returns false if some component failed.
+    fn namei(&mut self, path: &Path) -> io::Result<bool> {
+        writeln!(self.out, "f: {}", path.display())?;
+        self.links.clear();
+        match self.walk(path, PathBuf::from("."), 0)? {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                eprintln!("namei: {e}");
+                Ok(false)
+            }
+        }
+    }
+}
+
+fn main() {
+    let matches = Command::new("namei")
+        .arg(
+            Arg::new("long")
+                .short('l')
+                .long("long")
+                .action(ArgAction::SetTrue)
+                .help("Use a long listing format (same as -m -o)."),
+        )
+        .arg(
+            Arg::new("modes")
+                .short('m')
+                .long("modes")
+                .action(ArgAction::SetTrue)
+                .help("Show the mode bits of each file."),
+        )
+        .arg(
+            Arg::new("owners")
+                .short('o')
+                .long("owners")
+                .action(ArgAction::SetTrue)
+                .help("Show owner and group name of each file."),
+        )
+        .arg(
+            Arg::new("mountpoints")
+                .short('x')
+                .long("mountpoints")
+                .action(ArgAction::SetTrue)
+                .help("Show mount point directories with a 'D'."),
+        )
+        .arg(
+            Arg::new("nosymlinks")
+                .short('n')
+                .long("nosymlinks")
+                .action(ArgAction::SetTrue)
+                .help("Don't follow symlinks."),
+        )
+        .arg(
+            Arg::new("paths")
+                .required(true)
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .get_matches();
+    let config = Config::from(&matches);
+    let mut resolver = Resolver {
+        config: &config,
+        out: io::stdout().lock(),
+        links: Vec::new(),
+    };
+    let mut ok = true;
+    for path in &config.paths {
+        match resolver.namei(path) {
+            Ok(res) => ok &= res,
+            Err(e) => {
+                eprintln!("namei: {e}");
+                process::exit(1);
+            }
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    fn run(config: &Config, path: &Path) -> (String, bool) {
+        let mut out = Vec::new();
+        let mut resolver = Resolver {
+            config,
+            out: &mut out,
+            links: Vec::new(),
+        };
+        let ok = resolver.namei(path).unwrap();
+        (String::from_utf8(out).unwrap(), ok)
+    }
+
+    #[test]
+    fn test_symlinked_middle_component() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("a/b/file"), "").unwrap();
+        symlink("a/b", dir.path().join("link")).unwrap();
+
+        let path = dir.path().join("link/file");
+        let (out, ok) = run(&Config::default(), &path);
+        assert!(ok);
+        let lines: Vec<&str> = out.lines().collect();
+        let n = lines.len();
+        assert_eq!(lines[0], format!("f: {}", path.display()));
+        assert_eq!(lines[1], " d /");
+        assert_eq!(
+            lines[n - 4..],
+            [" l link -> a/b", "   d a", "   d b", " - file"]
+        );
+    }
+
+    #[test]
+    fn test_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        let config = Config {
+            modes: true,
+            ..Default::default()
+        };
+        let (out, _) = run(&config, &file);
+        assert!(out.ends_with(" -rw-r----- file\n"));
+    }
+
+    #[test]
+    fn test_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        symlink("b", dir.path().join("a")).unwrap();
+        symlink("a", dir.path().join("b")).unwrap();
+        let (out, ok) = run(&Config::default(), &dir.path().join("a"));
+        assert!(!ok);
+        assert!(out.ends_with(" l a -> b\n   l b -> a\n     l a -> b\n"));
+    }
+
+    #[test]
+    fn test_unreadable_component() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            modes: true,
+            owners: true,
+            ..Default::default()
+        };
+        let (out, ok) = run(&config, &dir.path().join("missing/deeper"));
+        assert!(!ok);
+        assert!(out.ends_with(" ?????????? ? ? missing\n ?????????? ? ? deeper\n"));
+
+        let locked = dir.path().join("locked");
+        fs::create_dir_all(locked.join("inner")).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Only if the mode keeps this user out is there anything to see.
+        if fs::read_dir(&locked).is_ok() {
+            return;
+        }
+        let (out, ok) = run(&Config::default(), &locked.join("inner"));
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(!ok);
+        assert!(out.ends_with(" d locked\n ? inner\n"));
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+
+// util-linux rename's exit codes.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_SOME_FAILED: i32 = 2;
+const EXIT_NOTHING_RENAMED: i32 = 4;
+
+#[derive(Debug)]
+enum Mode {
+    Substitute {
+        from: Vec<u8>,
+        to: Vec<u8>,
+        all: bool,
+    },
+    Lowercase,
+    Uppercase,
+}
+
+#[derive(Debug)]
+struct Config {
+    mode: Mode,
+    verbose: bool,
+    dry_run: bool,
+    files: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Result<Self, String> {
+        let mut args: Vec<OsString> = options
+            .get_many::<OsString>("args")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default();
+        let mode = if options.get_flag("lowercase") {
+            Mode::Lowercase
+        } else if options.get_flag("uppercase") {
+            Mode::Uppercase
+        } else {
+            if args.len() < 2 {
+                return Err("not enough arguments".to_string());
+            }
+            let rest = args.split_off(2);
+            let to = args.pop().unwrap().into_vec();
+            let from = args.pop().unwrap().into_vec();
+            args = rest;
+            if from.is_empty() {
+                return Err("FROM must not be empty".to_string());
+            }
+            Mode::Substitute {
+                from,
+                to,
+                all: options.get_flag("all"),
+            }
+        };
+        if args.is_empty() {
+            return Err("no files given".to_string());
+        }
+        Ok(Self {
+            mode,
+            verbose: options.get_flag("verbose"),
+            dry_run: options.get_flag("no_act"),
+            files: args.into_iter().map(PathBuf::from).collect(),
+        })
+    }
+}
+
+fn replace(name: &[u8], from: &[u8], to: &[u8], all: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(i) = rest.windows(from.len()).position(|w| w == from) {
+        out.extend_from_slice(&rest[..i]);
+        out.extend_from_slice(to);
+        rest = &rest[i + from.len()..];
+        if !all {
+            break;
+        }
+    }
+    out.extend_from_slice(rest);
+    out
+}
+
+/// Returns the new final path component, or None when it stays the same.
+/// Only the file name is rewritten so a match in a directory part of the
+/// operand cannot move the file somewhere else.
+fn new_name(name: &[u8], mode: &Mode) -> Option<Vec<u8>> {
+    let renamed = match mode {
+        Mode::Substitute { from, to, all } => replace(name, from, to, *all),
+        Mode::Lowercase => name.to_ascii_lowercase(),
+        Mode::Uppercase => name.to_ascii_uppercase(),
+    };
+    (renamed != name).then_some(renamed)
+}
+
+#[derive(Debug, PartialEq)]
+struct Rename {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+/// Works out every rename before touching the filesystem, so that a
+/// collision leaves all files where they were.
+fn plan(config: &Config) -> Result<Vec<Rename>, String> {
+    let mut renames = Vec::new();
+    let mut targets: HashMap<PathBuf, &PathBuf> = HashMap::new();
+    for path in &config.files {
+        let Some(name) = path.file_name() else {
+            return Err(format!("{}: not a file name", path.display()));
+        };
+        let Some(renamed) = new_name(name.as_bytes(), &config.mode) else {
+            continue;
+        };
+        let to = path.with_file_name(OsStr::from_bytes(&renamed));
+        if let Some(other) = targets.insert(to.clone(), path) {
+            return Err(format!(
+                "{} and {} would both be renamed to {}",
+                other.display(),
+                path.display(),
+                to.display()
+            ));
+        }
+        if fs::symlink_metadata(&to).is_ok() {
+            return Err(format!(
+                "{}: not overwriting {}",
+                path.display(),
+                to.display()
+            ));
+        }
+        renames.push(Rename {
+            from: path.clone(),
+            to,
+        });
+    }
+    Ok(renames)
+}
+
+/// Renames `from` to `to` unless something is at `to` by now, which may
+/// have turned up since the plan was made. Only Linux can do this in one
+/// step; elsewhere there is a window between the check and the rename.
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let c_from = CString::new(from.as_os_str().as_bytes())?;
+        let c_to = CString::new(to.as_os_str().as_bytes())?;
+        let res = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                c_from.as_ptr(),
+                libc::AT_FDCWD,
+                c_to.as_ptr(),
+                libc::RENAME_NOREPLACE,
+            )
+        };
+        if res == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        // Old kernels and some file systems do not support the flag.
+        if !matches!(e.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) {
+            return Err(e);
+        }
+    }
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(io::ErrorKind::AlreadyExists.into());
+    }
+    fs::rename(from, to)
+}
+
+fn describe<W: Write>(out: &mut W, rename: &Rename) -> io::Result<()> {
+    out.write_all(b"`")?;
+    out.write_all(rename.from.as_os_str().as_bytes())?;
+    out.write_all(b"' -> `")?;
+    out.write_all(rename.to.as_os_str().as_bytes())?;
+    out.write_all(b"'\n")
+}
+
+fn main() {
+    let matches = Command::new("rename")
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("Explain what is being done."),
+        )
+        .arg(
+            Arg::new("no_act")
+                .short('n')
+                .long("no-act")
+                .action(ArgAction::SetTrue)
+                .help("Show what would be renamed without renaming anything."),
+        )
+        .arg(
+            Arg::new("no_overwrite")
+                .short('o')
+                .long("no-overwrite")
+                .action(ArgAction::SetTrue)
+                .help("Do not overwrite existing files (always the case)."),
+        )
+        .arg(
+            Arg::new("all")
+                .short('a')
+                .long("all")
+                .action(ArgAction::SetTrue)
+                .help("Replace all occurrences of FROM."),
+        )
+        .arg(
+            Arg::new("lowercase")
+                .short('l')
+                .long("lowercase")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["uppercase", "all"])
+                .help("Lowercase the file names instead of substituting."),
+        )
+        .arg(
+            Arg::new("uppercase")
+                .short('u')
+                .long("uppercase")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("all")
+                .help("Uppercase the file names instead of substituting."),
+        )
+        .arg(
+            Arg::new("args")
+                .value_name("FROM TO FILES")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(OsString)),
+        )
+        .get_matches();
+    let config = match Config::from(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("rename: {e}");
+            process::exit(EXIT_FAILURE);
+        }
+    };
+
+    let renames = match plan(&config) {
+        Ok(renames) => renames,
+        Err(e) => {
+            eprintln!("rename: {e}");
+            process::exit(EXIT_FAILURE);
+        }
+    };
+    if renames.is_empty() {
+        process::exit(EXIT_NOTHING_RENAMED);
+    }
+
+    let mut out = io::stdout().lock();
+    let mut failed = 0;
+    for rename in &renames {
+        if !config.dry_run {
+            if let Err(e) = rename_no_replace(&rename.from, &rename.to) {
+                match e.kind() {
+                    io::ErrorKind::AlreadyExists => eprintln!(
+                        "rename: {}: not overwriting {}",
+                        rename.from.display(),
+                        rename.to.display()
+                    ),
+                    _ => eprintln!("rename: {}: {e}", rename.from.display()),
+                }
+                failed += 1;
+                continue;
+            }
+        }
+        if config.verbose || config.dry_run {
+            describe(&mut out, rename).unwrap_or_else(|e| eprintln!("rename: {e}"));
+        }
+    }
+    match failed {
+        0 => {}
+        n if n == renames.len() => process::exit(EXIT_FAILURE),
+        _ => process::exit(EXIT_SOME_FAILED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn substitute(from: &str, to: &str, all: bool, files: Vec<PathBuf>) -> Config {
+        Config {
+            mode: Mode::Substitute {
+                from: from.as_bytes().to_vec(),
+                to: to.as_bytes().to_vec(),
+                all,
+            },
+            verbose: false,
+            dry_run: true,
+            files,
+        }
+    }
+
+    #[test]
+    fn test_replace() {
+        assert_eq!(replace(b"a.b.c", b".", b"_", false), b"a_b.c");
+        assert_eq!(replace(b"a.b.c", b".", b"_", true), b"a_b_c");
+        assert_eq!(
+            new_name(b"ReadMe", &Mode::Uppercase),
+            Some(b"README".to_vec())
+        );
+        assert_eq!(new_name(b"abc", &Mode::Lowercase), None);
+    }
+
+    #[test]
+    fn test_dry_run_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("photo.jpeg");
+        fs::write(&file, "").unwrap();
+        let config = substitute(".jpeg", ".jpg", false, vec![file.clone()]);
+        let renames = plan(&config).unwrap();
+        let mut out = Vec::new();
+        describe(&mut out, &renames[0]).unwrap();
+        let expected = format!(
+            "`{}' -> `{}'\n",
+            file.display(),
+            dir.path().join("photo.jpg").display()
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn test_two_sources_one_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let upper = dir.path().join("README");
+        let mixed = dir.path().join("ReadMe");
+        fs::write(&upper, "").unwrap();
+        fs::write(&mixed, "").unwrap();
+        let mut config = substitute("", "", false, vec![upper.clone(), mixed.clone()]);
+        config.mode = Mode::Lowercase;
+        let err = plan(&config).unwrap_err();
+        assert!(err.contains("would both be renamed to"));
+    }
+
+    #[test]
+    fn test_existing_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        fs::write(&a, "").unwrap();
+        fs::write(&b, "").unwrap();
+        let config = substitute("a", "b", false, vec![a.clone()]);
+        let err = plan(&config).unwrap_err();
+        assert!(err.contains("not overwriting"));
+
+        // Nor if it turns up after the plan was made.
+        fs::remove_file(&b).unwrap();
+        let renames = plan(&config).unwrap();
+        fs::write(&b, "b").unwrap();
+        let err = rename_no_replace(&renames[0].from, &renames[0].to).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(a.exists());
+        assert_eq!(fs::read(&b).unwrap(), b"b");
+    }
+
+    #[test]
+    fn test_non_utf8_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        let file = dir.path().join(name);
+        fs::write(&file, "").unwrap();
+        let mut config = substitute(".txt", ".md", false, vec![file.clone()]);
+        config.dry_run = false;
+        for rename in plan(&config).unwrap() {
+            rename_no_replace(&rename.from, &rename.to).unwrap();
+        }
+        assert!(dir.path().join(OsStr::from_bytes(b"caf\xe9.md")).exists());
+        assert!(!file.exists());
+    }
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// fsync each file.
+    File,
+    /// fdatasync each file.
+    Data,
+    /// syncfs the filesystem each file is on.
+    FileSystem,
+}
+
+#[derive(Debug)]
+struct Config {
+    mode: Mode,
+    paths: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Self {
+        let mode = if options.get_flag("data") {
+            Mode::Data
+        } else if options.get_flag("file_system") {
+            Mode::FileSystem
+        } else {
+            Mode::File
+        };
+        Self {
+            mode,
+            paths: options
+                .get_many::<PathBuf>("paths")
+                .map(|v| v.cloned().collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn syncfs(file: &fs::File) -> io::Result<()> {
+    check(unsafe { libc::syncfs(file.as_raw_fd()) })
+}
+
+/// Without syncfs the best we can do is sync everything.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn syncfs(_: &fs::File) -> io::Result<()> {
+    unsafe { libc::sync() };
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn fdatasync(file: &fs::File) -> io::Result<()> {
+    check(unsafe { libc::fdatasync(file.as_raw_fd()) })
+}
+
+#[cfg(target_os = "macos")]
+fn fdatasync(file: &fs::File) -> io::Result<()> {
+    file.sync_all()
+}
+
+/// Opens a file only to sync it. Read access is enough for fsync, but some
+/// files can only be opened for writing.
+fn open(path: &Path) -> io::Result<fs::File> {
+    let read = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path);
+    match read {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path),
+        res => res,
+    }
+}
+
+fn sync_path(path: &Path, mode: Mode) -> Result<(), String> {
+    let file = open(path).map_err(|e| format!("error opening '{}': {e}", path.display()))?;
+    let res = match mode {
+        Mode::File => check(unsafe { libc::fsync(file.as_raw_fd()) }),
+        Mode::Data => fdatasync(&file),
+        Mode::FileSystem => syncfs(&file),
+    };
+    res.map_err(|e| format!("error syncing '{}': {e}", path.display()))
+}
+
+fn main() {
+    let matches = Command::new("sync")
+        .arg(
+            Arg::new("data")
+                .short('d')
+                .long("data")
+                .action(ArgAction::SetTrue)
+                .requires("paths")
+                .help("Sync only file data, no unneeded metadata."),
+        )
+        .arg(
+            Arg::new("file_system")
+                .short('f')
+                .long("file-system")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("data")
+                .help("Sync the file systems that contain the files."),
+        )
+        .arg(
+            Arg::new("paths")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .get_matches();
+    let config = Config::from(&matches);
+
+    if config.paths.is_empty() {
+        unsafe { libc::sync() };
+        return;
+    }
+
+    let mut ok = true;
+    for path in &config.paths {
+        if let Err(e) = sync_path(path, config.mode) {
+            eprintln!("sync: {e}");
+            ok = false;
+        }
+    }
+    if !ok {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "data").unwrap();
+        for mode in [Mode::File, Mode::Data, Mode::FileSystem] {
+            assert_eq!(sync_path(&path, mode), Ok(()));
+        }
+        assert_eq!(sync_path(dir.path(), Mode::File), Ok(()));
+    }
+
+    #[test]
+    fn test_removed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "data").unwrap();
+        fs::remove_file(&path).unwrap();
+        let err = sync_path(&path, Mode::File).unwrap_err();
+        assert!(err.starts_with(&format!("error opening '{}': ", path.display())));
+    }
+}
@@ -0,0 +1,33 @@
+use std::fs;
+use std::process::{Command, Stdio};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// Roughly how much log-like text is pushed through qtee per run.
+const SIZE: usize = 64 << 20;
+
+fn bench_throughput(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input");
+    let output = dir.path().join("output");
+    fs::write(&input, b"a line of log output\n".repeat(SIZE / 21)).unwrap();
+
+    let mut group = c.benchmark_group("throughput");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(fs::metadata(&input).unwrap().len()));
+    group.bench_function("file", |b| {
+        b.iter(|| {
+            let status = Command::new(env!("CARGO_BIN_EXE_qtee"))
+                .arg(&output)
+                .stdin(fs::File::open(&input).unwrap())
+                .stdout(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_throughput);
+criterion_main!(benches);
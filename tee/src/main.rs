@@ -1,51 +1,506 @@
-use std::env;
+use std::cell::Cell;
 use std::fs;
-use std::io;
-use std::path::Path;
+use std::io::{self, Read, Seek, Write};
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use clap::{Arg, ArgAction, ArgMatches, Command};
+use chrono::Local;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use qoreutils_common::error::strerror;
+use qoreutils_common::size::parse_size;
+use qoreutils_common::strftime;
+
+/// How much is read from stdin at a time, and how much each file output
+/// buffers.
+const BUFFER_SIZE: usize = 128 * 1024;
+
+/// What standard output is called in messages.
+const STDOUT: &str = "standard output";
+
+/// How often a FIFO output is checked for a reader.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What to do when writing to an output fails, as with --output-error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputError {
+    /// The default: a broken pipe passes without a word, like the SIGPIPE
+    /// that would end a C tee.
+    Sigpipe,
+    Warn,
+    WarnNopipe,
+    Exit,
+    ExitNopipe,
+}
+
+impl OutputError {
+    /// Whether `e` is worth a message and the exit status.
+    fn counts(self, e: &io::Error) -> bool {
+        e.kind() != io::ErrorKind::BrokenPipe || matches!(self, Self::Warn | Self::Exit)
+    }
+
+    fn exits(self) -> bool {
+        matches!(self, Self::Exit | Self::ExitNopipe)
+    }
+}
 
 #[derive(Debug)]
 struct Config {
     append: bool,
+    flush: bool,
+    line_atomic: bool,
+    mkdirs: bool,
+    /// The strftime format of the timestamp put in front of each line.
+    timestamp: Option<String>,
+    /// Whether stdout gets the timestamps too, not just the files.
+    timestamp_all: bool,
+    verbose: bool,
+    /// Where to read from instead of stdin.
+    input: Option<PathBuf>,
+    /// How long to wait for a FIFO output to get a reader, if not forever.
+    pipe_timeout: Option<Duration>,
+    /// The size at which file outputs are rotated.
+    max_size: Option<u64>,
+    /// How many rotated files to keep.
+    rotate: usize,
+    /// Sockets to send another copy to, with the names they were given by.
+    sockets: Vec<(String, Socket)>,
     // ignore_sigint: bool,
+    output_error: OutputError,
+}
+
+#[derive(Debug)]
+enum Socket {
+    Unix(PathBuf),
+    /// A host and port.
+    Tcp(String),
+}
+
+impl Socket {
+    /// Parses "unix:PATH" or "tcp:HOST:PORT".
+    fn parse(spec: &str) -> Result<Self, String> {
+        if let Some(path) = spec.strip_prefix("unix:") {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        match spec.strip_prefix("tcp:") {
+            Some(addr) if addr.contains(':') => Ok(Self::Tcp(addr.to_string())),
+            _ => Err(format!(
+                "invalid socket '{spec}': use unix:PATH or tcp:HOST:PORT"
+            )),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Box<dyn io::Write>> {
+        Ok(match self {
+            Self::Unix(path) => Box::new(UnixStream::connect(path)?),
+            Self::Tcp(addr) => Box::new(TcpStream::connect(addr)?),
+        })
+    }
 }
 
 impl Config {
-    pub fn from(options: &ArgMatches) -> Self {
-        Self {
+    pub fn from(options: &ArgMatches) -> Result<Self, String> {
+        let output_error = match options
+            .get_one::<String>("output_error")
+            .map(String::as_str)
+        {
+            Some("warn") => OutputError::Warn,
+            Some("exit") => OutputError::Exit,
+            Some("exit-nopipe") => OutputError::ExitNopipe,
+            Some(_) => OutputError::WarnNopipe,
+            None if options.get_flag("nopipe") => OutputError::WarnNopipe,
+            None => OutputError::Sigpipe,
+        };
+        let timestamp = options.get_one::<String>("timestamp").cloned();
+        if let Some(format) = &timestamp {
+            if !strftime::is_valid(format) {
+                return Err(format!("invalid timestamp format '{format}'"));
+            }
+        }
+        let pipe_timeout = match options.get_one::<f64>("pipe_timeout") {
+            Some(secs) if *secs < 0.0 || !secs.is_finite() => {
+                return Err(format!("invalid pipe timeout: {secs}"))
+            }
+            Some(secs) => Some(Duration::from_secs_f64(*secs)),
+            None => None,
+        };
+        Ok(Self {
             append: options.get_flag("append"),
+            flush: options.get_flag("flush"),
+            line_atomic: options.get_flag("line_atomic"),
+            mkdirs: options.get_flag("mkdirs"),
+            timestamp,
+            timestamp_all: options.get_flag("timestamp_all"),
+            verbose: options.get_flag("verbose"),
+            input: options.get_one::<PathBuf>("input").cloned(),
+            pipe_timeout,
+            max_size: options
+                .get_one::<String>("max_size")
+                .map(|s| match parse_size(s) {
+                    Ok(0) => Err(format!("invalid size '{s}'")),
+                    size => size.map_err(|e| e.to_string()),
+                })
+                .transpose()?,
+            rotate: options.get_one::<usize>("rotate").copied().unwrap_or(1),
+            sockets: options
+                .get_many::<String>("socket")
+                .into_iter()
+                .flatten()
+                .map(|spec| Ok((spec.clone(), Socket::parse(spec)?)))
+                .collect::<Result<_, String>>()?,
             // ignore_sigint: options.get_flag("ignore_sigint"),
+            output_error,
+        })
+    }
+}
+
+/// One destination, with the name its errors are reported under.
+struct Output {
+    name: String,
+    /// None once writing to it has failed.
+    writer: Option<Box<dyn Sink>>,
+    /// How many bytes have made it through, for --verbose.
+    written: Rc<Cell<u64>>,
+}
+
+impl Output {
+    fn file(path: &Path, file: fs::File, config: &Config) -> Self {
+        let written = Rc::default();
+        // Only regular files are rotated.
+        let regular = file.metadata().is_ok_and(|m| m.is_file());
+        let file: Box<dyn io::Write> = match config.max_size {
+            Some(max_size) if regular => Box::new(Rotating {
+                path: path.to_path_buf(),
+                size: file.metadata().map_or(0, |m| m.len()),
+                file,
+                max_size,
+                keep: config.rotate,
+                append: config.append,
+            }),
+            _ => Box::new(file),
+        };
+        // The buffer is never bigger than a rotated file, so that what it
+        // holds back fits in one.
+        let buffer_size = match config.max_size {
+            Some(max_size) if regular => {
+                usize::try_from(max_size).map_or(BUFFER_SIZE, |max_size| max_size.min(BUFFER_SIZE))
+            }
+            _ => BUFFER_SIZE,
+        };
+        let file = Counted {
+            inner: file,
+            count: Rc::clone(&written),
+        };
+        let writer: Box<dyn Sink> = match config.line_atomic {
+            true => Box::new(LineAtomic {
+                inner: file,
+                pending: Vec::new(),
+            }),
+            false => Box::new(io::BufWriter::with_capacity(buffer_size, file)),
+        };
+        Self {
+            name: path.display().to_string(),
+            writer: Some(stamped(writer, config.timestamp.as_deref())),
+            written,
+        }
+    }
+
+    fn socket(name: &str, stream: Box<dyn io::Write>) -> Self {
+        let written = Rc::default();
+        let stream = Counted {
+            inner: stream,
+            count: Rc::clone(&written),
+        };
+        Self {
+            name: name.to_string(),
+            writer: Some(Box::new(io::BufWriter::with_capacity(BUFFER_SIZE, stream))),
+            written,
+        }
+    }
+
+    fn stdout(config: &Config) -> Self {
+        let written = Rc::default();
+        let stdout = Counted {
+            inner: io::stdout(),
+            count: Rc::clone(&written),
+        };
+        let timestamp = config.timestamp.as_deref().filter(|_| config.timestamp_all);
+        Self {
+            name: STDOUT.to_string(),
+            writer: Some(stamped(Box::new(stdout), timestamp)),
+            written,
         }
     }
 }
 
-struct TeeWriters {
-    writers: Vec<Box<dyn io::Write>>,
+/// Puts a timestamp in front of every line going to `writer`, given a
+/// format.
+fn stamped(writer: Box<dyn Sink>, format: Option<&str>) -> Box<dyn Sink> {
+    match format {
+        Some(format) => Box::new(Stamped {
+            inner: writer,
+            format: format.to_string(),
+            line_start: true,
+        }),
+        None => writer,
+    }
 }
 
-impl io::Write for TeeWriters {
-    // io::Write has two methods: write and flush
+/// Prefixes each line with the time its first byte came in, formatted
+/// strftime style.
+struct Stamped {
+    inner: Box<dyn Sink>,
+    format: String,
+    line_start: bool,
+}
+
+impl io::Write for Stamped {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.writers.iter_mut().for_each(|w| {
-            w.write_all(buf).unwrap_or_else(|e| {
-                eprintln!("{e}");
-            })
-        });
+        let stamp = format!("{} ", Local::now().format(&self.format));
+        let mut out = Vec::with_capacity(buf.len() + stamp.len());
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            if self.line_start {
+                out.extend_from_slice(stamp.as_bytes());
+            }
+            out.extend_from_slice(line);
+            self.line_start = line.ends_with(b"\n");
+        }
+        self.inner.write_all(&out)?;
         Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.writers.iter_mut().for_each(|w| {
-            w.flush().unwrap_or_else(|e| {
-                eprintln!("{e}");
-            });
+        self.inner.flush()
+    }
+}
+
+/// A file output that is moved aside to NAME.1, NAME.2, ... for a fresh
+/// one whenever a write would take it past `max_size`, keeping the newest
+/// `keep` of the old ones. A write is never split between two files.
+struct Rotating {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+    append: bool,
+}
+
+impl Rotating {
+    fn aside(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.size = 0;
+        if self.keep == 0 {
+            self.file.set_len(0)?;
+            self.file.seek(io::SeekFrom::Start(0))?;
+            return Ok(());
+        }
+        let moved = (1..=self.keep).rev().try_for_each(|n| {
+            let from = match n {
+                1 => self.path.clone(),
+                _ => self.aside(n - 1),
+            };
+            match fs::rename(from, self.aside(n)) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound && n > 1 => Ok(()),
+                res => res,
+            }
         });
+        if let Err(e) = moved {
+            // Better a file that grows too big than a lost stream. The next
+            // try comes after another max_size bytes.
+            eprintln!(
+                "tee: cannot rotate {}: {}",
+                self.path.display(),
+                strerror(&e)
+            );
+            return Ok(());
+        }
+        self.file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(self.append)
+            .truncate(!self.append)
+            .open(&self.path)?;
         Ok(())
     }
 }
 
+impl io::Write for Rotating {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Counts the bytes written to `inner`. It sits below any buffering, so
+/// what was lost to a failed flush is not counted.
+struct Counted<W> {
+    inner: W,
+    count: Rc<Cell<u64>>,
+}
+
+impl<W: io::Write> io::Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Holds back a partial line until the rest of it comes, so that every
+/// write to `inner` is whole lines. Appending to a file, each of those
+/// writes lands in one piece however many others append to it too. Only
+/// up to `BUFFER_SIZE` is held back, so a longer line goes out in pieces
+/// that are not.
+struct LineAtomic<W> {
+    inner: W,
+    pending: Vec<u8>,
+}
+
+impl<W: io::Write> io::Write for LineAtomic<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match buf.iter().rposition(|&b| b == b'\n') {
+            None => self.pending.extend_from_slice(buf),
+            Some(end) => {
+                let lines = match self.pending.is_empty() {
+                    true => &buf[..=end],
+                    false => {
+                        self.pending.extend_from_slice(&buf[..=end]);
+                        &self.pending[..]
+                    }
+                };
+                self.inner.write_all(lines)?;
+                self.pending.clear();
+                self.pending.extend_from_slice(&buf[end + 1..]);
+            }
+        }
+        if self.pending.len() >= BUFFER_SIZE {
+            self.inner.write_all(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An output's writer, which gets finished rather than just flushed once
+/// the input has ended.
+trait Sink: io::Write {
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl<W: io::Write> Sink for io::BufWriter<W> {}
+
+impl<W: io::Write> Sink for Counted<W> {}
+
+impl Sink for Stamped {
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()
+    }
+}
+
+impl<W: io::Write> Sink for LineAtomic<W> {
+    /// Writes out the partial line at the end too, there being no more to it.
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            self.inner.write_all(&self.pending)?;
+            self.pending.clear();
+        }
+        self.inner.flush()
+    }
+}
+
+struct TeeWriters {
+    outputs: Vec<Output>,
+    output_error: OutputError,
+    /// Whether every chunk is flushed as soon as it is written, as with -u.
+    flush: bool,
+    /// Whether some write has failed, which decides the exit status.
+    failed: bool,
+    /// Set when copying has to stop: every output has failed, or one has
+    /// with --output-error=exit.
+    stopped: bool,
+}
+
+impl TeeWriters {
+    /// Runs `op` on every output still going, dropping those it fails on.
+    fn each(&mut self, op: impl Fn(&mut Box<dyn Sink>) -> io::Result<()>) -> io::Result<()> {
+        for output in self.outputs.iter_mut() {
+            let Some(w) = &mut output.writer else {
+                continue;
+            };
+            let Err(e) = op(w) else { continue };
+            // A failed output is not tried again, so each gets one message.
+            output.writer = None;
+            if self.output_error.counts(&e) {
+                eprintln!("tee: {}: {}", output.name, strerror(&e));
+                self.failed = true;
+                if self.output_error.exits() {
+                    self.stopped = true;
+                    break;
+                }
+            }
+        }
+        if self.outputs.iter().all(|o| o.writer.is_none()) {
+            self.stopped = true;
+        }
+        match self.stopped {
+            true => Err(io::Error::other("no outputs left")),
+            false => Ok(()),
+        }
+    }
+
+    /// Writes `buf` to every output still going. Fails only when copying
+    /// has to stop.
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        let flush = self.flush;
+        self.each(|w| {
+            w.write_all(buf)?;
+            match flush {
+                true => w.flush(),
+                false => Ok(()),
+            }
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.each(|w| w.finish())
+    }
+}
+
 fn main() {
+    // A reader that goes away must not kill us before the files have
+    // everything; a broken pipe is dealt with as a write error instead.
+    unsafe { libc::signal(libc::SIGPIPE, libc::SIG_IGN) };
+
     let cmd = Command::new("qtee")
         .arg(
             Arg::new("append")
@@ -53,49 +508,259 @@ fn main() {
                 .action(ArgAction::SetTrue)
                 .help("Append the output to the files rather than overwriting them."),
         )
+        .arg(
+            Arg::new("flush")
+                .short('u')
+                .long("flush")
+                .action(ArgAction::SetTrue)
+                .help("Flush every output after each chunk of input, so files can be followed live."),
+        )
+        .arg(
+            Arg::new("line_atomic")
+                .long("line-atomic")
+                .action(ArgAction::SetTrue)
+                .help("Write only whole lines to files, each batch in a single write, so lines appended with -a by several tees never get mixed up."),
+        )
+        .arg(
+            Arg::new("mkdirs")
+                .long("mkdirs")
+                .action(ArgAction::SetTrue)
+                .help("Create missing parent directories of the files."),
+        )
+        .arg(
+            Arg::new("timestamp")
+                .long("timestamp")
+                .value_name("FORMAT")
+                .help("Put the time each line came in, in the strftime FORMAT, in front of it in the files."),
+        )
+        .arg(
+            Arg::new("timestamp_all")
+                .long("timestamp-all")
+                .action(ArgAction::SetTrue)
+                .requires("timestamp")
+                .help("Put the timestamps in standard output too."),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .action(ArgAction::SetTrue)
+                .help("Print how many bytes went to each output to stderr at the end."),
+        )
+        .arg(
+            Arg::new("input")
+                .short('f')
+                .long("input")
+                .value_name("FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("Read from FILE instead of standard input."),
+        )
+        .arg(
+            Arg::new("max_size")
+                .long("max-size")
+                .value_name("BYTES")
+                .help("Rotate file outputs before they grow past BYTES: FILE moves to FILE.1, FILE.1 to FILE.2 and so on, and a new FILE is started."),
+        )
+        .arg(
+            Arg::new("rotate")
+                .long("rotate")
+                .value_name("N")
+                .requires("max_size")
+                .value_parser(value_parser!(usize))
+                .help("Keep N rotated files (default 1)."),
+        )
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .value_name("ADDRESS")
+                .action(ArgAction::Append)
+                .help("Send another copy to a socket, unix:PATH or tcp:HOST:PORT. It is connected to before copying starts."),
+        )
+        .arg(
+            Arg::new("pipe_timeout")
+                .long("pipe-timeout")
+                .value_name("SECS")
+                .value_parser(value_parser!(f64))
+                .help("Give up on a FIFO output that has no reader after SECS seconds. Without this tee waits for one forever, but only once every other output is open."),
+        )
         .arg(
             Arg::new("ignore_sigint")
                 .short('i')
                 .action(ArgAction::SetTrue)
                 .help("Ignore the SIGINT signal"),
         )
-        .arg(Arg::new("paths").action(ArgAction::Append));
+        .arg(
+            Arg::new("nopipe")
+                .short('p')
+                .action(ArgAction::SetTrue)
+                .help("Diagnose errors writing to non pipes."),
+        )
+        .arg(
+            Arg::new("output_error")
+                .long("output-error")
+                .value_name("MODE")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("warn-nopipe")
+                .value_parser(["warn", "warn-nopipe", "exit", "exit-nopipe"])
+                .help("Set behavior on write error: warn about every failed output, warn-nopipe about those that are not pipes, or exit at the first such failure with exit or exit-nopipe."),
+        )
+        .arg(
+            Arg::new("paths")
+                .value_name("FILE")
+                .action(ArgAction::Append)
+                .help("Files to copy to. A '-' is one more copy to standard output."),
+        );
 
-    let args: Vec<String> = env::args().skip(1).collect();
-    let matches = cmd.get_matches_from(args);
-    let config = Config::from(&matches);
-    dbg!(&config);
+    let matches = cmd.get_matches();
+    let config = match Config::from(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("tee: {e}");
+            process::exit(1);
+        }
+    };
     let paths = matches
         .get_many::<String>("paths")
         .map(|v| v.map(Path::new).collect())
         .unwrap_or(vec![]);
 
-    tee(paths, &config);
+    if !tee(paths, &config) {
+        process::exit(1);
+    }
+}
+
+fn is_fifo(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo())
 }
 
-fn tee(paths: Vec<&Path>, config: &Config) {
-    let mut reader = io::stdin();
-    let mut writers: Vec<Box<dyn io::Write>> = paths
-        .into_iter()
-        .filter_map(|p| {
-            let mut file = fs::OpenOptions::new();
-            file.create(true);
-            if config.append {
-                file.append(true);
+/// Opens an output file, making its directory first with --mkdirs. With
+/// --pipe-timeout a FIFO is opened without blocking, which fails until
+/// there is a reader, and retried until then.
+fn open(path: &Path, config: &Config) -> io::Result<fs::File> {
+    if config.mkdirs {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    let mut options = fs::OpenOptions::new();
+    options
+        .write(true)
+        .create(true)
+        .append(config.append)
+        .truncate(!config.append);
+    let Some(timeout) = config.pipe_timeout.filter(|_| is_fifo(path)) else {
+        return options.open(path);
+    };
+    options.custom_flags(libc::O_NONBLOCK);
+    let deadline = Instant::now() + timeout;
+    loop {
+        match options.open(path) {
+            // Once open, writes wait for the reader like they would anywhere.
+            Ok(file) => {
+                unsafe {
+                    let flags = libc::fcntl(file.as_raw_fd(), libc::F_GETFL);
+                    libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+                }
+                return Ok(file);
             }
-            match file.open(p) {
-                Ok(handle) => Some(Box::new(handle) as Box<dyn io::Write>),
-                Err(e) => {
-                    eprintln!("{}: {e}", p.display());
-                    None
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "no reader for the FIFO",
+                    ));
                 }
+                thread::sleep(POLL_INTERVAL.min(deadline - now));
             }
-        })
-        .collect();
-    writers.push(Box::new(io::stdout()));
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Copies the input to stdout and every path, where "-" is stdout once more.
+/// Returns false if the input could not be read or some output could not
+/// be opened or written to.
+fn tee(paths: Vec<&Path>, config: &Config) -> bool {
+    // The input goes first: there is no point truncating the outputs
+    // without it.
+    let mut input: Box<dyn Read> = match &config.input {
+        Some(path) => match fs::File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("tee: {}: {}", path.display(), strerror(&e));
+                return false;
+            }
+        },
+        None => Box::new(io::stdin().lock()),
+    };
+    let mut ok = true;
+    // FIFOs go last, so that waiting for their readers holds up nothing
+    // else.
+    let mut order: Vec<usize> = (0..paths.len()).collect();
+    order.sort_by_key(|&i| is_fifo(paths[i]));
+    let mut opened: Vec<Option<Output>> = paths.iter().map(|_| None).collect();
+    for i in order {
+        let p = paths[i];
+        if p == Path::new("-") {
+            opened[i] = Some(Output::stdout(config));
+            continue;
+        }
+        match open(p, config) {
+            Ok(file) => opened[i] = Some(Output::file(p, file, config)),
+            Err(e) => {
+                eprintln!("tee: {}: {}", p.display(), strerror(&e));
+                ok = false;
+            }
+        }
+    }
+    let mut outputs: Vec<Output> = opened.into_iter().flatten().collect();
+    for (name, socket) in &config.sockets {
+        match socket.connect() {
+            Ok(stream) => outputs.push(Output::socket(name, stream)),
+            Err(e) => {
+                eprintln!("tee: {name}: {}", strerror(&e));
+                ok = false;
+            }
+        }
+    }
+    outputs.push(Output::stdout(config));
 
-    let mut tee_writers = TeeWriters { writers };
-    if let Err(e) = io::copy(&mut reader, &mut tee_writers) {
-        eprintln!("{e}");
+    let mut tee_writers = TeeWriters {
+        outputs,
+        output_error: config.output_error,
+        flush: config.flush,
+        failed: false,
+        stopped: false,
     };
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let n = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("tee: {}", strerror(&e));
+                ok = false;
+                break;
+            }
+        };
+        if tee_writers.write(&buffer[..n]).is_err() {
+            break;
+        }
+    }
+    // Anything still buffered has to make it out too.
+    if !tee_writers.stopped {
+        let _ = tee_writers.finish();
+    }
+    if config.verbose {
+        for output in &tee_writers.outputs {
+            eprintln!(
+                "tee: '{}': {} bytes written",
+                output.name,
+                output.written.get()
+            );
+        }
+    }
+    ok && !tee_writers.failed
 }
@@ -0,0 +1,603 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn qtee() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_qtee"))
+}
+
+/// Runs qtee with `input` on stdin. The input is fed from another thread
+/// so a large one cannot deadlock against the output, and qtee is free to
+/// stop reading early.
+fn run(cmd: &mut Command, input: &[u8]) -> Output {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let feeder = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    let _ = feeder.join().unwrap();
+    output
+}
+
+#[test]
+fn test_unopenable_output_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let output = run(qtee().arg("-a").arg(&out).arg(dir.path()), b"data\n");
+    assert_eq!(output.status.code(), Some(1));
+    // The outputs that could be opened still get everything.
+    assert_eq!(output.stdout, b"data\n");
+    assert_eq!(fs::read(&out).unwrap(), b"data\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr,
+        format!("tee: {}: Is a directory\n", dir.path().display())
+    );
+}
+
+#[test]
+fn test_write_error_fails() {
+    // Many chunks' worth, but the dead output is only reported once while
+    // the others get everything.
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let input = b"data\n".repeat(200_000);
+    let output = run(qtee().args(["-a", "/dev/full"]).arg(&out), &input);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout == input);
+    assert!(fs::read(&out).unwrap() == input);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "tee: /dev/full: No space left on device\n"
+    );
+
+    // Standard output counts as much as any file.
+    let full = fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/full")
+        .unwrap();
+    let mut child = qtee()
+        .stdin(Stdio::piped())
+        .stdout(full)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"data\n").unwrap();
+    assert_eq!(child.wait().unwrap().code(), Some(1));
+
+    let output = run(&mut qtee(), b"data\n");
+    assert!(output.status.success());
+}
+
+/// Runs qtee with `args`, its standard output a pipe nobody reads from.
+fn run_into_closed_pipe(args: &[&str], input: &[u8]) -> (Option<i32>, String) {
+    let mut child = qtee()
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    // With an exit mode qtee stops reading at the first failure.
+    let _ = child.stdin.take().unwrap().write_all(input);
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_output_error_modes() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let out = out.to_str().unwrap();
+    let input = b"line\n".repeat(100_000);
+
+    // A closed pipe.
+    for (args, code, warned) in [
+        (&[][..], Some(0), false),
+        (&["--output-error=warn"][..], Some(1), true),
+        (&["--output-error=warn-nopipe"][..], Some(0), false),
+        (&["--output-error"][..], Some(0), false),
+        (&["-p"][..], Some(0), false),
+        (&["--output-error=exit"][..], Some(1), true),
+        (&["--output-error=exit-nopipe"][..], Some(0), false),
+    ] {
+        fs::remove_file(out).ok();
+        let args = [args, &["-a", out]].concat();
+        let (status, stderr) = run_into_closed_pipe(&args, &input);
+        assert_eq!(status, code, "{args:?}");
+        assert_eq!(
+            stderr == "tee: standard output: Broken pipe\n",
+            warned,
+            "{args:?}: {stderr}"
+        );
+        // Only exit stops at a broken pipe.
+        if args[0] != "--output-error=exit" {
+            assert_eq!(fs::read(out).unwrap(), input, "{args:?}");
+        }
+    }
+
+    // An output that cannot be written to.
+    for (args, code, complete) in [
+        (&[][..], Some(1), true),
+        (&["--output-error=warn"][..], Some(1), true),
+        (&["-p"][..], Some(1), true),
+        (&["--output-error=exit"][..], Some(1), false),
+        (&["--output-error=exit-nopipe"][..], Some(1), false),
+    ] {
+        let args = [args, &["-a", "/dev/full"]].concat();
+        let output = run(qtee().args(&args), &input);
+        assert_eq!(output.status.code(), code, "{args:?}");
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            "tee: /dev/full: No space left on device\n",
+            "{args:?}"
+        );
+        assert_eq!(output.stdout == input, complete, "{args:?}");
+    }
+}
+
+#[test]
+fn test_files_complete_after_reader_exits() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log.txt");
+    let script = format!(
+        "{} {} | head -c 1 >/dev/null",
+        env!("CARGO_BIN_EXE_qtee"),
+        log.display()
+    );
+    let input: Vec<u8> = (0..1 << 20).map(|i| (i % 251) as u8).collect();
+    let output = run(Command::new("sh").args(["-c", &script]), &input);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    assert_eq!(fs::read(&log).unwrap(), input);
+}
+
+#[test]
+fn test_truncates_unless_appending() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out");
+    fs::write(&path, "old and longer\n").unwrap();
+
+    let output = run(qtee().arg(&path), b"new\n");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"new\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+
+    let output = run(qtee().arg("-a").arg(&path), b"more\n");
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "new\nmore\n");
+}
+
+#[test]
+fn test_dash_is_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = run(
+        qtee().current_dir(dir.path()).args(["out", "-"]),
+        b"hello\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hello\nhello\n");
+    assert_eq!(fs::read(dir.path().join("out")).unwrap(), b"hello\n");
+    assert!(!dir.path().join("-").exists());
+}
+
+/// Waits for `path` to hold `expected`, for a second at most.
+fn wait_for(path: &Path, expected: &str) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        if fs::read_to_string(path).unwrap_or_default() == expected {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn test_flush() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out");
+    let mut child = qtee()
+        .arg("-u")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"first\n").unwrap();
+    assert!(wait_for(&path, "first\n"));
+    stdin.write_all(b"second\n").unwrap();
+    assert!(wait_for(&path, "first\nsecond\n"));
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn test_large_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("out");
+    // Several buffers' worth, ending in a partial one.
+    let input: Vec<u8> = (0..(5 << 20) + 12345).map(|i| (i % 253) as u8).collect();
+    let output = run(qtee().arg(&path), &input);
+    assert!(output.status.success());
+    assert!(output.stdout == input);
+    assert!(fs::read(&path).unwrap() == input);
+}
+
+#[test]
+fn test_verbose() {
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+    let input = b"0123456789".repeat(30_000);
+    let output = run(qtee().arg("--verbose").arg(&a).arg(&b), &input);
+    assert!(output.status.success());
+    assert!(output.stdout == input);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "tee: '{}': 300000 bytes written\n\
+             tee: '{}': 300000 bytes written\n\
+             tee: 'standard output': 300000 bytes written\n",
+            a.display(),
+            b.display()
+        )
+    );
+
+    // An output that fails partway reports what it took before that.
+    let output = run(qtee().args(["--verbose", "-a", "/dev/full"]), &input);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("tee: '/dev/full': 0 bytes written\n"));
+}
+
+#[test]
+fn test_fifo_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let fifo = dir.path().join("fifo");
+    let out = dir.path().join("out");
+    assert!(Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap()
+        .success());
+
+    // Nobody reads: give up on the FIFO, but not on the file after it.
+    let start = Instant::now();
+    let output = run(
+        qtee().args(["--pipe-timeout", "0.2"]).arg(&fifo).arg(&out),
+        b"data\n",
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"data\n");
+    assert_eq!(fs::read(&out).unwrap(), b"data\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("tee: {}: no reader for the FIFO\n", fifo.display())
+    );
+
+    // A reader that turns up in time gets everything.
+    let reader = {
+        let fifo = fifo.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            fs::read(fifo).unwrap()
+        })
+    };
+    let output = run(
+        qtee().args(["--pipe-timeout", "5"]).arg(&fifo).arg(&out),
+        b"data\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(reader.join().unwrap(), b"data\n");
+}
+
+#[test]
+fn test_input_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = dir.path().join("data.txt");
+    let out = dir.path().join("out.txt");
+    fs::write(&data, "from a file\n").unwrap();
+    let output = run(qtee().arg("--input").arg(&data).arg(&out), b"stdin\n");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"from a file\n");
+    assert_eq!(fs::read(&out).unwrap(), b"from a file\n");
+
+    // Without it stdin is read.
+    let output = run(qtee().arg(&out), b"stdin\n");
+    assert_eq!(output.stdout, b"stdin\n");
+    assert_eq!(fs::read(&out).unwrap(), b"stdin\n");
+
+    // A missing input leaves the outputs alone.
+    let missing = dir.path().join("missing");
+    let output = run(qtee().arg("-f").arg(&missing).arg(&out), b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("tee: {}: No such file or directory\n", missing.display())
+    );
+    assert_eq!(fs::read(&out).unwrap(), b"stdin\n");
+}
+
+#[test]
+fn test_line_atomic_appends() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log");
+    let lines = |tag: char| -> Vec<u8> {
+        (0..100_000)
+            .flat_map(|i| format!("{tag}{i} {}\n", tag.to_string().repeat(i % 97)).into_bytes())
+            .collect()
+    };
+    let inputs = [lines('a'), lines('b')];
+    let start = Arc::new(Barrier::new(2));
+    let children: Vec<_> = inputs
+        .into_iter()
+        .map(|input| {
+            let mut child = qtee()
+                .args(["-a", "-u", "--line-atomic"])
+                .arg(&log)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap();
+            let mut stdin = child.stdin.take().unwrap();
+            let start = Arc::clone(&start);
+            // Small odd-sized writes, so reads end mid-line.
+            let feeder = thread::spawn(move || {
+                start.wait();
+                for chunk in input.chunks(4093) {
+                    stdin.write_all(chunk).unwrap();
+                }
+            });
+            (child, feeder)
+        })
+        .collect();
+    for (mut child, feeder) in children {
+        feeder.join().unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+
+    let log = fs::read_to_string(&log).unwrap();
+    let mut counts = [0; 2];
+    for line in log.lines() {
+        let (head, tail) = line.split_once(' ').unwrap();
+        let tag = head.chars().next().unwrap();
+        let i: usize = head[1..].parse().unwrap();
+        assert_eq!(tail, tag.to_string().repeat(i % 97), "{line}");
+        counts[(tag == 'b') as usize] += 1;
+    }
+    assert_eq!(counts, [100_000, 100_000]);
+}
+
+#[test]
+fn test_line_atomic_long_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let mut child = qtee()
+        .args(["--line-atomic"])
+        .arg(&out)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let input = vec![b'x'; 1 << 20];
+    stdin.write_all(&input).unwrap();
+    // A line that never ends is not held back for good, however long.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fs::metadata(&out).map_or(0, |m| m.len()) < 1 << 19 {
+        assert!(Instant::now() < deadline, "nothing written yet");
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+    assert!(fs::read(&out).unwrap() == input);
+}
+
+#[test]
+fn test_timestamp() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let input = b"one\ntwo\n\nlast";
+    // Lines that come in split across reads get one stamp.
+    let mut child = qtee()
+        .args(["--timestamp", "[%Y-%m-%dT%H:%M:%S%.3f]"])
+        .arg(&out)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for chunk in input.chunks(3) {
+        stdin.write_all(chunk).unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, input);
+
+    let stamped = fs::read_to_string(&out).unwrap();
+    assert!(!stamped.ends_with('\n'));
+    let lines: Vec<&str> = stamped
+        .lines()
+        .map(|line| {
+            let (stamp, rest) = line.split_once("] ").unwrap();
+            assert_eq!(stamp.len(), "[2024-01-01T00:00:00.000".len(), "{line}");
+            rest
+        })
+        .collect();
+    assert_eq!(lines, ["one", "two", "", "last"]);
+
+    let output = run(
+        qtee().args(["--timestamp", "%Y", "--timestamp-all"]),
+        b"a\nb\n",
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().filter(|l| l.len() == 6).count(), 2);
+
+    let output = run(qtee().args(["--timestamp", "%Q"]), b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "tee: invalid timestamp format '%Q'\n"
+    );
+}
+
+#[test]
+fn test_rotation_below_buffer_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let mut child = qtee()
+        .args(["--max-size", "10K", "--rotate", "20"])
+        .arg(&out)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    // Written a line at a time, so that it is read in pieces much smaller
+    // than the limit, and only the buffering could take a file past it.
+    let mut stdin = child.stdin.take().unwrap();
+    let mut input = Vec::new();
+    for i in 0..100 {
+        let line = format!("{i:0>999}\n");
+        stdin.write_all(line.as_bytes()).unwrap();
+        input.extend(line.into_bytes());
+        thread::sleep(Duration::from_millis(2));
+    }
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+
+    let mut kept = Vec::new();
+    for n in (0..=20).rev() {
+        let path = match n {
+            0 => out.clone(),
+            _ => dir.path().join(format!("out.{n}")),
+        };
+        let Ok(data) = fs::read(&path) else {
+            continue;
+        };
+        assert!(
+            !data.is_empty() && data.len() <= 10 << 10,
+            "{}",
+            path.display()
+        );
+        kept.extend(data);
+    }
+    assert!(kept == input);
+}
+
+#[test]
+fn test_rotation() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let input: Vec<u8> = (0..1 << 20).map(|i| (i % 249) as u8).collect();
+    let output = run(
+        qtee()
+            .args(["--max-size", "256K", "--rotate", "2"])
+            .arg(&out),
+        &input,
+    );
+    assert!(output.status.success());
+    assert!(output.stdout == input);
+
+    // The files kept hold the end of the stream, in order, with nothing
+    // lost or repeated between them.
+    let mut kept = Vec::new();
+    for name in ["out.2", "out.1", "out"] {
+        let data = fs::read(dir.path().join(name)).unwrap();
+        assert!(!data.is_empty() && data.len() <= 256 << 10, "{name}");
+        kept.extend(data);
+    }
+    assert!(input.ends_with(&kept));
+    assert!(!dir.path().join("out.3").exists());
+
+    // A file that cannot be moved aside keeps growing instead.
+    let log = dir.path().join("log");
+    fs::create_dir_all(dir.path().join("log.1/in-the-way")).unwrap();
+    let output = run(
+        qtee().args(["--max-size", "64K"]).arg(&log),
+        &input[..300_000],
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with(&format!("tee: cannot rotate {}: ", log.display())));
+    assert!(fs::read(&log).unwrap() == input[..300_000]);
+}
+
+#[test]
+fn test_mkdirs() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("2024/05/run.log");
+
+    let output = run(qtee().arg(&out), b"data\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("tee: {}: No such file or directory\n", out.display())
+    );
+    assert_eq!(output.stdout, b"data\n");
+
+    let output = run(qtee().arg("--mkdirs").arg(&out), b"data\n");
+    assert!(output.status.success());
+    assert_eq!(fs::read(&out).unwrap(), b"data\n");
+}
+
+#[test]
+fn test_sockets() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("collector.sock");
+    let unix = UnixListener::bind(&path).unwrap();
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = tcp.local_addr().unwrap().port();
+    let unix = thread::spawn(move || {
+        let mut received = Vec::new();
+        unix.accept().unwrap().0.read_to_end(&mut received).unwrap();
+        received
+    });
+    let tcp = thread::spawn(move || {
+        let mut received = Vec::new();
+        tcp.accept().unwrap().0.read_to_end(&mut received).unwrap();
+        received
+    });
+
+    let input = b"build log\n".repeat(50_000);
+    let output = run(
+        qtee()
+            .arg("--socket")
+            .arg(format!("unix:{}", path.display()))
+            .arg("--socket")
+            .arg(format!("tcp:127.0.0.1:{port}")),
+        &input,
+    );
+    assert!(output.status.success());
+    assert!(output.stdout == input);
+    assert!(unix.join().unwrap() == input);
+    assert!(tcp.join().unwrap() == input);
+
+    // Nobody listening is like a file that cannot be opened.
+    let missing = format!("unix:{}", dir.path().join("missing").display());
+    let output = run(qtee().arg("--socket").arg(&missing), b"data\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"data\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("tee: {missing}: No such file or directory\n")
+    );
+}
@@ -0,0 +1,291 @@
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local, Utc};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use qoreutils_common::strftime;
+
+const DEFAULT_FORMAT: &str = "%b %d %H:%M:%S";
+// Relative stamps are durations, so the date fields make no sense there.
+const DEFAULT_RELATIVE_FORMAT: &str = "%H:%M:%S";
+
+#[derive(Debug, PartialEq)]
+enum Mode {
+    Absolute,
+    Incremental,
+    SinceStart,
+}
+
+#[derive(Debug)]
+struct Config {
+    mode: Mode,
+    monotonic: bool,
+    format: String,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Self {
+        let mode = if options.get_flag("incremental") {
+            Mode::Incremental
+        } else if options.get_flag("since_start") {
+            Mode::SinceStart
+        } else {
+            Mode::Absolute
+        };
+        let format = match options.get_one::<String>("format") {
+            Some(f) => expand_subseconds(f),
+            None if mode == Mode::Absolute => DEFAULT_FORMAT.to_string(),
+            None => DEFAULT_RELATIVE_FORMAT.to_string(),
+        };
+        Self {
+            mode,
+            monotonic: options.get_flag("monotonic"),
+            format,
+        }
+    }
+}
+
+/// Rewrites the "%.S", "%.s" and "%.T" extensions, which add microseconds
+/// to the field, into the equivalent strftime sequences.
+fn expand_subseconds(format: &str) -> String {
+    format
+        .replace("%.S", "%S%.6f")
+        .replace("%.s", "%s%.6f")
+        .replace("%.T", "%T%.6f")
+}
+
+trait Clock {
+    /// Time since the Unix epoch.
+    fn realtime(&self) -> Duration;
+    /// Time since a fixed point; unlike realtime it never jumps.
+    fn monotonic(&self) -> Duration;
+}
+
+struct SystemClock {
+    base: Instant,
+}
+
+impl Clock for SystemClock {
+    fn realtime(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.base.elapsed()
+    }
+}
+
+struct Stamper<C: Clock> {
+    clock: C,
+    config: Config,
+    /// Wall-clock and monotonic readings taken at startup.
+    start: (Duration, Duration),
+    previous: Duration,
+}
+
+impl<C: Clock> Stamper<C> {
+    fn new(clock: C, config: Config) -> Self {
+        let start = (clock.realtime(), clock.monotonic());
+        let previous = if config.monotonic { start.1 } else { start.0 };
+        Self {
+            clock,
+            config,
+            start,
+            previous,
+        }
+    }
+
+    fn now(&self) -> Duration {
+        match self.config.monotonic {
+            true => self.clock.monotonic(),
+            false => self.clock.realtime(),
+        }
+    }
+
+    /// Returns the timestamp for a line read now.
+    fn stamp(&mut self) -> String {
+        let now = self.now();
+        match self.config.mode {
+            Mode::Absolute => {
+                // A monotonic absolute time is the start time moved forward
+                // by the monotonic time elapsed since.
+                let wall = match self.config.monotonic {
+                    true => self.start.0 + now.saturating_sub(self.start.1),
+                    false => now,
+                };
+                let time: DateTime<Local> = (UNIX_EPOCH + wall).into();
+                time.format(&self.config.format).to_string()
+            }
+            Mode::Incremental => {
+                let elapsed = now.saturating_sub(self.previous);
+                self.previous = now;
+                format_duration(elapsed, &self.config.format)
+            }
+            Mode::SinceStart => {
+                let origin = if self.config.monotonic {
+                    self.start.1
+                } else {
+                    self.start.0
+                };
+                format_duration(now.saturating_sub(origin), &self.config.format)
+            }
+        }
+    }
+}
+
+/// Formats a duration as if it were a UTC time that many seconds after the
+/// epoch, so "%H:%M:%S" reads as hours, minutes and seconds.
+fn format_duration(elapsed: Duration, format: &str) -> String {
+    let time: DateTime<Utc> = (UNIX_EPOCH + elapsed).into();
+    time.format(format).to_string()
+}
+
+fn ts<C: Clock>(stamper: &mut Stamper<C>) -> io::Result<()> {
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        write!(output, "{} ", stamper.stamp())?;
+        output.write_all(&line)?;
+        // Flush every line, since ts is mostly used on live pipelines.
+        output.flush()?;
+    }
+}
+
+fn main() {
+    let matches = Command::new("ts")
+        .arg(
+            Arg::new("incremental")
+                .short('i')
+                .action(ArgAction::SetTrue)
+                .conflicts_with("since_start")
+                .help("Show the time elapsed since the previous line."),
+        )
+        .arg(
+            Arg::new("since_start")
+                .short('s')
+                .action(ArgAction::SetTrue)
+                .help("Show the time elapsed since the program started."),
+        )
+        .arg(
+            Arg::new("monotonic")
+                .short('m')
+                .action(ArgAction::SetTrue)
+                .help("Use the monotonic clock, which is not affected by clock changes."),
+        )
+        .arg(Arg::new("format"))
+        .get_matches();
+    let config = Config::from(&matches);
+    if !strftime::is_valid(&config.format) {
+        eprintln!("ts: invalid format '{}'", config.format);
+        process::exit(1);
+    }
+    let clock = SystemClock {
+        base: Instant::now(),
+    };
+    let mut stamper = Stamper::new(clock, config);
+    if let Err(e) = ts(&mut stamper) {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("ts: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A clock the test moves by hand. The wall clock can be made to jump
+    /// independently of the monotonic one.
+    #[derive(Clone, Default)]
+    struct FakeClock {
+        realtime: Rc<Cell<Duration>>,
+        monotonic: Rc<Cell<Duration>>,
+    }
+
+    impl FakeClock {
+        fn advance(&self, secs: u64) {
+            self.realtime
+                .set(self.realtime.get() + Duration::from_secs(secs));
+            self.monotonic
+                .set(self.monotonic.get() + Duration::from_secs(secs));
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn realtime(&self) -> Duration {
+            self.realtime.get()
+        }
+
+        fn monotonic(&self) -> Duration {
+            self.monotonic.get()
+        }
+    }
+
+    fn config(mode: Mode, monotonic: bool, format: &str) -> Config {
+        Config {
+            mode,
+            monotonic,
+            format: format.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_incremental() {
+        let clock = FakeClock::default();
+        let mut stamper = Stamper::new(clock.clone(), config(Mode::Incremental, false, "%H:%M:%S"));
+        clock.advance(5);
+        assert_eq!(stamper.stamp(), "00:00:05");
+        clock.advance(3725);
+        assert_eq!(stamper.stamp(), "01:02:05");
+        assert_eq!(stamper.stamp(), "00:00:00");
+    }
+
+    #[test]
+    fn test_since_start() {
+        let clock = FakeClock::default();
+        let mut stamper = Stamper::new(clock.clone(), config(Mode::SinceStart, false, "%H:%M:%S"));
+        clock.advance(5);
+        assert_eq!(stamper.stamp(), "00:00:05");
+        clock.advance(10);
+        assert_eq!(stamper.stamp(), "00:00:15");
+    }
+
+    #[test]
+    fn test_monotonic_ignores_clock_jumps() {
+        let clock = FakeClock::default();
+        let mut stamper = Stamper::new(clock.clone(), config(Mode::SinceStart, true, "%H:%M:%S"));
+        clock.advance(5);
+        clock.realtime.set(Duration::from_secs(86400 * 365));
+        assert_eq!(stamper.stamp(), "00:00:05");
+    }
+
+    #[test]
+    fn test_subseconds() {
+        let clock = FakeClock::default();
+        let format = expand_subseconds("%.S");
+        let mut stamper = Stamper::new(clock.clone(), config(Mode::Incremental, false, &format));
+        clock.realtime.set(Duration::from_millis(1500));
+        assert_eq!(stamper.stamp(), "01.500000");
+    }
+
+    #[test]
+    fn test_absolute() {
+        let clock = FakeClock::default();
+        clock.realtime.set(Duration::from_secs(1_000_000_000));
+        let mut stamper = Stamper::new(clock.clone(), config(Mode::Absolute, true, "%s"));
+        clock.advance(7);
+        clock.realtime.set(Duration::ZERO);
+        assert_eq!(stamper.stamp(), "1000000007");
+    }
+}
@@ -0,0 +1,364 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use md5::Md5;
+use qoreutils_common::error::strerror;
+use sha1::{Digest, Sha1};
+
+// 100ns intervals between the Gregorian reform (1582-10-15), where RFC 4122
+// time starts, and the Unix epoch.
+const GREGORIAN_OFFSET: u64 = 0x01B2_1DD2_1381_4000;
+
+const NAMESPACES: &[(&str, &str)] = &[
+    ("@dns", "6ba7b810-9dad-11d1-80b4-00c04fd430c8"),
+    ("@url", "6ba7b811-9dad-11d1-80b4-00c04fd430c8"),
+    ("@oid", "6ba7b812-9dad-11d1-80b4-00c04fd430c8"),
+    ("@x500", "6ba7b814-9dad-11d1-80b4-00c04fd430c8"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Uuid([u8; 16]);
+
+impl Uuid {
+    /// Reads the 8-4-4-4-12 form that `Display` writes, in either case.
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.as_bytes();
+        let well_formed = s.len() == 36
+            && s.iter().enumerate().all(|(i, &b)| match i {
+                8 | 13 | 18 | 23 => b == b'-',
+                _ => b.is_ascii_hexdigit(),
+            });
+        if !well_formed {
+            return None;
+        }
+        let digits: Vec<u8> = s
+            .iter()
+            .filter(|&&b| b != b'-')
+            .map(|&b| (b as char).to_digit(16).unwrap() as u8)
+            .collect();
+        let mut bytes = [0u8; 16];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            *byte = pair[0] << 4 | pair[1];
+        }
+        Some(Self(bytes))
+    }
+
+    /// Stamps the version nibble and the RFC 4122 variant bits.
+    fn with_version(mut self, version: u8) -> Self {
+        self.0[6] = (self.0[6] & 0x0f) | (version << 4);
+        self.0[8] = (self.0[8] & 0x3f) | 0x80;
+        self
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut buf = [0u8; N];
+    if let Err(e) = getrandom::getrandom(&mut buf) {
+        eprintln!("uuidgen: cannot read random data: {e}");
+        process::exit(1);
+    }
+    buf
+}
+
+fn random_uuid() -> Uuid {
+    Uuid(random_bytes()).with_version(4)
+}
+
+fn name_uuid<D: Digest>(namespace: &Uuid, name: &[u8], version: u8) -> Uuid {
+    let mut hasher = D::new();
+    hasher.update(namespace.0);
+    hasher.update(name);
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Uuid(bytes).with_version(version)
+}
+
+/// Generates version 1 UUIDs. The clock sequence is picked once at random;
+/// timestamps that would repeat within a batch are bumped forward instead.
+struct TimeGenerator {
+    node: [u8; 6],
+    clock_seq: u16,
+    last: u64,
+}
+
+impl TimeGenerator {
+    fn new(node: [u8; 6], clock_seq: u16) -> Self {
+        Self {
+            node,
+            clock_seq: clock_seq & 0x3fff,
+            last: 0,
+        }
+    }
+
+    fn generate(&mut self, now: u64) -> Uuid {
+        let ts = now.max(self.last + 1);
+        self.last = ts;
+
+        let mut b = [0u8; 16];
+        b[0..4].copy_from_slice(&(ts as u32).to_be_bytes());
+        b[4..6].copy_from_slice(&((ts >> 32) as u16).to_be_bytes());
+        b[6..8].copy_from_slice(&(((ts >> 48) as u16) & 0x0fff).to_be_bytes());
+        b[8..10].copy_from_slice(&self.clock_seq.to_be_bytes());
+        b[10..16].copy_from_slice(&self.node);
+        Uuid(b).with_version(1)
+    }
+}
+
+/// The current time in 100ns intervals since the Gregorian reform.
+fn gregorian_now() -> u64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    GREGORIAN_OFFSET + (since_epoch.as_nanos() / 100) as u64
+}
+
+/// Returns the first usable hardware address, or a random one with the
+/// multicast bit set as RFC 4122 asks for when there is none.
+fn node_id() -> [u8; 6] {
+    let hardware = fs::read_dir("/sys/class/net").ok().and_then(|dir| {
+        dir.filter_map(|e| e.ok())
+            .filter(|e| e.file_name() != "lo")
+            .filter_map(|e| fs::read_to_string(e.path().join("address")).ok())
+            .filter_map(|addr| parse_mac(addr.trim()))
+            .find(|mac| mac.iter().any(|&b| b != 0))
+    });
+    hardware.unwrap_or_else(|| {
+        let mut node: [u8; 6] = random_bytes();
+        node[0] |= 0x01;
+        node
+    })
+}
+
+fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut parts = s.split(':');
+    for b in mac.iter_mut() {
+        *b = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
+}
+
+#[derive(Debug)]
+enum Kind {
+    Random,
+    Time,
+    Md5 { namespace: Uuid, name: String },
+    Sha1 { namespace: Uuid, name: String },
+}
+
+#[derive(Debug)]
+struct Config {
+    kind: Kind,
+    count: u64,
+    upper: bool,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Result<Self, String> {
+        let md5 = options.get_flag("md5");
+        let sha1 = options.get_flag("sha1");
+        let kind = if md5 || sha1 {
+            let (Some(ns), Some(name)) = (
+                options.get_one::<String>("namespace"),
+                options.get_one::<String>("name"),
+            ) else {
+                return Err("--namespace and --name are required for name-based UUIDs".into());
+            };
+            let namespace = NAMESPACES
+                .iter()
+                .find(|(alias, _)| alias == ns)
+                .map(|(_, uuid)| *uuid)
+                .unwrap_or(ns);
+            let namespace = Uuid::parse(namespace).ok_or(format!("invalid namespace '{ns}'"))?;
+            let name = name.clone();
+            match md5 {
+                true => Kind::Md5 { namespace, name },
+                false => Kind::Sha1 { namespace, name },
+            }
+        } else if options.get_flag("time") {
+            Kind::Time
+        } else {
+            Kind::Random
+        };
+        Ok(Self {
+            kind,
+            count: *options.get_one::<u64>("count").unwrap(),
+            upper: options.get_flag("upper"),
+        })
+    }
+}
+
+fn main() {
+    let matches = Command::new("uuidgen")
+        .arg(
+            Arg::new("random")
+                .long("random")
+                .action(ArgAction::SetTrue)
+                .help("Generate a random-based UUID (the default)."),
+        )
+        .arg(
+            Arg::new("time")
+                .short('t')
+                .long("time")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("random")
+                .help("Generate a time-based UUID."),
+        )
+        .arg(
+            Arg::new("md5")
+                .long("md5")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["random", "time", "sha1"])
+                .help("Generate an md5 hash-based UUID."),
+        )
+        .arg(
+            Arg::new("sha1")
+                .long("sha1")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["random", "time"])
+                .help("Generate a sha1 hash-based UUID."),
+        )
+        .arg(
+            Arg::new("namespace")
+                .long("namespace")
+                .value_name("NS")
+                .help("Namespace UUID, or one of @dns, @url, @oid, @x500."),
+        )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .help("Name to hash for --md5 and --sha1."),
+        )
+        .arg(
+            Arg::new("count")
+                .short('r')
+                .long("count")
+                .value_name("NUM")
+                .default_value("1")
+                .value_parser(value_parser!(u64))
+                .help("Generate NUM UUIDs."),
+        )
+        .arg(
+            Arg::new("upper")
+                .short('x')
+                .long("upper")
+                .action(ArgAction::SetTrue)
+                .help("Print in uppercase."),
+        )
+        .get_matches();
+    let config = match Config::from(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("uuidgen: {e}");
+            process::exit(1);
+        }
+    };
+
+    let mut time_generator = match config.kind {
+        Kind::Time => Some(TimeGenerator::new(
+            node_id(),
+            u16::from_ne_bytes(random_bytes()),
+        )),
+        _ => None,
+    };
+    let mut out = io::stdout().lock();
+    for _ in 0..config.count {
+        let uuid = match &config.kind {
+            Kind::Random => random_uuid(),
+            Kind::Time => time_generator.as_mut().unwrap().generate(gregorian_now()),
+            Kind::Md5 { namespace, name } => name_uuid::<Md5>(namespace, name.as_bytes(), 3),
+            Kind::Sha1 { namespace, name } => name_uuid::<Sha1>(namespace, name.as_bytes(), 5),
+        };
+        let res = match config.upper {
+            true => writeln!(out, "{}", uuid.to_string().to_uppercase()),
+            false => writeln!(out, "{uuid}"),
+        };
+        match res {
+            Ok(()) => {}
+            // Whoever was reading has gone away, as in `uuidgen -r 100 | head -1`.
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return,
+            Err(e) => {
+                eprintln!("uuidgen: write error: {}", strerror(&e));
+                process::exit(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn dns() -> Uuid {
+        Uuid::parse(NAMESPACES[0].1).unwrap()
+    }
+
+    fn assert_rfc4122(uuid: &Uuid, version: u8) {
+        assert_eq!(uuid.0[6] >> 4, version);
+        assert_eq!(uuid.0[8] & 0xc0, 0x80);
+    }
+
+    #[test]
+    fn test_random() {
+        let uuids: HashSet<_> = (0..1000).map(|_| random_uuid().0).collect();
+        assert_eq!(uuids.len(), 1000);
+        uuids.iter().for_each(|b| assert_rfc4122(&Uuid(*b), 4));
+    }
+
+    #[test]
+    fn test_time() {
+        let mut generator = TimeGenerator::new([1, 2, 3, 4, 5, 6], 0xffff);
+        // The same clock reading twice must still give distinct UUIDs.
+        let a = generator.generate(GREGORIAN_OFFSET);
+        let b = generator.generate(GREGORIAN_OFFSET);
+        assert_ne!(a, b);
+        assert_rfc4122(&a, 1);
+        assert_eq!(a.to_string(), "13814000-1dd2-11b2-bfff-010203040506");
+    }
+
+    #[test]
+    fn test_name_based() {
+        let v3 = name_uuid::<Md5>(&dns(), b"python.org", 3);
+        assert_eq!(v3.to_string(), "6fa459ea-ee8a-3ca4-894e-db77e160355e");
+        let v5 = name_uuid::<Sha1>(&dns(), b"python.org", 5);
+        assert_eq!(v5.to_string(), "886313e1-3b8a-5372-9b90-0c9aee199e5d");
+        assert_rfc4122(&v5, 5);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Uuid::parse(NAMESPACES[0].1).unwrap().to_string(),
+            NAMESPACES[0].1
+        );
+        assert!(Uuid::parse("6ba7b810-9dad-11d1-80b4").is_none());
+        assert!(Uuid::parse("6ba7b810-9dad-11d1-80b4-00c04fd430cg").is_none());
+        assert_eq!(
+            Uuid::parse("6BA7B810-9DAD-11D1-80B4-00C04FD430C8"),
+            Some(dns())
+        );
+        // Hyphens go in their places, and nothing but hex digits between.
+        assert!(Uuid::parse("6ba7b8109-dad-11d1-80b4-00c04fd430c8").is_none());
+        assert!(Uuid::parse("6ba7b810-9dad-11d1-80b400c04fd430c8-").is_none());
+        assert!(Uuid::parse("+ba7b810-9dad-11d1-80b4-00c04fd430c8").is_none());
+        assert!(Uuid::parse("6ba7b810-9dad-11d1-80b4-00c04fd430\u{e9}").is_none());
+    }
+}
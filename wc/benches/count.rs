@@ -0,0 +1,41 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use qoreutils_wc::WordCounter;
+
+/// A few megabytes of log-like text, the kind wc spends most of its time on.
+fn sample() -> Vec<u8> {
+    let mut text = Vec::new();
+    for i in 0..40_000 {
+        let line = format!(
+            "2024-01-01T00:00:{:02} host{} GET /index.html 200 {}\n",
+            i % 60,
+            i % 7,
+            i
+        );
+        text.extend_from_slice(line.as_bytes());
+    }
+    text
+}
+
+fn bench_count(c: &mut Criterion) {
+    let text = sample();
+    let mut group = c.benchmark_group("count");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_with_input(BenchmarkId::new("blocks", text.len()), &text, |b, text| {
+        b.iter(|| {
+            let mut counter = WordCounter::new();
+            counter.update(text);
+            counter.finish()
+        })
+    });
+    group.bench_with_input(BenchmarkId::new("scalar", text.len()), &text, |b, text| {
+        b.iter(|| {
+            let mut counter = WordCounter::new();
+            counter.update_scalar(text);
+            counter.finish()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_count);
+criterion_main!(benches);
@@ -0,0 +1,348 @@
+//! Line, word and character counting, fed one chunk at a time.
+//!
+//! `qwc` reads files through this, and anything else that needs the same
+//! counts over a stream can feed it whatever chunks it happens to have.
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Counts {
+    pub lines: usize,
+    pub words: usize,
+    pub chars: usize,
+    pub bytes: usize,
+    pub max_line_length: usize,
+}
+
+impl Counts {
+    pub fn add(&mut self, other: &Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.chars += other.chars;
+        self.bytes += other.bytes;
+        // The total shows the longest line of any file, not a sum.
+        self.max_line_length = self.max_line_length.max(other.max_line_length);
+    }
+}
+
+enum Decoded {
+    Char(char),
+    /// One byte that is not part of any valid UTF-8 sequence.
+    Invalid,
+}
+
+/// Decodes UTF-8 a byte at a time, so that a character split between two
+/// chunks is still seen whole.
+#[derive(Default)]
+struct Utf8Decoder {
+    code: u32,
+    /// The smallest code point the current sequence may encode, to catch
+    /// overlong forms.
+    min: u32,
+    /// Continuation bytes still expected.
+    needed: u8,
+    /// Bytes of the current sequence seen so far.
+    seen: u8,
+}
+
+impl Utf8Decoder {
+    fn push(&mut self, byte: u8, mut emit: impl FnMut(Decoded)) {
+        if self.needed > 0 {
+            if byte & 0xc0 == 0x80 {
+                self.code = self.code << 6 | u32::from(byte & 0x3f);
+                self.needed -= 1;
+                self.seen += 1;
+                if self.needed == 0 {
+                    match char::from_u32(self.code).filter(|_| self.code >= self.min) {
+                        Some(c) => emit(Decoded::Char(c)),
+                        None => self.finish(&mut emit),
+                    }
+                    self.seen = 0;
+                }
+                return;
+            }
+            // The sequence broke off, but this byte may still start another.
+            self.finish(&mut emit);
+        }
+        let (needed, code, min) = match byte {
+            0x00..=0x7f => return emit(Decoded::Char(char::from(byte))),
+            0xc2..=0xdf => (1, byte & 0x1f, 0x80),
+            0xe0..=0xef => (2, byte & 0x0f, 0x800),
+            0xf0..=0xf4 => (3, byte & 0x07, 0x10000),
+            _ => return emit(Decoded::Invalid),
+        };
+        self.code = u32::from(code);
+        self.min = min;
+        self.needed = needed;
+        self.seen = 1;
+    }
+
+    fn is_idle(&self) -> bool {
+        self.seen == 0
+    }
+
+    /// Gives up on an unfinished sequence, reporting each of its bytes.
+    fn finish(&mut self, mut emit: impl FnMut(Decoded)) {
+        for _ in 0..self.seen {
+            emit(Decoded::Invalid);
+        }
+        self.needed = 0;
+        self.seen = 0;
+    }
+}
+
+/// What is known about the input so far, one character at a time.
+#[derive(Default)]
+struct Scan {
+    counts: Counts,
+    in_word: bool,
+    line_length: usize,
+    /// Whether "\r\n" ends a line, with the "\r" taking up no width.
+    crlf: bool,
+    /// A "\r" whose width depends on whether "\n" comes next.
+    pending_cr: bool,
+}
+
+impl Scan {
+    // An invalid byte counts as one character, like GNU wc does.
+    fn char(&mut self, decoded: Decoded) {
+        self.counts.chars += 1;
+        if self.pending_cr && !matches!(decoded, Decoded::Char('\n')) {
+            self.line_length += 1;
+        }
+        self.pending_cr = false;
+        match decoded {
+            Decoded::Char('\n') => {
+                self.counts.lines += 1;
+                self.counts.max_line_length = self.counts.max_line_length.max(self.line_length);
+                self.line_length = 0;
+            }
+            Decoded::Char('\t') => self.line_length += 8 - self.line_length % 8,
+            Decoded::Char('\r') if self.crlf => self.pending_cr = true,
+            _ => self.line_length += 1,
+        }
+        // Bytes that are not valid UTF-8 can only be part of a word.
+        if matches!(decoded, Decoded::Char(c) if c.is_whitespace()) {
+            self.in_word = false;
+        } else if !self.in_word {
+            self.in_word = true;
+            self.counts.words += 1;
+        }
+    }
+}
+
+const SPACES: u64 = 0x2020_2020_2020_2020;
+const LOW7: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+const HIGH: u64 = 0x8080_8080_8080_8080;
+
+/// Whether all eight bytes are ASCII from ' ' to DEL, which take up one
+/// column each and of which only ' ' separates words.
+fn is_printable_ascii(block: u64) -> bool {
+    // Adding 0x60 sets the high bit of exactly the bytes from 0x20 up, and
+    // cannot carry between bytes that are already known to be ASCII.
+    block & HIGH == 0 && block.wrapping_add(0x6060_6060_6060_6060) & HIGH == HIGH
+}
+
+impl Scan {
+    /// Takes eight printable ASCII characters at once, finding the word
+    /// starts among them from a mask of the bytes that are not spaces.
+    fn printable_block(&mut self, block: u64) {
+        let t = block ^ SPACES;
+        // The high bit of every byte of `t` that is not zero.
+        let word = (((t & LOW7) + LOW7) | t) & HIGH;
+        // A word starts wherever the byte before, or before the block, was
+        // not in one.
+        let before = word << 8 | if self.in_word { 0x80 } else { 0 };
+        self.counts.words += (word & !before).count_ones() as usize;
+        self.in_word = word >> 63 == 1;
+        self.counts.chars += 8;
+        self.line_length += 8;
+    }
+}
+
+/// Counts input handed over in chunks of any size. Words, lines and
+/// characters split between two chunks are counted once.
+#[derive(Default)]
+pub struct WordCounter {
+    scan: Scan,
+    decoder: Utf8Decoder,
+}
+
+impl WordCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A counter that treats "\r\n" as a single line terminator, so that
+    /// the "\r" does not count towards the line length.
+    pub fn with_crlf(crlf: bool) -> Self {
+        let mut counter = Self::default();
+        counter.scan.crlf = crlf;
+        counter
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.scan.counts.bytes += buf.len();
+        // Most text is runs of plain ASCII, which go eight bytes at a time.
+        // Anything else, or anything following an unfinished character,
+        // goes through the decoder.
+        let mut blocks = buf.chunks_exact(8);
+        for block in &mut blocks {
+            let bits = u64::from_le_bytes(block.try_into().unwrap());
+            if self.decoder.is_idle() && !self.scan.pending_cr && is_printable_ascii(bits) {
+                self.scan.printable_block(bits);
+            } else {
+                self.push_bytes(block);
+            }
+        }
+        self.push_bytes(blocks.remainder());
+    }
+
+    /// The same as `update`, a byte at a time. Only there to check and
+    /// measure `update` against.
+    #[doc(hidden)]
+    pub fn update_scalar(&mut self, buf: &[u8]) {
+        self.scan.counts.bytes += buf.len();
+        self.push_bytes(buf);
+    }
+
+    fn push_bytes(&mut self, buf: &[u8]) {
+        for &byte in buf {
+            self.decoder.push(byte, |decoded| self.scan.char(decoded));
+        }
+    }
+
+    pub fn finish(mut self) -> Counts {
+        // A sequence cut off by the end of input is still part of a word.
+        self.decoder.finish(|decoded| self.scan.char(decoded));
+        if self.scan.pending_cr {
+            self.scan.line_length += 1;
+        }
+        let mut counts = self.scan.counts;
+        // The last line counts even without a trailing newline.
+        counts.max_line_length = counts.max_line_length.max(self.scan.line_length);
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn count(input: &[u8]) -> Counts {
+        let mut counter = WordCounter::new();
+        counter.update(input);
+        counter.finish()
+    }
+
+    #[test]
+    fn test_chunks() {
+        let mut counter = WordCounter::new();
+        for chunk in ["hel", "lo w", "\u{3000}", "orld", "\n", "你", "好"] {
+            counter.update(chunk.as_bytes());
+        }
+        let counts = counter.finish();
+        assert_eq!(counts, count("hello w\u{3000}orld\n你好".as_bytes()));
+        assert_eq!(counts.words, 4);
+        assert_eq!(counts.chars, 15);
+    }
+
+    #[test]
+    fn test_total_takes_max_line_length() {
+        let mut total = Counts::default();
+        total.add(&count(b"abcd\n"));
+        total.add(&count(b"ab\nabcdef\n"));
+        assert_eq!(total.lines, 3);
+        assert_eq!(total.max_line_length, 6);
+    }
+
+    #[test]
+    fn test_printable_blocks() {
+        assert!(is_printable_ascii(u64::from_le_bytes(*b" a~ \x7fz0!")));
+        assert!(!is_printable_ascii(u64::from_le_bytes(*b"abc\tdefg")));
+        assert!(!is_printable_ascii(u64::from_le_bytes(*b"abcdefg\x1f")));
+        assert!(!is_printable_ascii(u64::from_le_bytes(*b"abc\xc3\xa9efg")));
+        let counts = count(b"  ab cd   efgh  i");
+        assert_eq!(counts.words, 4);
+        assert_eq!(counts.max_line_length, 17);
+        // A word running across blocks is counted once.
+        assert_eq!(count(b"abcdefghijklmnopqrstuvwxyz").words, 1);
+    }
+
+    #[test]
+    fn test_crlf() {
+        let input = b"abcdef\r\nabc\nab\r\n\ra\r";
+        let mut counter = WordCounter::with_crlf(true);
+        counter.update(input);
+        let counts = counter.finish();
+        assert_eq!(counts.lines, 3);
+        assert_eq!(counts.words, 4);
+        assert_eq!(counts.max_line_length, 6);
+        // A "\r" anywhere but before "\n" still takes up a column.
+        let mut counter = WordCounter::with_crlf(true);
+        counter.update(b"\r\ra\rb\r");
+        assert_eq!(counter.finish().max_line_length, 6);
+
+        // Split between chunks the pair still ends the line.
+        let mut counter = WordCounter::with_crlf(true);
+        counter.update(b"abcdef\r");
+        counter.update(b"\nab\n");
+        assert_eq!(counter.finish().max_line_length, 6);
+
+        // Without the mode every "\r" is one column wide.
+        let counts = count(input);
+        assert_eq!(counts.lines, 3);
+        assert_eq!(counts.words, 4);
+        assert_eq!(counts.max_line_length, 7);
+    }
+
+    proptest! {
+        #[test]
+        fn test_split_anywhere(
+            input in proptest::collection::vec(
+                prop_oneof![
+                    Just(b' '), Just(b'\n'), Just(b'\t'), Just(b'a'),
+                    Just(0xc2), Just(0xa0), Just(0xe3), Just(0x80), Just(0xff),
+                    any::<u8>(),
+                ],
+                0..200,
+            ),
+            cuts in proptest::collection::vec(any::<prop::sample::Index>(), 0..8),
+        ) {
+            let mut cuts: Vec<usize> = cuts.iter().map(|i| i.index(input.len() + 1)).collect();
+            cuts.sort();
+            let mut counter = WordCounter::new();
+            let mut start = 0;
+            for cut in cuts {
+                counter.update(&input[start..cut]);
+                start = cut;
+            }
+            counter.update(&input[start..]);
+            prop_assert_eq!(counter.finish(), count(&input));
+        }
+
+        #[test]
+        fn test_blocks_match_scalar(
+            input in proptest::collection::vec(
+                prop_oneof![
+                    8 => b' '..=b'~',
+                    4 => Just(b' '),
+                    1 => Just(b'\t'),
+                    1 => Just(b'\n'),
+                    1 => Just(b'\r'),
+                    1 => any::<u8>(),
+                ],
+                0..300,
+            ),
+            crlf in any::<bool>(),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let cut = cut.index(input.len() + 1);
+            let mut fast = WordCounter::with_crlf(crlf);
+            fast.update(&input[..cut]);
+            fast.update(&input[cut..]);
+            let mut scalar = WordCounter::with_crlf(crlf);
+            scalar.update_scalar(&input);
+            prop_assert_eq!(fast.finish(), scalar.finish());
+        }
+    }
+}
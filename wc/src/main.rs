@@ -0,0 +1,629 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use qoreutils_common::error::strerror;
+use qoreutils_common::files0::Files0;
+use qoreutils_common::glob::glob_match;
+use qoreutils_wc::{Counts, WordCounter};
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Default)]
+struct Config {
+    lines: bool,
+    words: bool,
+    chars: bool,
+    bytes: bool,
+    max_line_length: bool,
+    json: bool,
+    crlf: bool,
+    recursive: bool,
+    include: Vec<String>,
+}
+
+impl Config {
+    pub fn from(options: &ArgMatches) -> Self {
+        let mut config = Self {
+            lines: options.get_flag("lines"),
+            words: options.get_flag("words"),
+            chars: options.get_flag("chars"),
+            bytes: options.get_flag("bytes"),
+            max_line_length: options.get_flag("max_line_length"),
+            json: options.get_flag("json"),
+            crlf: options.get_flag("crlf"),
+            recursive: options.get_flag("recursive"),
+            include: options
+                .get_many::<String>("include")
+                .map(|v| v.cloned().collect())
+                .unwrap_or_default(),
+        };
+        if !(config.lines || config.words || config.chars || config.bytes || config.max_line_length)
+        {
+            config.lines = true;
+            config.words = true;
+            config.bytes = true;
+        }
+        config
+    }
+
+    /// Whether the byte count is all that is asked for.
+    fn bytes_only(&self) -> bool {
+        self.bytes && !(self.lines || self.words || self.chars || self.max_line_length)
+    }
+
+    /// Whether newlines are all that need counting.
+    fn lines_only(&self) -> bool {
+        self.lines && !(self.words || self.chars || self.bytes || self.max_line_length)
+    }
+}
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+fn count_reader<R: Read>(mut reader: R, mut counter: WordCounter) -> io::Result<Counts> {
+    let mut buf = [0; BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => counter.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(counter.finish())
+}
+
+/// Counts nothing but newlines, which lets the scan skip everything else.
+fn count_lines_only<R: Read>(mut reader: R) -> io::Result<Counts> {
+    let mut counts = Counts::default();
+    let mut buf = [0; BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => counts.lines += memchr::memchr_iter(b'\n', &buf[..n]).count(),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(counts)
+}
+
+fn count<R: Read>(reader: R, config: &Config) -> io::Result<Counts> {
+    match config.lines_only() {
+        true => count_lines_only(reader),
+        false => count_reader(reader, WordCounter::with_crlf(config.crlf)),
+    }
+}
+
+fn count_file(path: &Path, config: &Config) -> io::Result<Counts> {
+    let meta = fs::metadata(path)?;
+    if meta.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::IsADirectory,
+            "Is a directory",
+        ));
+    }
+    let file = fs::File::open(path)?;
+    if config.bytes_only() {
+        // The size of a regular file is known without reading it. Files
+        // like those in /proc report a size of 0 and still have to be read.
+        if meta.is_file() && meta.len() > 0 {
+            return Ok(Counts {
+                bytes: meta.len() as usize,
+                ..Default::default()
+            });
+        }
+    }
+    count(file, config)
+}
+
+/// Adds every regular file under `dir` to `paths`, in sorted order, leaving
+/// out symlinks and files that match none of the --include patterns.
+/// Returns false if some directory could not be read.
+fn walk(dir: &Path, config: &Config, paths: &mut Vec<PathBuf>) -> bool {
+    let entries = match fs::read_dir(dir).and_then(|d| d.collect::<io::Result<Vec<_>>>()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("wc: {}: {}", dir.display(), strerror(&e));
+            return false;
+        }
+    };
+    let mut entries: Vec<(OsString, PathBuf)> =
+        entries.iter().map(|e| (e.file_name(), e.path())).collect();
+    entries.sort();
+    let mut ok = true;
+    for (name, path) in entries {
+        let file_type = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta.file_type(),
+            Err(e) => {
+                eprintln!("wc: {}: {}", path.display(), strerror(&e));
+                ok = false;
+                continue;
+            }
+        };
+        if file_type.is_dir() {
+            ok &= walk(&path, config, paths);
+        } else if file_type.is_file()
+            && (config.include.is_empty()
+                || config
+                    .include
+                    .iter()
+                    .any(|glob| glob_match(glob.as_bytes(), name.as_bytes())))
+        {
+            paths.push(path);
+        }
+    }
+    ok
+}
+
+fn count_stdin(config: &Config) -> io::Result<Counts> {
+    count(io::stdin().lock(), config)
+}
+
+// With --files0-from the names are streamed, so nothing can be measured up
+// front. This is the width GNU wc uses when it cannot tell.
+const STREAM_WIDTH: usize = 7;
+
+/// The counts `config` asks for, in output order, with their JSON names.
+fn fields(counts: &Counts, config: &Config) -> Vec<(&'static str, usize)> {
+    [
+        (config.lines, "lines", counts.lines),
+        (config.words, "words", counts.words),
+        (config.chars, "chars", counts.chars),
+        (config.bytes, "bytes", counts.bytes),
+        (
+            config.max_line_length,
+            "max_line_length",
+            counts.max_line_length,
+        ),
+    ]
+    .into_iter()
+    .filter(|(wanted, _, _)| *wanted)
+    .map(|(_, key, n)| (key, n))
+    .collect()
+}
+
+/// The width of the widest number that will be printed. Every total is at
+/// least as large as the matching per-file count, so the totals decide.
+fn number_width(total: &Counts, config: &Config) -> usize {
+    let widest = fields(total, config).into_iter().map(|(_, n)| n).max();
+    widest.unwrap_or(0).to_string().len()
+}
+
+/// Formats one output row. Input read from stdin without any operands has
+/// no name.
+fn format_counts(counts: &Counts, name: Option<&str>, config: &Config, width: usize) -> String {
+    let fields: Vec<String> = fields(counts, config)
+        .iter()
+        .map(|(_, n)| format!("{n:width$}"))
+        .collect();
+    match name {
+        Some(name) => format!("{} {name}", fields.join(" ")),
+        None => fields.join(" "),
+    }
+}
+
+/// The JSON object for one input: its name, if it has one, and only the
+/// requested counts.
+fn json_counts(counts: &Counts, name: Option<&str>, config: &Config) -> Value {
+    let mut object = Map::new();
+    if let Some(name) = name {
+        object.insert("name".to_string(), name.into());
+    }
+    for (key, n) in fields(counts, config) {
+        object.insert(key.to_string(), n.into());
+    }
+    Value::Object(object)
+}
+
+fn print_counts(
+    out: &mut impl Write,
+    counts: &Counts,
+    name: Option<&str>,
+    config: &Config,
+    width: usize,
+) -> io::Result<()> {
+    match config.json {
+        true => writeln!(out, "{}", json_counts(counts, name, config)),
+        false => writeln!(out, "{}", format_counts(counts, name, config, width)),
+    }
+}
+
+fn print_total(
+    out: &mut impl Write,
+    total: &Counts,
+    config: &Config,
+    width: usize,
+) -> io::Result<()> {
+    match config.json {
+        true => {
+            let mut object = json_counts(total, None, config);
+            object["total"] = true.into();
+            writeln!(out, "{object}")
+        }
+        false => print_counts(out, total, Some("total"), config, width),
+    }
+}
+
+fn print_error(out: &mut impl Write, name: &str, e: &io::Error, config: &Config) -> io::Result<()> {
+    eprintln!("wc: {name}: {}", strerror(e));
+    match config.json {
+        true => writeln!(out, "{}", json!({ "name": name, "error": strerror(e) })),
+        false => Ok(()),
+    }
+}
+
+fn main() {
+    let matches = Command::new("wc")
+        .arg(
+            Arg::new("lines")
+                .short('l')
+                .long("lines")
+                .action(ArgAction::SetTrue)
+                .help("Print the newline counts."),
+        )
+        .arg(
+            Arg::new("words")
+                .short('w')
+                .long("words")
+                .action(ArgAction::SetTrue)
+                .help("Print the word counts."),
+        )
+        .arg(
+            Arg::new("chars")
+                .short('m')
+                .long("chars")
+                .action(ArgAction::SetTrue)
+                .help("Print the character counts."),
+        )
+        .arg(
+            Arg::new("bytes")
+                .short('c')
+                .long("bytes")
+                .action(ArgAction::SetTrue)
+                .help("Print the byte counts."),
+        )
+        .arg(
+            Arg::new("max_line_length")
+                .short('L')
+                .long("max-line-length")
+                .action(ArgAction::SetTrue)
+                .help("Print the maximum display width."),
+        )
+        .arg(
+            Arg::new("crlf")
+                .long("crlf")
+                .action(ArgAction::SetTrue)
+                .help("Treat \\r\\n as a single line terminator that adds nothing to the line length."),
+        )
+        .arg(
+            Arg::new("recursive")
+                .short('r')
+                .long("recursive")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("files0_from")
+                .help("Count every regular file under directory operands, skipping symlinks."),
+        )
+        .arg(
+            Arg::new("include")
+                .long("include")
+                .value_name("GLOB")
+                .action(ArgAction::Append)
+                .requires("recursive")
+                .help("With -r, count only files whose name matches GLOB."),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print one JSON object per line for each input, holding its name and only the requested counts, followed by a total object when there are several inputs. Unreadable inputs give an object with an error field instead."),
+        )
+        .arg(
+            Arg::new("files0_from")
+                .long("files0-from")
+                .value_name("F")
+                .conflicts_with("paths")
+                .value_parser(value_parser!(PathBuf))
+                .help("Read input from the files specified by NUL-terminated names in file F; if F is - then read names from standard input."),
+        )
+        .arg(
+            Arg::new("paths")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .get_matches();
+    let config = Config::from(&matches);
+    let mut out = io::stdout().lock();
+    match wc(&config, &matches, &mut out) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        // Whoever was reading has gone away, as in `wc -l * | head -1`.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            eprintln!("wc: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Counts every input and prints the rows. Returns false if some input
+/// could not be counted.
+fn wc(config: &Config, matches: &ArgMatches, out: &mut impl Write) -> io::Result<bool> {
+    let files0_from = matches.get_one::<PathBuf>("files0_from");
+    let mut ok = true;
+
+    let paths: Box<dyn Iterator<Item = io::Result<PathBuf>>> = match files0_from {
+        Some(list) if list.as_os_str() == "-" => Box::new(Files0::new(io::stdin().lock())),
+        Some(list) => match fs::File::open(list) {
+            Ok(file) => Box::new(Files0::new(io::BufReader::new(file))),
+            Err(e) => {
+                eprintln!(
+                    "wc: cannot open '{}' for reading: {}",
+                    list.display(),
+                    strerror(&e)
+                );
+                return Ok(false);
+            }
+        },
+        None => {
+            let mut paths: Vec<PathBuf> = matches
+                .get_many::<PathBuf>("paths")
+                .map(|v| v.cloned().collect())
+                .unwrap_or_default();
+            // Directories with nothing in them do not make for stdin.
+            let no_operands = paths.is_empty();
+            if config.recursive {
+                let operands = std::mem::take(&mut paths);
+                for path in operands {
+                    match path.is_dir() {
+                        true => ok &= walk(&path, config, &mut paths),
+                        false => paths.push(path),
+                    }
+                }
+            }
+            if no_operands {
+                let counts = count_stdin(config)?;
+                let width = number_width(&counts, config);
+                print_counts(out, &counts, None, config, width)?;
+                return Ok(true);
+            }
+            Box::new(paths.into_iter().map(Ok))
+        }
+    };
+
+    // JSON rows need no common width, so they can go out right away.
+    let streaming = files0_from.is_some() || config.json;
+    let mut results = Vec::new();
+    let mut total = Counts::default();
+    let mut count = 0;
+    for (i, path) in paths.enumerate() {
+        let path = path?;
+        count += 1;
+        if let Some(list) = files0_from {
+            if path.as_os_str().is_empty() {
+                eprintln!(
+                    "wc: {}:{}: invalid zero-length file name",
+                    list.display(),
+                    i + 1
+                );
+                ok = false;
+                continue;
+            }
+            if path.as_os_str() == "-" && list.as_os_str() == "-" {
+                eprintln!(
+                    "wc: when reading file names from standard input, no file name of '-' allowed"
+                );
+                ok = false;
+                continue;
+            }
+        }
+        // Once stdin is used up, a later "-" just counts nothing.
+        let counts = match path.as_os_str() == "-" {
+            true => count_stdin(config),
+            false => count_file(&path, config),
+        };
+        let counts = match counts {
+            Ok(counts) => counts,
+            Err(e) => {
+                print_error(out, &path.to_string_lossy(), &e, config)?;
+                ok = false;
+                // Like GNU wc, a directory still gets a row of zeros.
+                if config.json || e.kind() != io::ErrorKind::IsADirectory {
+                    continue;
+                }
+                Counts::default()
+            }
+        };
+        total.add(&counts);
+        let name = path.to_string_lossy().into_owned();
+        match streaming {
+            true => print_counts(out, &counts, Some(&name), config, STREAM_WIDTH)?,
+            false => results.push((counts, name)),
+        }
+    }
+    let width = match streaming {
+        true => STREAM_WIDTH,
+        false => number_width(&total, config),
+    };
+    for (counts, name) in &results {
+        print_counts(out, counts, Some(name), config, width)?;
+    }
+    if count > 1 {
+        print_total(out, &total, config, width)?;
+    }
+    Ok(ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_str(s: &str) -> Counts {
+        count_reader(s.as_bytes(), WordCounter::new()).unwrap()
+    }
+
+    #[test]
+    fn test_count() {
+        let counts = count_str("hello world\nfoo\n");
+        assert_eq!(counts.lines, 2);
+        assert_eq!(counts.words, 3);
+        assert_eq!(counts.bytes, 16);
+        assert_eq!(counts.chars, 16);
+    }
+
+    #[test]
+    fn test_count_multibyte() {
+        let counts = count_str("héllo wörld");
+        assert_eq!(counts.lines, 0);
+        assert_eq!(counts.words, 2);
+        assert_eq!(counts.bytes, 13);
+        assert_eq!(counts.chars, 11);
+    }
+
+    #[test]
+    fn test_unicode_whitespace() {
+        assert_eq!(count_str("foo\u{00A0}bar").words, 2);
+        assert_eq!(count_str("你好　世界").words, 2);
+        // Split across reads the separator is still recognised.
+        let counts = count_reader(TinyReader("你好　世界".as_bytes()), WordCounter::new()).unwrap();
+        assert_eq!(counts.words, 2);
+        // Invalid bytes are never separators.
+        assert_eq!(
+            count_reader(&b"a\xe3\x80 b\xff"[..], WordCounter::new())
+                .unwrap()
+                .words,
+            2
+        );
+        assert_eq!(
+            count_reader(&b" \xe3\x80"[..], WordCounter::new())
+                .unwrap()
+                .words,
+            1
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_chars() {
+        let chars = |input: &[u8]| count_reader(input, WordCounter::new()).unwrap().chars;
+        // Lone continuation bytes and bytes that never start a sequence.
+        assert_eq!(chars(b"a\x80\x80b"), 4);
+        assert_eq!(chars(b"\xff\xfe"), 2);
+        // Overlong encodings of '/' and of U+0000.
+        assert_eq!(chars(b"\xc0\xaf"), 2);
+        assert_eq!(chars(b"\xe0\x80\xaf"), 3);
+        // A sequence cut short by another character or by the end of input.
+        assert_eq!(chars(b"\xe4\xbda"), 3);
+        assert_eq!(chars(b"a\xe4\xbd"), 3);
+        // Surrogates are not scalar values.
+        assert_eq!(chars(b"\xed\xa0\x80"), 3);
+        // A character split across two reads is still one character.
+        let counts = count_reader(TinyReader("ab你好".as_bytes()), WordCounter::new()).unwrap();
+        assert_eq!(counts.chars, 4);
+        assert_eq!(counts.bytes, 8);
+    }
+
+    #[test]
+    fn test_max_line_length() {
+        assert_eq!(count_str("ab\nabcd\nabc\n").max_line_length, 4);
+        // The last line counts without a trailing newline.
+        assert_eq!(count_str("ab\nabcdef").max_line_length, 6);
+        // Characters, not bytes.
+        assert_eq!(count_str("héllo\n").max_line_length, 5);
+        // Tabs advance to the next multiple of 8.
+        assert_eq!(count_str("a\tb\n").max_line_length, 9);
+        assert_eq!(count_str("").max_line_length, 0);
+    }
+
+    /// Hands out at most three bytes per read.
+    struct TinyReader<'a>(&'a [u8]);
+
+    impl Read for TinyReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_tiny_reads() {
+        for input in ["hello world\nfoo\n", "héllo wörld\tx\n  a  bb ccc", ""] {
+            let counts = count_reader(TinyReader(input.as_bytes()), WordCounter::new()).unwrap();
+            assert_eq!(counts, count_str(input), "{input:?}");
+        }
+        let counts =
+            count_reader(TinyReader("ab cd ef\ngh".as_bytes()), WordCounter::new()).unwrap();
+        assert_eq!(counts.words, 4);
+        assert_eq!(counts.lines, 1);
+    }
+
+    #[test]
+    fn test_lines_only_matches_general_path() {
+        let mut input = Vec::new();
+        for i in 0..200_000 {
+            input.extend_from_slice(format!("line {i} with some words").as_bytes());
+            if i % 7 != 0 {
+                input.push(b'\n');
+            }
+        }
+        let general = count_reader(&input[..], WordCounter::new()).unwrap();
+        let fast = count_lines_only(&input[..]).unwrap();
+        assert!(input.len() > 4 * BUFFER_SIZE);
+        assert_eq!(fast.lines, general.lines);
+        let fast = count_lines_only(TinyReader(b"a\nb\n\nc")).unwrap();
+        assert_eq!(fast.lines, 3);
+    }
+
+    #[test]
+    fn test_bytes_only_uses_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "héllo\nworld\n").unwrap();
+        let config = Config {
+            bytes: true,
+            ..Default::default()
+        };
+        assert!(config.bytes_only());
+        let counts = count_file(&path, &config).unwrap();
+        assert_eq!(counts.bytes, 13);
+        assert_eq!(counts.lines, 0);
+        assert_eq!(counts.bytes, count_str("héllo\nworld\n").bytes);
+
+        let empty = dir.path().join("empty");
+        fs::write(&empty, "").unwrap();
+        assert_eq!(count_file(&empty, &config).unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn test_width_follows_totals() {
+        let config = Config {
+            lines: true,
+            words: true,
+            bytes: true,
+            ..Default::default()
+        };
+        let mut total = Counts::default();
+        let small = count_str("a\n");
+        let big = count_str(&"word ".repeat(30_000));
+        total.add(&small);
+        total.add(&big);
+        let width = number_width(&total, &config);
+        assert_eq!(width, 6);
+        assert_eq!(
+            format_counts(&small, Some("small"), &config, width),
+            "     1      1      2 small"
+        );
+        assert_eq!(
+            format_counts(&total, Some("total"), &config, width),
+            "     1  30001 150002 total"
+        );
+        // A lone count needs no padding at all.
+        assert_eq!(number_width(&small, &config), 1);
+        assert_eq!(format_counts(&small, None, &config, 1), "1 1 2");
+        assert_eq!(format_counts(&small, Some("-"), &config, 1), "1 1 2 -");
+    }
+}
@@ -0,0 +1,290 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn qwc() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_qwc"))
+}
+
+#[test]
+fn test_missing_file_does_not_stop_the_rest() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("real.txt"), "one two\nthree\n").unwrap();
+    let output = qwc()
+        .current_dir(dir.path())
+        .args(["missing.txt", "real.txt"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        " 2  3 14 real.txt\n 2  3 14 total\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "wc: missing.txt: No such file or directory\n"
+    );
+}
+
+#[test]
+fn test_all_good_files_succeed() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a"), "a\n").unwrap();
+    fs::write(dir.path().join("b"), "b b\n").unwrap();
+    let output = qwc()
+        .current_dir(dir.path())
+        .args(["-l", "a", "b"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 a\n1 b\n2 total\n"
+    );
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_byte_count_from_pipe_matches_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = "some bytes\nand more\n".repeat(1000);
+    fs::write(dir.path().join("file"), &data).unwrap();
+    let from_file = qwc()
+        .current_dir(dir.path())
+        .args(["-c", "file"])
+        .output()
+        .unwrap();
+    let mut child = qwc()
+        .arg("-c")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(data.as_bytes())
+        .unwrap();
+    let from_pipe = child.wait_with_output().unwrap();
+    let expected = data.len();
+    assert_eq!(
+        String::from_utf8(from_file.stdout).unwrap(),
+        format!("{expected} file\n")
+    );
+    assert_eq!(
+        String::from_utf8(from_pipe.stdout).unwrap(),
+        format!("{expected}\n")
+    );
+}
+
+#[test]
+fn test_implicit_stdin_has_no_name() {
+    let mut child = qwc()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"hi\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1 1 3\n");
+}
+
+#[test]
+fn test_dash_operands_read_stdin() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+    fs::write(dir.path().join("b.txt"), "b b\nb\n").unwrap();
+    let mut child = qwc()
+        .current_dir(dir.path())
+        .args(["a.txt", "-", "b.txt", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"one two three\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        " 1  1  2 a.txt\n 1  3 14 -\n 2  3  6 b.txt\n 0  0  0 -\n 4  7 22 total\n"
+    );
+}
+
+#[test]
+fn test_json() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a b.txt"), "one two\nthree\n").unwrap();
+    fs::write(dir.path().join("c.txt"), "x\n").unwrap();
+    let output = qwc()
+        .current_dir(dir.path())
+        .args(["--json", "-l", "-w", "a b.txt", "missing", "c.txt"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let rows: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            serde_json::json!({ "name": "a b.txt", "lines": 2, "words": 3 }),
+            serde_json::json!({
+                "name": "missing",
+                "error": "No such file or directory",
+            }),
+            serde_json::json!({ "name": "c.txt", "lines": 1, "words": 1 }),
+            serde_json::json!({ "total": true, "lines": 3, "words": 4 }),
+        ]
+    );
+}
+
+#[test]
+fn test_directory_operand() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("somedir")).unwrap();
+    fs::write(dir.path().join("file"), "a b\n").unwrap();
+    let output = qwc()
+        .current_dir(dir.path())
+        .args(["somedir", "file"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "wc: somedir: Is a directory\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "0 0 0 somedir\n1 2 4 file\n1 2 4 total\n"
+    );
+}
+
+#[test]
+fn test_reader_going_away() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a"), "a\n").unwrap();
+    // Far more output than a pipe holds, so wc is still writing when the
+    // reader is gone.
+    let mut child = qwc()
+        .current_dir(dir.path())
+        .arg("-l")
+        .args(std::iter::repeat_n("a", 20_000))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first = [0; 8];
+    child.stdout.take().unwrap().read_exact(&mut first).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(&first, b"    1 a\n");
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_recursive() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    fs::create_dir_all(src.join("sub/deeper")).unwrap();
+    fs::write(src.join("b.rs"), "b\nb\n").unwrap();
+    fs::write(src.join("a.rs"), "a\n").unwrap();
+    fs::write(src.join("notes.txt"), "n\n").unwrap();
+    fs::write(src.join("sub/deeper/c.rs"), "c\nc\nc\n").unwrap();
+    std::os::unix::fs::symlink("a.rs", src.join("link.rs")).unwrap();
+
+    let output = qwc()
+        .current_dir(dir.path())
+        .args(["-l", "-r", "--include", "*.rs", "src"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 src/a.rs\n2 src/b.rs\n3 src/sub/deeper/c.rs\n6 total\n"
+    );
+
+    let output = qwc()
+        .current_dir(dir.path())
+        .args(["-l", "-r", "src"])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "1 src/a.rs\n2 src/b.rs\n1 src/notes.txt\n3 src/sub/deeper/c.rs\n7 total\n"
+    );
+
+    // An unreadable file is reported without stopping the walk. Privileged
+    // users can read it anyway, so only check when it really is unreadable.
+    let locked = src.join("locked.rs");
+    fs::write(&locked, "x\n").unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::File::open(&locked).is_ok() {
+        return;
+    }
+    let output = qwc()
+        .current_dir(dir.path())
+        .args(["-l", "-r", "src"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("wc: src/locked.rs: "));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .ends_with("3 src/sub/deeper/c.rs\n7 total\n"));
+}
+
+#[test]
+fn test_recursive_empty_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("empty")).unwrap();
+    let mut child = qwc()
+        .current_dir(dir.path())
+        .args(["-r", "empty"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Reading stdin would wait for as long as the pipe is open.
+    let _stdin = child.stdin.take();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("wc -r read stdin for an empty directory");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+}
+
+#[test]
+fn test_missing_files0_list() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = qwc()
+        .current_dir(dir.path())
+        .arg("--files0-from=missing")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "wc: cannot open 'missing' for reading: No such file or directory\n"
+    );
+    assert!(output.stdout.is_empty());
+}